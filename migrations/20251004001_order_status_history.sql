@@ -0,0 +1,16 @@
+-- audit trail of every order status transition --
+CREATE TABLE IF NOT EXISTS order_status_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL,
+    from_status order_status NOT NULL,
+    to_status order_status NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT fk_status_history_order
+        FOREIGN KEY (order_id)
+        REFERENCES orders(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_order_status_history_order
+    ON order_status_history (order_id, changed_at);
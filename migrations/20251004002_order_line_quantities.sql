@@ -0,0 +1,14 @@
+-- order_items becomes a proper order line: how many of the item and at what price --
+ALTER TABLE order_items
+    ADD COLUMN IF NOT EXISTS quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity > 0),
+    ADD COLUMN IF NOT EXISTS unit_price DOUBLE PRECISION;
+
+-- existing lines were one-of-each, capture the current item price for them --
+UPDATE order_items oi
+SET unit_price = i.price
+FROM items i
+WHERE i.id = oi.item_id
+  AND oi.unit_price IS NULL;
+
+ALTER TABLE order_items
+    ALTER COLUMN unit_price SET NOT NULL;
@@ -0,0 +1,39 @@
+CREATE TYPE stock_movement_reason AS ENUM (
+    'Initial',
+    'Adjustment',
+    'OrderPlaced',
+    'OrderUpdated',
+    'OrderCancelled',
+    'OrderDeleted',
+    'OrderRestored'
+);
+
+-- every change to items.quantity gets a row here, positive adds stock and negative removes it --
+CREATE TABLE IF NOT EXISTS inventory_ledger (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- NULL once the item has been purged, its movements stay on record --
+    item_id UUID,
+    order_id UUID,
+    quantity_change INTEGER NOT NULL,
+    reason stock_movement_reason NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT fk_inventory_ledger_item
+        FOREIGN KEY (item_id)
+        REFERENCES items(id)
+        ON DELETE SET NULL,
+
+    CONSTRAINT fk_inventory_ledger_order
+        FOREIGN KEY (order_id)
+        REFERENCES orders(id)
+        ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_inventory_ledger_item
+    ON inventory_ledger (item_id, created_at);
+
+-- open the ledger with the stock that already exists --
+INSERT INTO inventory_ledger (item_id, quantity_change, reason)
+SELECT id, quantity, 'Initial'
+FROM items
+WHERE quantity > 0;
@@ -0,0 +1,22 @@
+-- order lines keep the item name they were bought under, so they no longer depend on the live items row --
+ALTER TABLE order_items
+    ADD COLUMN IF NOT EXISTS name VARCHAR(255);
+
+UPDATE order_items oi
+SET name = i.name
+FROM items i
+WHERE i.id = oi.item_id
+  AND oi.name IS NULL;
+
+ALTER TABLE order_items
+    ALTER COLUMN name SET NOT NULL;
+
+-- deleting an item that has been ordered must not silently drop order lines --
+ALTER TABLE order_items
+    DROP CONSTRAINT IF EXISTS order_items_item_id_fkey;
+
+ALTER TABLE order_items
+    ADD CONSTRAINT order_items_item_id_fkey
+        FOREIGN KEY (item_id)
+        REFERENCES items(id)
+        ON DELETE RESTRICT;
@@ -0,0 +1,31 @@
+-- money moves from DOUBLE PRECISION to integer minor units plus an ISO 4217 currency code --
+-- existing rows are assumed to be USD amounts with two decimal places, nothing in the old schema says otherwise --
+-- a store that priced in another currency has to update the currency columns after this runs, --
+-- and rescale the *_minor columns too when that currency does not have two decimal places --
+
+ALTER TABLE items
+    ADD COLUMN IF NOT EXISTS price_minor BIGINT,
+    ADD COLUMN IF NOT EXISTS currency CHAR(3) NOT NULL DEFAULT 'USD';
+UPDATE items SET price_minor = ROUND(price * 100)::BIGINT WHERE price_minor IS NULL;
+ALTER TABLE items
+    ALTER COLUMN price_minor SET NOT NULL,
+    ALTER COLUMN currency DROP DEFAULT,
+    DROP COLUMN price;
+
+ALTER TABLE orders
+    ADD COLUMN IF NOT EXISTS amount_minor BIGINT,
+    ADD COLUMN IF NOT EXISTS currency CHAR(3) NOT NULL DEFAULT 'USD';
+UPDATE orders SET amount_minor = ROUND(amount * 100)::BIGINT WHERE amount_minor IS NULL;
+ALTER TABLE orders
+    ALTER COLUMN amount_minor SET NOT NULL,
+    ALTER COLUMN currency DROP DEFAULT,
+    DROP COLUMN amount;
+
+ALTER TABLE order_items
+    ADD COLUMN IF NOT EXISTS unit_price_minor BIGINT,
+    ADD COLUMN IF NOT EXISTS currency CHAR(3) NOT NULL DEFAULT 'USD';
+UPDATE order_items SET unit_price_minor = ROUND(unit_price * 100)::BIGINT WHERE unit_price_minor IS NULL;
+ALTER TABLE order_items
+    ALTER COLUMN unit_price_minor SET NOT NULL,
+    ALTER COLUMN currency DROP DEFAULT,
+    DROP COLUMN unit_price;
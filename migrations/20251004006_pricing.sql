@@ -0,0 +1,65 @@
+-- tax rates and percentages are stored in basis points, 10000 = 100% --
+CREATE TABLE IF NOT EXISTS tax_categories (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(255) UNIQUE NOT NULL,
+    rate_bps INTEGER NOT NULL CHECK (rate_bps >= 0 AND rate_bps <= 10000),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+ALTER TABLE items
+    ADD COLUMN IF NOT EXISTS tax_category_id UUID;
+
+ALTER TABLE items
+    ADD CONSTRAINT fk_items_tax_category
+        FOREIGN KEY (tax_category_id)
+        REFERENCES tax_categories(id)
+        ON DELETE SET NULL;
+
+CREATE TYPE coupon_kind AS ENUM (
+    'Percentage',
+    'Fixed'
+);
+
+CREATE TABLE IF NOT EXISTS coupons (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    code VARCHAR(64) UNIQUE NOT NULL,
+    kind coupon_kind NOT NULL,
+    percent_bps INTEGER CHECK (percent_bps > 0 AND percent_bps <= 10000),
+    amount_minor BIGINT CHECK (amount_minor > 0),
+    currency CHAR(3),
+    expires_at TIMESTAMPTZ,
+    usage_limit INTEGER CHECK (usage_limit > 0),
+    times_used INTEGER NOT NULL DEFAULT 0,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT chk_coupon_value CHECK (
+        (kind = 'Percentage' AND percent_bps IS NOT NULL)
+        OR (kind = 'Fixed' AND amount_minor IS NOT NULL AND currency IS NOT NULL)
+    )
+);
+
+-- order lines keep the tax rate they were priced with --
+ALTER TABLE order_items
+    ADD COLUMN IF NOT EXISTS tax_rate_bps INTEGER NOT NULL DEFAULT 0;
+
+-- amount_minor stays the total, the rest of the breakdown sits next to it --
+ALTER TABLE orders
+    ADD COLUMN IF NOT EXISTS subtotal_minor BIGINT,
+    ADD COLUMN IF NOT EXISTS discount_minor BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS tax_minor BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS coupon_code VARCHAR(64);
+
+UPDATE orders SET subtotal_minor = amount_minor WHERE subtotal_minor IS NULL;
+
+ALTER TABLE orders
+    ALTER COLUMN subtotal_minor SET NOT NULL;
+
+ALTER TABLE orders
+    ADD CONSTRAINT fk_orders_coupon
+        FOREIGN KEY (coupon_code)
+        REFERENCES coupons(code)
+        ON UPDATE CASCADE
+        ON DELETE SET NULL;
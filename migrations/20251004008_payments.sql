@@ -0,0 +1,40 @@
+CREATE TYPE payment_kind AS ENUM (
+    'Capture',
+    'Refund'
+);
+
+-- Pending is written before a capture is sent to the provider and settled once it answers --
+CREATE TYPE payment_status AS ENUM (
+    'Pending',
+    'Succeeded',
+    'Declined'
+);
+
+-- every call to the payment provider gets a row here, declined attempts included --
+CREATE TABLE IF NOT EXISTS payments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL,
+    kind payment_kind NOT NULL,
+    status payment_status NOT NULL,
+    amount_minor BIGINT NOT NULL,
+    currency CHAR(3) NOT NULL,
+    provider VARCHAR(64) NOT NULL,
+    provider_ref VARCHAR(255),
+    failure_reason TEXT,
+    -- sent with the capture, a retry of the same attempt reuses it so the provider charges once --
+    idempotency_key UUID UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+    CONSTRAINT fk_payments_order
+        FOREIGN KEY (order_id)
+        REFERENCES orders(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_payments_order
+    ON payments (order_id, created_at);
+
+-- one capture in flight per order --
+CREATE UNIQUE INDEX IF NOT EXISTS idx_payments_pending_capture
+    ON payments (order_id)
+    WHERE kind = 'Capture' AND status = 'Pending';
@@ -0,0 +1,10 @@
+-- full-text search over item names and descriptions, names weigh more than descriptions --
+ALTER TABLE items
+    ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', coalesce(name, '')), 'A') ||
+        setweight(to_tsvector('english', coalesce(description, '')), 'B')
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_items_search_vector
+    ON items USING GIN (search_vector);
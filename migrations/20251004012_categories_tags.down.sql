@@ -0,0 +1,9 @@
+DROP TABLE IF EXISTS item_tags;
+
+DROP TABLE IF EXISTS tags;
+
+-- dropping the column also drops fk_items_category and idx_items_category_id --
+ALTER TABLE items
+    DROP COLUMN IF EXISTS category_id;
+
+DROP TABLE IF EXISTS categories;
@@ -0,0 +1,41 @@
+-- categories form a tree through parent_id, a category with children cannot be deleted --
+-- UNIQUE NULLS NOT DISTINCT keeps top level names unique too, it needs Postgres 15 or later --
+CREATE TABLE IF NOT EXISTS categories (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    parent_id UUID REFERENCES categories(id) ON DELETE RESTRICT,
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT uq_categories_parent_name UNIQUE NULLS NOT DISTINCT (parent_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_categories_parent_id
+    ON categories (parent_id);
+
+ALTER TABLE items
+    ADD COLUMN IF NOT EXISTS category_id UUID;
+
+ALTER TABLE items
+    ADD CONSTRAINT fk_items_category
+        FOREIGN KEY (category_id)
+        REFERENCES categories(id)
+        ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_items_category_id
+    ON items (category_id);
+
+-- tag names are stored lower case --
+CREATE TABLE IF NOT EXISTS tags (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(64) UNIQUE NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS item_tags (
+    item_id UUID NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+    tag_id UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (item_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_item_tags_tag_id
+    ON item_tags (tag_id);
@@ -0,0 +1,9 @@
+DROP TABLE IF EXISTS import_errors;
+
+DROP TABLE IF EXISTS imports;
+
+DROP TYPE IF EXISTS import_status;
+
+DROP TYPE IF EXISTS import_format;
+
+DROP TYPE IF EXISTS import_kind;
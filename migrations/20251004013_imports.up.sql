@@ -0,0 +1,37 @@
+CREATE TYPE import_kind AS ENUM (
+    'Users',
+    'Items'
+);
+
+CREATE TYPE import_format AS ENUM (
+    'Csv',
+    'Ndjson'
+);
+
+CREATE TYPE import_status AS ENUM (
+    'Pending',
+    'Completed',
+    'Failed'
+);
+
+-- uploaded files wait here until a worker imports them, the body is cleared once done --
+CREATE TABLE IF NOT EXISTS imports (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kind import_kind NOT NULL,
+    format import_format NOT NULL,
+    status import_status NOT NULL DEFAULT 'Pending',
+    body TEXT NOT NULL,
+    job_id BIGINT,
+    total_rows INTEGER NOT NULL DEFAULT 0,
+    imported_rows INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+-- rows rejected by validation, row 0 means the file itself could not be read --
+CREATE TABLE IF NOT EXISTS import_errors (
+    import_id UUID NOT NULL REFERENCES imports(id) ON DELETE CASCADE,
+    row_number INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (import_id, row_number)
+);
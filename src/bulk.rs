@@ -0,0 +1,312 @@
+use std::str::FromStr;
+use serde::de::DeserializeOwned;
+use crate::models::{ImportFormat, UserImportRow, ItemImportRow, UserDB, ItemDB, Order};
+
+// Upload limit for import files, they are read whole before being queued
+pub const IMPORT_MAX_BYTES: usize = 10 * 1024 * 1024;
+
+// Rows read from an import file, each one parsed or the reason it could not be
+pub type ParsedRows<T> = Vec<Result<T, String>>;
+
+// Splits CSV text into records. Quoted fields may hold commas, line breaks and "" for a quote.
+// Blank lines are skipped.
+pub fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut records = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record.len() == 1 && record[0].is_empty() {
+                    record.clear();
+                } else {
+                    records.push(std::mem::take(&mut record));
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("Unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+// Quotes a CSV field when it holds a separator, a quote or a line break
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn csv_line(fields: &[String]) -> String {
+    let mut line = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",");
+    line.push('\n');
+    line
+}
+
+// A CSV record with its fields looked up by header name
+pub struct CsvRow<'a> {
+    headers: &'a [String],
+    fields: &'a [String],
+}
+
+impl CsvRow<'_> {
+    // Empty cells count as missing
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let index = self.headers.iter().position(|header| header == name)?;
+        self.fields.get(index).map(|field| field.trim()).filter(|field| !field.is_empty())
+    }
+
+    pub fn required(&self, name: &str) -> Result<&str, String> {
+        self.get(name).ok_or_else(|| format!("{} is required", name))
+    }
+
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.get(name)
+            .map(|value| value.parse().map_err(|_| format!("{} has an invalid value '{}'", name, value)))
+            .transpose()
+    }
+
+    pub fn parse_required<T: FromStr>(&self, name: &str) -> Result<T, String> {
+        self.parse(name)?.ok_or_else(|| format!("{} is required", name))
+    }
+}
+
+// Import rows that can be read from a CSV record as well as from a JSON line
+pub trait FromCsvRow: Sized {
+    fn from_csv_row(row: &CsvRow) -> Result<Self, String>;
+}
+
+impl FromCsvRow for UserImportRow {
+    fn from_csv_row(row: &CsvRow) -> Result<Self, String> {
+        Ok(Self {
+            name: row.required("name")?.to_string(),
+            email: row.required("email")?.to_string(),
+            is_active: row.parse("is_active")?,
+        })
+    }
+}
+
+impl FromCsvRow for ItemImportRow {
+    fn from_csv_row(row: &CsvRow) -> Result<Self, String> {
+        Ok(Self {
+            name: row.required("name")?.to_string(),
+            price_minor: row.parse_required("price_minor")?,
+            currency: row.required("currency")?.to_string(),
+            quantity: row.parse_required("quantity")?,
+            description: row.get("description").map(str::to_string),
+            is_active: row.parse("is_active")?,
+            tax_category_id: row.parse("tax_category_id")?,
+            category_id: row.parse("category_id")?,
+        })
+    }
+}
+
+// Parses every row of an import file. Err is for a file that cannot be read at all,
+// a bad row only fails that row.
+pub fn parse_rows<T>(format: ImportFormat, body: &str) -> Result<ParsedRows<T>, String>
+where
+    T: FromCsvRow + DeserializeOwned,
+{
+    match format {
+        ImportFormat::Ndjson => Ok(body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e)))
+            .collect()),
+        ImportFormat::Csv => {
+            let records = parse_csv(body)?;
+            let Some((headers, rows)) = records.split_first() else {
+                return Err("The file has no header line".to_string());
+            };
+            let headers: Vec<String> = headers.iter().map(|header| header.trim().to_string()).collect();
+            Ok(rows
+                .iter()
+                .map(|fields| {
+                    if fields.len() != headers.len() {
+                        return Err(format!("Expected {} fields, found {}", headers.len(), fields.len()));
+                    }
+                    T::from_csv_row(&CsvRow { headers: &headers, fields })
+                })
+                .collect())
+        }
+    }
+}
+
+// Rows that export as a CSV line with a fixed header
+pub trait CsvRecord {
+    const HEADER: &'static [&'static str];
+
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+impl CsvRecord for UserDB {
+    const HEADER: &'static [&'static str] = &["id", "name", "email", "is_active", "created_at", "updated_at"];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            self.email.clone(),
+            self.is_active.to_string(),
+            self.created_at.to_rfc3339(),
+            self.updated_at.to_rfc3339(),
+        ]
+    }
+}
+
+impl CsvRecord for ItemDB {
+    const HEADER: &'static [&'static str] = &[
+        "id", "name", "price_minor", "currency", "quantity", "description", "is_active",
+        "tax_category_id", "category_id", "created_at", "updated_at",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            self.price_minor.to_string(),
+            self.currency.clone(),
+            self.quantity.to_string(),
+            optional(&self.description),
+            self.is_active.to_string(),
+            optional(&self.tax_category_id),
+            optional(&self.category_id),
+            self.created_at.to_rfc3339(),
+            self.updated_at.to_rfc3339(),
+        ]
+    }
+}
+
+// Order headers only, the NDJSON export carries the lines
+impl CsvRecord for Order {
+    const HEADER: &'static [&'static str] = &[
+        "id", "user_id", "status", "subtotal_minor", "discount_minor", "tax_minor", "amount_minor",
+        "currency", "coupon_code", "created_at", "updated_at",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.user_id.to_string(),
+            format!("{:?}", self.status),
+            self.subtotal.amount_minor.to_string(),
+            self.discount.amount_minor.to_string(),
+            self.tax.amount_minor.to_string(),
+            self.amount.amount_minor.to_string(),
+            self.amount.currency.clone(),
+            optional(&self.coupon_code),
+            self.created_at.to_rfc3339(),
+            self.updated_at.to_rfc3339(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let records = parse_csv("name,description\n\"Widget, large\",plain\n\"\",\"\"\n").unwrap();
+        assert_eq!(records, vec![
+            record(&["name", "description"]),
+            record(&["Widget, large", "plain"]),
+            record(&["", ""]),
+        ]);
+    }
+
+    #[test]
+    fn csv_escaped_quotes() {
+        let records = parse_csv("\"say \"\"hi\"\"\",\"\"\"\"\n").unwrap();
+        assert_eq!(records, vec![record(&["say \"hi\"", "\""])]);
+        // A quote inside an unquoted field is kept as it is
+        assert_eq!(parse_csv("5\" screen,x").unwrap(), vec![record(&["5\" screen", "x"])]);
+    }
+
+    #[test]
+    fn csv_crlf_blank_lines_and_bom() {
+        let records = parse_csv("\u{feff}a,b\r\n1,2\r\n\r\n3,4").unwrap();
+        assert_eq!(records, vec![record(&["a", "b"]), record(&["1", "2"]), record(&["3", "4"])]);
+    }
+
+    #[test]
+    fn csv_embedded_newlines() {
+        let records = parse_csv("a,b\n\"line one\r\nline two\",x\n").unwrap();
+        assert_eq!(records, vec![record(&["a", "b"]), record(&["line one\r\nline two", "x"])]);
+        assert_eq!(parse_csv("a,\"open\nnever closed").unwrap_err(), "Unterminated quoted field");
+    }
+
+    #[test]
+    fn csv_round_trips_through_csv_line() {
+        let fields = record(&["plain", "with, comma", "with \"quote\"", "two\nlines", ""]);
+        let text = csv_line(&fields);
+        assert_eq!(parse_csv(&text).unwrap(), vec![fields]);
+    }
+
+    #[test]
+    fn csv_rows_fail_one_at_a_time() {
+        let body = "name,email,is_active\nAda,ada@example.com,true\nBob,,\nCy,cy@example.com,maybe\nshort\n";
+        let rows: ParsedRows<UserImportRow> = parse_rows(ImportFormat::Csv, body).unwrap();
+        assert_eq!(rows.len(), 4);
+        let ada = rows[0].as_ref().unwrap();
+        assert_eq!((ada.name.as_str(), ada.email.as_str(), ada.is_active), ("Ada", "ada@example.com", Some(true)));
+        assert_eq!(rows[1].as_ref().unwrap_err(), "email is required");
+        assert_eq!(rows[2].as_ref().unwrap_err(), "is_active has an invalid value 'maybe'");
+        assert_eq!(rows[3].as_ref().unwrap_err(), "Expected 3 fields, found 1");
+
+        assert!(parse_rows::<UserImportRow>(ImportFormat::Csv, "").is_err());
+    }
+
+    #[test]
+    fn ndjson_rows_with_errors() {
+        let body = concat!(
+            "{\"name\":\"Ada\",\"email\":\"ada@example.com\"}\n",
+            "\n",
+            "{\"name\":\"Bob\"}\r\n",
+            "not json\n",
+            "{\"name\":\"Cy\",\"email\":\"cy@example.com\",\"is_active\":false,\"id\":\"ignored\"}",
+        );
+        let rows: ParsedRows<UserImportRow> = parse_rows(ImportFormat::Ndjson, body).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].as_ref().unwrap().name, "Ada");
+        assert!(rows[1].as_ref().unwrap_err().contains("missing field `email`"));
+        assert!(rows[2].as_ref().unwrap_err().starts_with("Invalid JSON"));
+        assert_eq!(rows[3].as_ref().unwrap().is_active, Some(false));
+    }
+}
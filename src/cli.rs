@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use sqlx::migrate::Migrate;
+use crate::config::{AppConfig, Backend, ConfigOverrides};
+use crate::models::{JobPriority, JobStatus};
+use crate::repository::db::{self, MIGRATOR};
+use crate::utils::{generate_random_array, bubble_sort};
+
+pub const USAGE: &str = "\
+usage: heartbeetle-task [--config FILE] [command] [options]
+
+Settings come from the config file (--config, APP_CONFIG or ./config.toml), then env vars,
+then the flags below. See config.example.toml.
+
+commands:
+  serve                 run the HTTP server, the default when no command is given
+      --port PORT           listen port (default 3003)
+      --workers N           job workers (default 3)
+      --queue-size N        max jobs in the queue (default 100)
+      --backend B           json, postgres or all (default all)
+      --data-file PATH      JSON store (default data.json)
+  migrate up            apply pending migrations
+  migrate down [--steps N]
+                        revert the last N applied migrations (default 1)
+  migrate status        list migrations and whether they are applied
+  seed --users N --items M [--backend B]
+                        insert fake users and items (default backend postgres)
+  jobs list [--status S] [--url URL]
+                        list the jobs of a running server
+  jobs enqueue PAYLOAD [--priority P] [--max-retries N] [--ttl SECONDS] [--url URL]
+                        queue a job on a running server
+  sort [NUMBERS...]     bubble sort the numbers, or a random array when none are given
+  transfer ...          move data between data.json and Postgres, see `transfer --help`
+  help                  show this message";
+
+#[derive(Debug)]
+pub enum MigrateCommand {
+    Up,
+    Down { steps: usize },
+    Status,
+}
+
+#[derive(Debug)]
+pub struct SeedArgs {
+    pub users: usize,
+    pub items: usize,
+    pub backend: Backend,
+}
+
+#[derive(Debug)]
+pub enum JobsCommand {
+    // Without --url the server is looked for on the configured port
+    List { url: Option<String>, status: Option<JobStatus> },
+    Enqueue { url: Option<String>, body: serde_json::Value },
+}
+
+#[derive(Debug)]
+pub enum Command {
+    // Flags override the loaded config
+    Serve(ConfigOverrides),
+    Migrate(MigrateCommand),
+    Seed(SeedArgs),
+    Jobs(JobsCommand),
+    Sort(Vec<i32>),
+    // Parsed by the transfer module itself
+    Transfer(Vec<String>),
+    Help,
+}
+
+// Flags of one subcommand, `--name value` pairs plus bare positional words
+struct Flags {
+    values: HashMap<String, String>,
+    positional: Vec<String>,
+}
+
+impl Flags {
+    fn parse(args: &[String], known: &[&str]) -> Result<Self, String> {
+        let mut values = HashMap::new();
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if known.contains(&name) => {
+                    let value = args.next().ok_or_else(|| format!("--{} needs a value", name))?;
+                    values.insert(name.to_string(), value.clone());
+                }
+                Some(name) => return Err(format!("Unknown option --{}", name)),
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Self { values, positional })
+    }
+
+    fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.values
+            .get(name)
+            .map(|value| value.parse().map_err(|_| format!("--{} has an invalid value '{}'", name, value)))
+            .transpose()
+    }
+
+    fn no_positional(&self) -> Result<(), String> {
+        match self.positional.first() {
+            Some(arg) => Err(format!("Unexpected argument {}", arg)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn at_least_one(value: usize, name: &str) -> Result<usize, String> {
+    if value == 0 {
+        return Err(format!("--{} must be at least 1", name));
+    }
+    Ok(value)
+}
+
+// Takes a leading `--config FILE` off the arguments
+pub fn take_config_flag(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    if args.first().map(String::as_str) != Some("--config") {
+        return Ok(None);
+    }
+    if args.len() < 2 {
+        return Err("--config needs a file".to_string());
+    }
+    let path = args.remove(1);
+    args.remove(0);
+    Ok(Some(path))
+}
+
+// Without a command the server starts, as it always has
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let Some((command, rest)) = args.split_first() else {
+        return parse_serve(&[]);
+    };
+
+    match command.as_str() {
+        "serve" => parse_serve(rest),
+        "migrate" => {
+            let flags = Flags::parse(rest, &["steps"])?;
+            let command = match flags.positional.as_slice() {
+                [action] if action == "up" => MigrateCommand::Up,
+                [action] if action == "down" => MigrateCommand::Down {
+                    steps: at_least_one(flags.get("steps")?.unwrap_or(1), "steps")?,
+                },
+                [action] if action == "status" => MigrateCommand::Status,
+                _ => return Err("migrate needs one of up, down or status".to_string()),
+            };
+            Ok(Command::Migrate(command))
+        }
+        "seed" => {
+            let flags = Flags::parse(rest, &["users", "items", "backend"])?;
+            flags.no_positional()?;
+            let args = SeedArgs {
+                users: flags.get("users")?.unwrap_or(0),
+                items: flags.get("items")?.unwrap_or(0),
+                backend: flags.get("backend")?.unwrap_or(Backend::Postgres),
+            };
+            if args.users == 0 && args.items == 0 {
+                return Err("seed needs --users and/or --items".to_string());
+            }
+            Ok(Command::Seed(args))
+        }
+        "jobs" => {
+            let flags = Flags::parse(rest, &["url", "status", "priority", "max-retries", "ttl"])?;
+            let url = flags.values.get("url").cloned();
+            match flags.positional.as_slice() {
+                [action] if action == "list" => {
+                    let status = flags.values.get("status")
+                        .map(|status| serde_json::from_value(serde_json::Value::String(status.clone()))
+                            .map_err(|_| format!("Unknown job status {}", status)))
+                        .transpose()?;
+                    Ok(Command::Jobs(JobsCommand::List { url, status }))
+                }
+                [action, payload] if action == "enqueue" => {
+                    let priority = flags.values.get("priority")
+                        .map(|priority| serde_json::from_value::<JobPriority>(serde_json::Value::String(priority.clone()))
+                            .map_err(|_| format!("Unknown priority {}, expected low, medium or high", priority)))
+                        .transpose()?;
+                    let body = serde_json::json!({
+                        "payload": payload,
+                        "priority": priority,
+                        "max_retries": flags.get::<u32>("max-retries")?,
+                        "ttl_seconds": flags.get::<i64>("ttl")?,
+                    });
+                    Ok(Command::Jobs(JobsCommand::Enqueue { url, body }))
+                }
+                _ => Err("jobs needs `list` or `enqueue PAYLOAD`".to_string()),
+            }
+        }
+        "sort" => {
+            let numbers = rest.iter()
+                .map(|arg| arg.parse().map_err(|_| format!("{} is not a whole number", arg)))
+                .collect::<Result<Vec<i32>, String>>()?;
+            Ok(Command::Sort(numbers))
+        }
+        "transfer" => Ok(Command::Transfer(rest.to_vec())),
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        other => Err(format!("Unknown command {}", other)),
+    }
+}
+
+// Range checks happen once the config is loaded, with every other setting
+fn parse_serve(args: &[String]) -> Result<Command, String> {
+    let flags = Flags::parse(args, &["port", "workers", "queue-size", "backend", "data-file"])?;
+    flags.no_positional()?;
+
+    Ok(Command::Serve(ConfigOverrides {
+        port: flags.get("port")?,
+        backend: flags.get("backend")?,
+        workers: flags.get("workers")?,
+        queue_size: flags.get("queue-size")?,
+        data_file: flags.values.get("data-file").cloned(),
+    }))
+}
+
+pub async fn run_migrate(config: &AppConfig, command: MigrateCommand) -> io::Result<()> {
+    let pool = db::connect(&config.database).await.map_err(io::Error::other)?;
+
+    match command {
+        MigrateCommand::Up => {
+            MIGRATOR.run(&pool).await.map_err(io::Error::other)?;
+            println!("Migrations are up to date");
+        }
+        MigrateCommand::Down { steps } => {
+            let mut conn = pool.acquire().await.map_err(io::Error::other)?;
+            conn.lock().await.map_err(io::Error::other)?;
+            let reverted = revert_latest(&mut conn, steps).await;
+            conn.unlock().await.map_err(io::Error::other)?;
+            reverted?;
+        }
+        MigrateCommand::Status => {
+            let mut conn = pool.acquire().await.map_err(io::Error::other)?;
+            conn.ensure_migrations_table().await.map_err(io::Error::other)?;
+            let applied: HashMap<i64, Vec<u8>> = conn.list_applied_migrations()
+                .await
+                .map_err(io::Error::other)?
+                .into_iter()
+                .map(|migration| (migration.version, migration.checksum.into_owned()))
+                .collect();
+
+            println!("{:<14} {:<8} {:<10} description", "version", "state", "reversible");
+            for migration in MIGRATOR.iter().filter(|m| !m.migration_type.is_down_migration()) {
+                let state = match applied.get(&migration.version) {
+                    Some(checksum) if checksum.as_slice() != migration.checksum.as_ref() => "changed",
+                    Some(_) => "applied",
+                    None => "pending",
+                };
+                let reversible = if migration.migration_type.is_reversible() { "yes" } else { "no" };
+                println!("{:<14} {:<8} {:<10} {}", migration.version, state, reversible, migration.description);
+            }
+            for version in applied.keys().filter(|version| MIGRATOR.iter().all(|m| m.version != **version)) {
+                println!("{:<14} {:<8} {:<10} (not in ./migrations)", version, "unknown", "-");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Reverts newest first and stops at the first migration that has no down script
+async fn revert_latest(conn: &mut sqlx::PgConnection, steps: usize) -> io::Result<()> {
+    conn.ensure_migrations_table().await.map_err(io::Error::other)?;
+    if let Some(version) = conn.dirty_version().await.map_err(io::Error::other)? {
+        return Err(io::Error::other(format!("Migration {} is partially applied, fix it by hand first", version)));
+    }
+
+    let mut applied = conn.list_applied_migrations().await.map_err(io::Error::other)?;
+    applied.sort_by_key(|migration| std::cmp::Reverse(migration.version));
+
+    if applied.is_empty() {
+        println!("No migrations are applied");
+    }
+
+    for applied in applied.iter().take(steps) {
+        let down = MIGRATOR
+            .iter()
+            .find(|m| m.version == applied.version && m.migration_type.is_down_migration())
+            .ok_or_else(|| io::Error::other(format!(
+                "Migration {} has no down script and cannot be reverted", applied.version
+            )))?;
+        let elapsed = conn.revert(down).await.map_err(io::Error::other)?;
+        println!("Reverted {} {} in {:?}", down.version, down.description, elapsed);
+    }
+
+    Ok(())
+}
+
+// Bare HTTP/1.1 over a TcpStream, enough to reach the local job endpoints
+fn http_request(url: &str, method: &str, path: &str, body: Option<&str>) -> io::Result<(u16, String)> {
+    let host = url
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::other(format!("Only http:// server urls are supported, got {}", url)))?
+        .trim_end_matches('/');
+
+    let mut stream = TcpStream::connect(host)?;
+    let body = body.unwrap_or("");
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method, path, host, body.len(), body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::other("Malformed response from the server"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::other("Malformed status line from the server"))?;
+
+    Ok((status, body.to_string()))
+}
+
+fn print_response(status: u16, body: &str) -> io::Result<()> {
+    let pretty = serde_json::from_str::<serde_json::Value>(body)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| body.to_string());
+    println!("{}", pretty);
+
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("Server answered with status {}", status)))
+    }
+}
+
+// Job commands talk to a running server, the queue only lives in its memory
+pub fn run_jobs(config: &AppConfig, command: JobsCommand) -> io::Result<()> {
+    let local = || format!("http://127.0.0.1:{}", config.server.port);
+    let (status, body) = match command {
+        JobsCommand::List { url, status: None } => {
+            http_request(&url.unwrap_or_else(local), "GET", "/jobs", None)?
+        }
+        JobsCommand::List { url, status: Some(status) } => {
+            let path = format!("/jobs/status?status={:?}", status);
+            http_request(&url.unwrap_or_else(local), "GET", &path, None)?
+        }
+        JobsCommand::Enqueue { url, body } => {
+            http_request(&url.unwrap_or_else(local), "POST", "/jobs", Some(&body.to_string()))?
+        }
+    };
+    print_response(status, &body)
+}
+
+// Task 1, the bubble sort demo
+pub fn run_sort(mut numbers: Vec<i32>) {
+    if numbers.is_empty() {
+        numbers = generate_random_array();
+    }
+    println!("Before sorting: {:?}", numbers);
+    bubble_sort(&mut numbers);
+    println!("After sorting: {:?}", numbers);
+}
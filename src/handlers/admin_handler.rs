@@ -0,0 +1,20 @@
+use actix_web::{web, Responder};
+use crate::config::AppConfig;
+use crate::openapi::Envelope;
+use crate::response::ApiResponse;
+
+// The settings the server runs with, secrets masked
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    tag = "admin",
+    summary = "Effective configuration with secrets masked",
+    responses(
+        (status = 200, description = "OK", body = Envelope<AppConfig>),
+    )
+)]
+pub async fn get_config(
+    config: web::Data<AppConfig>,
+) -> impl Responder {
+    ApiResponse::ok(config.redacted())
+}
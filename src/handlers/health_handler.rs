@@ -0,0 +1,179 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use actix_web::{web, Responder};
+use crate::config::AppConfig;
+use crate::jobs::workers::WorkerHandles;
+use crate::models::{ComponentHealth, ComponentStatus, JobQueue, ReadinessChecks};
+use crate::repository::db::Database;
+use crate::openapi::{Envelope, ErrorEnvelope, LiveStatus, ReadyStatus};
+use crate::response::{ApiResponse, ErrorCode};
+
+// A readiness check slower than this counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Set once a shutdown signal arrives so readiness fails while connections drain
+#[derive(Default)]
+pub struct ShutdownState(AtomicBool);
+
+impl ShutdownState {
+    pub fn begin(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+fn unchecked(status: ComponentStatus, detail: &str) -> ComponentHealth {
+    ComponentHealth {
+        status,
+        latency_ms: 0.0,
+        detail: Some(detail.to_string()),
+    }
+}
+
+fn disabled(detail: &str) -> ComponentHealth {
+    unchecked(ComponentStatus::Disabled, detail)
+}
+
+// Runs one check under the timeout and records how long it took
+async fn timed<F>(check: F) -> ComponentHealth
+where
+    F: Future<Output = Result<Option<String>, String>>,
+{
+    let started = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = (started.elapsed().as_secs_f64() * 1000.0 * 1000.0).round() / 1000.0;
+
+    let (status, detail) = match result {
+        Ok(Ok(detail)) => (ComponentStatus::Up, detail),
+        Ok(Err(e)) => (ComponentStatus::Down, Some(e)),
+        Err(_) => (ComponentStatus::Down, Some(format!("No answer within {}s", CHECK_TIMEOUT.as_secs()))),
+    };
+    ComponentHealth { status, latency_ms, detail }
+}
+
+async fn check_database(config: &AppConfig, database: &Database) -> ComponentHealth {
+    if !config.server.backend.uses_postgres() {
+        return disabled("The server runs without Postgres");
+    }
+    // The backend needs Postgres, every /db route answers 503 until a url is configured
+    let Some(pool) = database.pool() else {
+        return unchecked(ComponentStatus::Down, "No database url configured");
+    };
+    // The supervisor has not reached or migrated Postgres yet, or has lost it, so the /db routes answer 503
+    if !database.is_connected() {
+        return unchecked(ComponentStatus::Down, "Not connected, or the migrations have not run yet");
+    }
+
+    timed(async {
+        sqlx::query!("SELECT 1 AS one")
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(None)
+    })
+    .await
+}
+
+// Opened for appending so nothing in the file changes
+async fn check_data_file(config: &AppConfig) -> ComponentHealth {
+    if !config.server.backend.uses_json() {
+        return disabled("The server runs without the JSON store");
+    }
+
+    timed(async {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&config.storage.data_file)
+            .await
+            .map_err(|e| format!("{} is not writable: {}", config.storage.data_file, e))?;
+        Ok(None)
+    })
+    .await
+}
+
+async fn check_workers(workers: &WorkerHandles) -> ComponentHealth {
+    timed(async {
+        let detail = format!("{} of {} worker(s) alive", workers.alive(), workers.total());
+        if workers.alive() < workers.total() {
+            return Err(detail);
+        }
+        Ok(Some(detail))
+    })
+    .await
+}
+
+// A full queue turns every new job away
+async fn check_queue(queue: &JobQueue) -> ComponentHealth {
+    timed(async {
+        let size = queue.size().await;
+        let detail = format!("{} of {} slot(s) used", size, queue.max_queue_size);
+        if size >= queue.max_queue_size {
+            return Err(detail);
+        }
+        Ok(Some(detail))
+    })
+    .await
+}
+
+// The process is up and serving requests
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    summary = "Liveness probe",
+    responses(
+        (status = 200, description = "The process is up", body = Envelope<LiveStatus>),
+    )
+)]
+pub async fn live() -> impl Responder {
+    ApiResponse::ok(serde_json::json!({ "status": "alive" }))
+}
+
+// 503 when a component the server uses is down or a shutdown has started
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    summary = "Readiness probe with per component checks",
+    responses(
+        (status = 200, description = "Every component in use is up", body = Envelope<ReadyStatus>),
+        (status = 503, description = "A component is down or a shutdown has started", body = ErrorEnvelope),
+    )
+)]
+pub async fn ready(
+    config: web::Data<AppConfig>,
+    database: web::Data<Database>,
+    queue: web::Data<JobQueue>,
+    workers: web::Data<WorkerHandles>,
+    shutdown: web::Data<ShutdownState>,
+) -> impl Responder {
+    let (database, data_file, workers, queue) = tokio::join!(
+        check_database(&config, &database),
+        check_data_file(&config),
+        check_workers(&workers),
+        check_queue(&queue),
+    );
+    let checks = ReadinessChecks { database, data_file, workers, queue };
+
+    let healthy = [&checks.database, &checks.data_file, &checks.workers, &checks.queue]
+        .iter()
+        .all(|check| check.status != ComponentStatus::Down);
+    let status = match (shutdown.is_shutting_down(), healthy) {
+        (true, _) => "shutting_down",
+        (false, true) => "ready",
+        (false, false) => "not_ready",
+    };
+
+    let body = serde_json::json!({ "status": status, "checks": checks });
+    if status == "ready" {
+        ApiResponse::ok(body)
+    } else {
+        ApiResponse::error(ErrorCode::NotReady, format!("Not ready: {}", status))
+            .details(&body)
+            .legacy(body)
+    }
+}
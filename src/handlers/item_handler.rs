@@ -1,9 +1,12 @@
-use actix_web::{web, HttpResponse, Responder};
-use crate::models::{SharedState, CreateItem, Item, UpdateItem};
+use actix_web::{web, Responder};
+use crate::models::{SharedState, CreateItem, Item, UpdateItem, SearchQuery, ItemSearchHit};
+use crate::search::highlight;
 use crate::utils::write_to_file;
-use chrono::{DateTime, Utc};
+use crate::openapi::{Envelope, ErrorEnvelope};
+use crate::validation::Valid;
+use crate::response::ApiResponse;
+use chrono::Utc;
 use uuid::Uuid;
-use serde_json::json;
 
 
 impl From<CreateItem> for Item {
@@ -18,6 +21,9 @@ impl From<CreateItem> for Item {
             created_at: now,
             updated_at: now,
             is_active: true,
+            tax_category_id: c.tax_category_id,
+            category_id: c.category_id,
+            deleted_at: None,
         }
     }
 }
@@ -25,26 +31,50 @@ impl From<CreateItem> for Item {
 
 // Items layer
 // Create Item Handler
+#[utoipa::path(
+    post,
+    path = "/items",
+    tag = "json store",
+    operation_id = "json_create_item",
+    request_body = CreateItem,
+    summary = "Create an item in the JSON store",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Item>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_item(
     state: web::Data<SharedState>,
-    req: web::Json<CreateItem>
+    req: Valid<CreateItem>
 ) -> impl Responder {
     let item: Item = req.into_inner().into();
     
     let mut s = state.lock().await;  //
     s.items.insert(item.id, item.clone());
+    s.item_index.insert(&item);
     
     match write_to_file(&s).await {
-        Ok(_) => HttpResponse::Created().json(item),
+        Ok(_) => ApiResponse::created(item),
         Err(e) => {
             s.items.remove(&item.id);
-            HttpResponse::InternalServerError().json(json!({
-                "error": format!("Failed to persist item: {}", e)
-            }))
+            s.item_index.remove(item.id);
+            ApiResponse::internal(format!("Failed to persist item: {}", e))
         }
     }
 }
 // Get Item Handler
+#[utoipa::path(
+    get,
+    path = "/items/{id}",
+    tag = "json store",
+    operation_id = "json_get_item",
+    summary = "Get an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_item(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>
@@ -52,17 +82,29 @@ pub async fn get_item(
     let item_id = path.into_inner();
     let s = state.lock().await;  // Lock acquired for reading
     match s.items.get(&item_id) {
-        Some(item) => HttpResponse::Ok().json(item),
-        None => HttpResponse::NotFound().json(json!({
-            "error": format!("Item with id {} not found", item_id)
-        }))
+        Some(item) => ApiResponse::ok(item),
+        None => ApiResponse::not_found(format!("Item with id {} not found", item_id))
     }
 }
 // Update Item Handler
+#[utoipa::path(
+    put,
+    path = "/items/{id}",
+    tag = "json store",
+    operation_id = "json_update_item",
+    request_body = UpdateItem,
+    summary = "Update an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn update_item(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateItem>
+    req: Valid<UpdateItem>
 ) -> impl Responder {
     let item_id = path.into_inner();
     let dto = req.into_inner();
@@ -84,56 +126,120 @@ pub async fn update_item(
         if let Some(active) = dto.is_active {
             item.is_active = active;
         }
+        if let Some(tax_category_id) = dto.tax_category_id {
+            item.tax_category_id = tax_category_id;
+        }
+        if let Some(category_id) = dto.category_id {
+            item.category_id = category_id;
+        }
         item.updated_at = Utc::now();
         let updated_item = item.clone();
+        s.item_index.insert(&updated_item);
         // Persist to file
         match write_to_file(&s).await {
-            Ok(_) => HttpResponse::Ok().json(updated_item),
-            Err(e) => HttpResponse::InternalServerError().json(json!({
-                "error": format!("Failed to persist update: {}", e)
-            }))
+            Ok(_) => ApiResponse::ok(updated_item),
+            Err(e) => ApiResponse::internal(format!("Failed to persist update: {}", e))
         }
     } else {
-        HttpResponse::NotFound().json(json!({
-            "error": format!("Item with id {} not found", item_id)
-        }))
+        ApiResponse::not_found(format!("Item with id {} not found", item_id))
     }
 }
 // Delete Item Handler
+#[utoipa::path(
+    delete,
+    path = "/items/{id}",
+    tag = "json store",
+    operation_id = "json_delete_item",
+    summary = "Delete an item that was never ordered",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "The item is on an order, deactivate it instead", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn delete_item(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>
 ) -> impl Responder {
     let item_id = path.into_inner();
     let mut s = state.lock().await;
+    // Items that appear on an order stay, deactivate them instead
+    let ordered = s.orders.values()
+        .any(|order| order.lines().iter().any(|line| line.item_id == item_id));
+    if ordered {
+        return ApiResponse::conflict(format!("Item with id {} has been ordered and cannot be deleted, deactivate it instead", item_id));
+    }
     match s.items.remove(&item_id) {
         Some(deleted_item) => {
+            s.item_index.remove(item_id);
             // Persist to file
             match write_to_file(&s).await {
-                Ok(_) => HttpResponse::Ok().json(json!({
-                    "message": "Item deleted successfully",
-                    "item": deleted_item
-                })),
+                Ok(_) => ApiResponse::ok(&deleted_item)
+                    .message("Item deleted successfully")
+                    .legacy_under("item"),
                 Err(e) => {
                     // Rollback
+                    s.item_index.insert(&deleted_item);
                     s.items.insert(item_id, deleted_item);
-                    HttpResponse::InternalServerError().json(json!({
-                        "error": format!("Failed to persist deletion: {}", e)
-                    }))
+                    ApiResponse::internal(format!("Failed to persist deletion: {}", e))
                 }
             }
         },
-        None => HttpResponse::NotFound().json(json!({
-            "error": format!("Item with id {} not found", item_id)
-        }))
+        None => ApiResponse::not_found(format!("Item with id {} not found", item_id))
     }
 }
 // List All Items Handler
+#[utoipa::path(
+    get,
+    path = "/items",
+    tag = "json store",
+    operation_id = "json_list_items",
+    summary = "List items",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Item>>),
+    )
+)]
 pub async fn list_items(
     state: web::Data<SharedState>
 ) -> impl Responder {
     let s = state.lock().await;  // Lock acquired for reading
     let items: Vec<Item> = s.items.values().cloned().collect();
-    HttpResponse::Ok().json(items)
+    ApiResponse::ok(items)
     // Lock released here
 }
+
+// Search Items Handler
+#[utoipa::path(
+    get,
+    path = "/items/search",
+    tag = "json store",
+    operation_id = "json_search_items",
+    params(SearchQuery),
+    summary = "Full text search over item names and descriptions",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<ItemSearchHit>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+    )
+)]
+pub async fn search_items(
+    state: web::Data<SharedState>,
+    query: web::Query<SearchQuery>
+) -> impl Responder {
+    let (q, limit) = match query.validate() {
+        Ok(parsed) => parsed,
+        Err(msg) => return ApiResponse::bad_request(msg)
+    };
+    let s = state.lock().await;
+    let hits: Vec<ItemSearchHit> = s.item_index.search(q)
+        .into_iter()
+        .filter_map(|(id, rank)| s.items.get(&id).map(|item| (item, rank)))
+        .take(limit)
+        .map(|(item, rank)| ItemSearchHit {
+            item: item.clone(),
+            rank,
+            snippet: highlight(item, q),
+        })
+        .collect();
+    ApiResponse::ok(hits)
+}
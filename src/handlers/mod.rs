@@ -1,3 +1,5 @@
 pub mod user_handler;
 pub mod order_handler;
-pub mod item_handler;
\ No newline at end of file
+pub mod item_handler;
+pub mod admin_handler;
+pub mod health_handler;
\ No newline at end of file
@@ -1,7 +1,12 @@
-use actix_web::{web, HttpResponse, Responder};
-use crate::models::{SharedState, CreateOrder, UpdateOrder, Order, OrderStatus, Item};
+use actix_web::{web, Responder};
+use crate::models::{SharedState, CreateOrder, CreateOrderLine, UpdateOrder, Order, OrderLine, OrderStatus, PriceBreakdown};
 use crate::utils::write_to_file;
-use chrono::{DateTime, Utc};
+use crate::money::{Money, DEFAULT_CURRENCY};
+use crate::pricing::{self, PricedLine};
+use crate::openapi::{Envelope, ErrorEnvelope};
+use crate::validation::Valid;
+use crate::response::ApiResponse;
+use chrono::Utc;
 use uuid::Uuid;
 
 impl From<CreateOrder> for Order {
@@ -10,79 +15,139 @@ impl From<CreateOrder> for Order {
         Order {
             id: Uuid::new_v4(),
             user_id: c.user_id,
-            items: Vec::new(),
-            amount: 0.0,
+            items: Some(Vec::new()),
+            subtotal: Money::zero(DEFAULT_CURRENCY),
+            discount: Money::zero(DEFAULT_CURRENCY),
+            tax: Money::zero(DEFAULT_CURRENCY),
+            amount: Money::zero(DEFAULT_CURRENCY),
+            coupon_code: None,
             status: OrderStatus::Pending,
             created_at: now,
             updated_at: now,
+            deleted_at: None,
         }
     }
 }
 
+// The JSON store has no tax categories or coupons, so this is the subtotal with zero discount and tax.
+// It also rejects orders that mix currencies.
+fn price_order_lines(lines: &[OrderLine]) -> Result<PriceBreakdown, String> {
+    let priced: Vec<PricedLine> = lines.iter()
+        .map(|line| PricedLine {
+            unit_price: line.unit_price.clone(),
+            quantity: line.quantity,
+            tax_rate_bps: line.tax_rate_bps,
+        })
+        .collect();
+    pricing::price_lines(&priced, None)
+}
+
 // Order layer
+#[utoipa::path(
+    post,
+    path = "/orders",
+    tag = "json store",
+    operation_id = "json_create_order",
+    request_body = CreateOrder,
+    summary = "Create an order in the JSON store",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Order>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "User or item not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_order(
     state: web::Data<SharedState>,
-    req: web::Json<CreateOrder>
+    req: Valid<CreateOrder>
 ) -> impl Responder {
     let dto = req.into_inner();
     let user_id = dto.user_id;
     let mut s = state.lock().await;
     // Verify user exists
     if !s.users.contains_key(&user_id) {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        }));
+        return ApiResponse::not_found(format!("User with id {} not found", user_id));
     }
-    // Calculate total amount from items
-    let mut total_amount = 0.0;
+    if dto.coupon_code.is_some() {
+        return ApiResponse::bad_request("Coupons are only supported on /db/orders");
+    }
+    let lines = match CreateOrderLine::merge(&dto.items) {
+        Ok(lines) => lines,
+        Err(msg) => {
+            return ApiResponse::bad_request(msg);
+        }
+    };
+    // Collect the order lines, the total is calculated from them below
     let mut items_vec = Vec::new();
 
 
-    for item_id in &dto.item_ids {
-        match s.items.get(item_id) {
+    for line in &lines {
+        match s.items.get(&line.item_id) {
             Some(item) => {
                 if !item.is_active {
-                    return HttpResponse::BadRequest().json(serde_json::json!({ // 400 BadRequest
-                        "error": format!("Item {} is not available", item_id)
-                    }));
+                    return ApiResponse::bad_request(format!("Item {} is not available", line.item_id));
                 }
-                total_amount += item.price;
-                items_vec.push(item.clone());
+                items_vec.push(OrderLine {
+                    item_id: item.id,
+                    name: item.name.clone(),
+                    quantity: line.quantity,
+                    unit_price: item.price.clone(),
+                    tax_rate_bps: 0,
+                });
             },
             None => {
-                return HttpResponse::NotFound().json(serde_json::json!({
-                    "error": format!("Item with id {} not found", item_id)
-                }));
+                return ApiResponse::not_found(format!("Item with id {} not found", line.item_id));
             }
         }
     }
+    let breakdown = match price_order_lines(&items_vec) {
+        Ok(breakdown) => breakdown,
+        Err(msg) => {
+            return ApiResponse::bad_request(msg);
+        }
+    };
     // Create order
     let mut order: Order = dto.into();
-    order.amount = total_amount;  // Set calculated amount
-    order.items = items_vec;
+    order.subtotal = breakdown.subtotal;
+    order.discount = breakdown.discount;
+    order.tax = breakdown.tax;
+    order.amount = breakdown.total;  // Set calculated amount
+    order.items = Some(items_vec);
 
 
     // Store order
     s.orders.insert(order.id, order.clone());
     // Persist to file
     match write_to_file(&s).await {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({
-            "message": "Order sucessfully created",
-            "order": order 
-        })),
+        Ok(_) => ApiResponse::created(&order)
+            .message("Order sucessfully created")
+            .legacy_under("order"),
         Err(e) => {
             s.orders.remove(&order.id);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to persist order: {}", e)
-            }))
+            ApiResponse::internal(format!("Failed to persist order: {}", e))
         }
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/orders/{id}",
+    tag = "json store",
+    operation_id = "json_update_order",
+    request_body = UpdateOrder,
+    summary = "Update an order's lines or status",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Order>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "The order can no longer change, or the status change is not allowed", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn update_order(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateOrder>
+    req: Valid<UpdateOrder>
 ) -> impl Responder {
     let order_id = path.into_inner();
     let dto = req.into_inner();
@@ -90,35 +155,59 @@ pub async fn update_order(
 
 
     // check if order exists
-    if !s.orders.contains_key(&order_id) {
-        return HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Order with id {} not found", order_id)
-        }));
+    let current_status = match s.orders.get(&order_id) {
+        Some(order) => order.status.clone(),
+        None => {
+            return ApiResponse::not_found(format!("Order with id {} not found", order_id));
+        }
+    };
+
+    // Reject illegal status transitions before anything is modified, with the same rules as /db/orders.
+    // The JSON store keeps no status history, only /db/orders/{id}/history has one.
+    if let Some(ref next) = dto.status
+        && let Err(msg) = current_status.check_update(next, order_id)
+    {
+        return ApiResponse::conflict(msg);
     }
 
     // Validate and collect items BEFORE getting mutable reference to order
-    let (new_items_vec, new_amount) = if let Some(new_item_ids) = &dto.item_ids {
-        let mut new_amount = 0.0;
+    let (new_items_vec, new_amount) = if let Some(new_items) = &dto.items {
+        let lines = match CreateOrderLine::merge(new_items) {
+            Ok(lines) => lines,
+            Err(msg) => {
+                return ApiResponse::bad_request(msg);
+            }
+        };
+        if let Err(msg) = current_status.check_items_editable() {
+            return ApiResponse::conflict(msg);
+        }
         let mut new_items_vec = Vec::new();
         
-        for item_id in new_item_ids {
-            match s.items.get(item_id) {
+        for line in &lines {
+            match s.items.get(&line.item_id) {
                 Some(item) => {
                     if !item.is_active {
-                        return HttpResponse::BadRequest().json(serde_json::json!({
-                            "error": format!("Item {} is not available", item_id)
-                        }));
+                        return ApiResponse::bad_request(format!("Item {} is not available", line.item_id));
                     }
-                    new_amount += item.price;
-                    new_items_vec.push(item.clone());
+                    new_items_vec.push(OrderLine {
+                        item_id: item.id,
+                        name: item.name.clone(),
+                        quantity: line.quantity,
+                        unit_price: item.price.clone(),
+                        tax_rate_bps: 0,
+                    });
                 },
                 None => {
-                    return HttpResponse::NotFound().json(serde_json::json!({
-                        "error": format!("Item with id {} not found", item_id)
-                    }));
+                    return ApiResponse::not_found(format!("Item with id {} not found", line.item_id));
                 }
             }
         }
+        let new_amount = match price_order_lines(&new_items_vec) {
+            Ok(breakdown) => breakdown,
+            Err(msg) => {
+                return ApiResponse::bad_request(msg);
+            }
+        };
         (Some(new_items_vec), Some(new_amount))
     } else {
         (None, None)
@@ -128,8 +217,13 @@ pub async fn update_order(
     if let Some(order) = s.orders.get_mut(&order_id) {
         // Update items if provided
         if let Some(items) = new_items_vec {
-            order.items = items;
-            order.amount = new_amount.unwrap();
+            order.items = Some(items);
+            if let Some(breakdown) = new_amount {
+                order.subtotal = breakdown.subtotal;
+                order.discount = breakdown.discount;
+                order.tax = breakdown.tax;
+                order.amount = breakdown.total;
+            }
         }
         
         // Update status if provided
@@ -142,21 +236,27 @@ pub async fn update_order(
         
         // Persist to file
         match write_to_file(&s).await {
-            Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-                "message": "Order successfully updated",
-                "updated_order": updated_order 
-            })),
-            Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to persist update: {}", e)
-            }))
+            Ok(_) => ApiResponse::ok(&updated_order)
+                .message("Order successfully updated")
+                .legacy_under("updated_order"),
+            Err(e) => ApiResponse::internal(format!("Failed to persist update: {}", e))
         }
     } else {
-        HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Order with id {} not found", order_id)
-        }))
+        ApiResponse::not_found(format!("Order with id {} not found", order_id))
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/orders/{id}",
+    tag = "json store",
+    operation_id = "json_get_order",
+    summary = "Get an order",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Order>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_order_with_details(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>
@@ -164,32 +264,26 @@ pub async fn get_order_with_details(
     let order_id = path.into_inner();
     let s = state.lock().await;
     match s.orders.get(&order_id) {
-        Some(order) => {
-            // Fetch full item details
-            let items: Vec<Item> = order.items.iter()
-                .filter_map(|item| s.items.get(&item.id).cloned())
-                .collect();
-            let order_details = Order {
-                id: order.id,
-                user_id: order.user_id,
-                items,
-                amount: order.amount,
-                status: order.status.clone(), // .clone() minizes peformance but I cut corners since performace cost is primary to gettign the full items within an order
-                created_at: order.created_at,
-                updated_at: order.updated_at,
-            };
-            HttpResponse::Ok().json(order_details)
-        },
-        None => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Order with id {} not found", order_id)
-        }))
+        // Order lines carry their own name and price snapshot, so they are returned as stored
+        Some(order) => ApiResponse::ok(order),
+        None => ApiResponse::not_found(format!("Order with id {} not found", order_id))
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/orders",
+    tag = "json store",
+    operation_id = "json_list_orders",
+    summary = "List orders",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Order>>),
+    )
+)]
 pub async fn list_orders(
     state: web::Data<SharedState>
 ) -> impl Responder {
     let s = state.lock().await;
     let orders: Vec<Order> = s.orders.values().cloned().collect();
-    HttpResponse::Ok().json(orders)
+    ApiResponse::ok(orders)
 }
\ No newline at end of file
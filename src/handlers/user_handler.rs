@@ -1,7 +1,10 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, Responder};
 use crate::models::{SharedState, CreateUser, User, Order, UpdateUser};
 use crate::utils::{write_to_file};
-use chrono::{DateTime, Utc};
+use crate::openapi::{Envelope, ErrorEnvelope};
+use crate::validation::Valid;
+use crate::response::ApiResponse;
+use chrono::Utc;
 use uuid::Uuid;
 
 // Data Transfer Object layer
@@ -16,14 +19,28 @@ impl From<CreateUser> for User{
             created_at: now,
             updated_at: now,
             is_active: true,
+            deleted_at: None,
         }
     }
 }
 
 // User layer
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "json store",
+    operation_id = "json_create_user",
+    request_body = CreateUser,
+    summary = "Create a user in the JSON store",
+    responses(
+        (status = 201, description = "Created", body = Envelope<User>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_user(
     state: web::Data<SharedState>,
-    req: web::Json<CreateUser>,
+    req: Valid<CreateUser>,
 ) -> impl Responder {
 //  let dto: CreateUser = req.into_inner();  // Deserializes JSON to CreateUser
 //  let user: User = dto.into();             // Calls From<CreateUser> for User
@@ -35,19 +52,27 @@ pub async fn create_user(
     // Store user
     s.users.insert(user.id, user.clone());
     match write_to_file(&s).await {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({
-            "message": "user successfully signed up",
-            "user": user,
-        })),
+        Ok(_) => ApiResponse::created(&user)
+            .message("user successfully signed up")
+            .legacy_under("user"),
         Err(err) => {
             s.users.remove(&user.id);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to pesist user: {}", err)
-            }))
+            ApiResponse::internal(format!("Failed to pesist user: {}", err))
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "json store",
+    operation_id = "json_get_user",
+    summary = "Get a user with their orders",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_user(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>
@@ -68,19 +93,32 @@ pub async fn get_user(
                 created_at: user.created_at,
                 updated_at: user.updated_at,
                 is_active: user.is_active,
+                deleted_at: user.deleted_at,
             };
-            HttpResponse::Ok().json(user_details)
+            ApiResponse::ok(user_details)
         },
-        None => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        }))
+        None => ApiResponse::not_found(format!("User with id {} not found", user_id))
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/users/{id}",
+    tag = "json store",
+    operation_id = "json_update_user",
+    request_body = UpdateUser,
+    summary = "Update a user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn update_user(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateUser>
+    req: Valid<UpdateUser>
 ) -> impl Responder {
     let user_id = path.into_inner();
     let dto = req.into_inner();
@@ -100,20 +138,27 @@ pub async fn update_user(
         let updated_user = user.clone();
         // Persist to file
         match write_to_file(&s).await {
-            Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-                "message": "user successfully updated",
-                "updated user": updated_user,
-            })),
-            Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to persist update: {}", e)
-            }))
+            Ok(_) => ApiResponse::ok(&updated_user)
+                .message("user successfully updated")
+                .legacy_under("updated user"),
+            Err(e) => ApiResponse::internal(format!("Failed to persist update: {}", e))
         }
     } else {
-        HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        }))
+        ApiResponse::not_found(format!("User with id {} not found", user_id))
     }
 }
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "json store",
+    operation_id = "json_delete_user",
+    summary = "Delete a user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn delete_user(
     state: web::Data<SharedState>,
     path: web::Path<Uuid>
@@ -124,28 +169,33 @@ pub async fn delete_user(
         Some(deleted_user) => {
             // Persist to file
             match write_to_file(&s).await {
-                Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-                    "message": "User deleted successfully",
-                    "user": deleted_user
-                })),
+                Ok(_) => ApiResponse::ok(&deleted_user)
+                    .message("User deleted successfully")
+                    .legacy_under("user"),
                 Err(e) => {
                     // Rollback - re-insert the user
                     s.users.insert(user_id, deleted_user);
-                    HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": format!("Failed to persist deletion: {}", e)
-                    }))
+                    ApiResponse::internal(format!("Failed to persist deletion: {}", e))
                 }
             }
         },
-        None => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        }))
+        None => ApiResponse::not_found(format!("User with id {} not found", user_id))
     }
 }
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "json store",
+    operation_id = "json_list_users",
+    summary = "List users",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<User>>),
+    )
+)]
 pub async fn list_users(
     state: web::Data<SharedState>
 ) -> impl Responder {
     let s = state.lock().await;
     let users: Vec<User> = s.users.values().cloned().collect();
-    HttpResponse::Ok().json(users)
+    ApiResponse::ok(users)
 }
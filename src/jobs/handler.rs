@@ -1,22 +1,49 @@
-use actix_web::{web, HttpResponse, Responder};
-use crate::models::{JobQueue, CreateJob, StatusJobQuery};
+use actix_web::{web, Responder};
+use crate::models::{Job, JobQueue, CreateJob, StatusJobQuery};
+use crate::openapi::{Envelope, ErrorEnvelope, JobCreated};
+use crate::validation::Valid;
+use crate::response::ApiResponse;
 
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    tag = "jobs",
+    request_body = CreateJob,
+    summary = "Queue a background job",
+    responses(
+        (status = 201, description = "Created", body = Envelope<JobCreated>),
+        (status = 400, description = "The queue is full, or the body failed validation", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_job(
     queue: web::Data<JobQueue>,
-    req: web::Json<CreateJob>,
+    req: Valid<CreateJob>,
 ) -> impl Responder {
     match queue.add_job(req.into_inner()).await {
-        Ok(job) => HttpResponse::Created().json(serde_json::json!({
+        Ok(job) => ApiResponse::created(serde_json::json!({
+            "job_id": job.job_id,
+            "status": job.status,
+        }))
+        .message("Job created successfully")
+        .legacy(serde_json::json!({
             "message": "Job created successfully",
             "job_id": job.job_id,
             "status": job.status,
         })),
-        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({
-            "error": e
-        }))
+        Err(e) => ApiResponse::bad_request(e)
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    summary = "Get a job",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Job>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_job(
     queue: web::Data<JobQueue>,
     path: web::Path<u64>,
@@ -24,21 +51,38 @@ pub async fn get_job(
     let job_id = path.into_inner();
 
     match queue.get_job(job_id).await {
-        Some(job) => HttpResponse::Ok().json(job),
-        None => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Job with id {} not found", job_id)
-        }))
+        Some(job) => ApiResponse::ok(job),
+        None => ApiResponse::not_found(format!("Job with id {} not found", job_id))
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "jobs",
+    summary = "List jobs",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Job>>),
+    )
+)]
 pub async fn list_jobs(
     queue: web::Data<JobQueue>,
 ) -> impl Responder {
     let jobs = queue.get_all_jobs().await;
-    HttpResponse::Ok().json(jobs)
+    ApiResponse::ok(jobs)
 }
 
 // Bonus: Get jobs by status
+#[utoipa::path(
+    get,
+    path = "/jobs/status",
+    tag = "jobs",
+    params(StatusJobQuery),
+    summary = "List jobs in one status",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Job>>),
+    )
+)]
 pub async fn list_jobs_by_status(
     queue: web::Data<JobQueue>,
     query: web::Query<StatusJobQuery>,
@@ -49,6 +93,6 @@ pub async fn list_jobs_by_status(
         .filter(|j| j.status == query.status)
         .collect();
     
-    HttpResponse::Ok().json(filtered)
+    ApiResponse::ok(filtered)
 }
 
@@ -1,7 +1,7 @@
-use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use crate::logging::current_request_id;
 use crate::models::{CreateJob, Job, JobStatus, JobQueue, JobPriority};
 
 impl JobQueue {
@@ -44,6 +44,8 @@ impl JobQueue {
             created_at: now,
             updated_at: now,
             expires_at,
+            // The request that queued the job, so the worker's logs can be matched to it
+            request_id: current_request_id(),
         };
 
         jobs.push(job.clone());
@@ -63,6 +65,11 @@ impl JobQueue {
         jobs.iter().find(|j| j.job_id == job_id).cloned()
     }
 
+    // Every stored job counts against max_queue_size, finished ones included
+    pub async fn size(&self) -> usize {
+        self.jobs.lock().await.len()
+    }
+
     pub async fn get_all_jobs(&self) -> Vec<Job> {
         let jobs = self.jobs.lock().await;
         jobs.clone()
@@ -107,13 +114,13 @@ impl JobQueue {
 
     pub async fn retry_job(&self, job_id: u64) -> bool {
         let mut jobs = self.jobs.lock().await;
-        if let Some(job) = jobs.iter_mut().find(|j| j.job_id == job_id) {
-            if job.retries < job.max_retries {
-                job.retries += 1;
-                job.status = JobStatus::Pending;
-                job.updated_at = Utc::now();
-                return true;
-            }
+        if let Some(job) = jobs.iter_mut().find(|j| j.job_id == job_id)
+            && job.retries < job.max_retries
+        {
+            job.retries += 1;
+            job.status = JobStatus::Pending;
+            job.updated_at = Utc::now();
+            return true;
         }
         false
     }
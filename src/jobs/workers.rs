@@ -1,70 +1,90 @@
-use crate::models::{JobQueue, JobStatus};
+use crate::logging::{span, with_request_id};
+use crate::models::{Job, JobQueue, JobStatus, CreateJob};
+use crate::repository::purge_db::purge_deleted;
+use crate::repository::bulk_db::run_import;
+use chrono::Utc;
 use rand::Rng;
+use sqlx::PgPool;
 use std::sync::Arc;
+use uuid::Uuid;
+use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
 
 pub struct Worker {
     id: usize,
     queue: Arc<JobQueue>,
+    // None when the server runs without Postgres
+    pool: Option<PgPool>,
+    poll_interval: Duration,
 }
 
 impl Worker {
-    pub fn new(id: usize, queue: Arc<JobQueue>) -> Self {
-        Self { id, queue }
+    pub fn new(id: usize, queue: Arc<JobQueue>, pool: Option<PgPool>, poll_interval: Duration) -> Self {
+        Self { id, queue, pool, poll_interval }
     }
 
     pub async fn start(self) {
-        println!("Worker {} started", self.id);
+        log::info!("Worker {} started", self.id);
 
         loop {
             // Check for next job
             if let Some(job) = self.queue.get_next_pending_job().await {
-                println!("Worker {} picked up job {}", self.id, job.job_id);
-
-                // Process the job
-                let result = self.process_job(&job.payload).await;
-
-                match result {
-                    Ok(output) => {
-                        println!("Worker {} completed job {}", self.id, job.job_id);
-                        self.queue
-                            .update_job_status(job.job_id, JobStatus::Completed, Some(output))
-                            .await;
-                    }
-                    Err(error) => {
-                        eprintln!("Worker {} failed job {}: {}", self.id, job.job_id, error);
-                        
-                        // Try to retry
-                        let retried = self.queue.retry_job(job.job_id).await;
-                        
-                        if retried {
-                            println!("Job {} queued for retry", job.job_id);
-                        } else {
-                            // Max retries reached
-                            self.queue
-                                .update_job_status(
-                                    job.job_id,
-                                    JobStatus::Failed,
-                                    Some(format!("Failed after {} retries: {}", job.max_retries, error)),
-                                )
-                                .await;
-                        }
-                    }
-                }
+                // Logged under the id of the request that queued the job
+                with_request_id(job.request_id.clone(), self.run_job(job)).await;
             } else {
                 // No jobs available, sleep briefly
-                sleep(Duration::from_millis(100)).await;
+                sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    async fn run_job(&self, job: Job) {
+        log::info!("Worker {} picked up job {}", self.id, job.job_id);
+
+        // Process the job
+        let result = self.process_job(&job.payload).await;
+
+        match result {
+            Ok(output) => {
+                log::info!("Worker {} completed job {}", self.id, job.job_id);
+                self.queue
+                    .update_job_status(job.job_id, JobStatus::Completed, Some(output))
+                    .await;
+            }
+            Err(error) => {
+                log::warn!("Worker {} failed job {}: {}", self.id, job.job_id, error);
+
+                // Try to retry
+                let retried = self.queue.retry_job(job.job_id).await;
+
+                if retried {
+                    log::info!("Job {} queued for retry", job.job_id);
+                } else {
+                    log::error!("Job {} failed after {} retries", job.job_id, job.max_retries);
+                    // Max retries reached
+                    self.queue
+                        .update_job_status(
+                            job.job_id,
+                            JobStatus::Failed,
+                            Some(format!("Failed after {} retries: {}", job.max_retries, error)),
+                        )
+                        .await;
+                }
             }
         }
     }
 
+    fn database(&self) -> Result<&PgPool, String> {
+        self.pool.as_ref().ok_or_else(|| "This job needs Postgres, which this server runs without".to_string())
+    }
+
     async fn process_job(&self, payload: &str) -> Result<String, String> {
         // Simulate long-running task
         let sleep_duration = {
             let mut rng = rand::rng();
             rng.random_range(2..=5)  // ✅ RNG dropped here
         };
-        println!(
+        log::debug!(
             "Worker {} processing payload '{}' (will take {}s)",
             self.id, payload, sleep_duration
         );
@@ -86,6 +106,43 @@ impl Worker {
                 .ok_or("Invalid payload format")?;
             
             Ok(format!("Email sent to {}", email))
+        } else if payload.starts_with("purge_deleted:") {
+            // purge_deleted:<retention days>
+            let days: i64 = payload
+                .split(':')
+                .nth(1)
+                .and_then(|days| days.parse().ok())
+                .filter(|days| *days >= 0)
+                .ok_or("Invalid payload format")?;
+
+            let report = span("purge_deleted", purge_deleted(self.database()?, Utc::now() - chrono::Duration::days(days)))
+                .await
+                .map_err(|e| format!("Purge failed: {}", e))?;
+
+            Ok(format!(
+                "Purged {} order(s), {} item(s) and {} user(s) deleted more than {} day(s) ago, kept {} order(s) still holding stock",
+                report.orders, report.items, report.users, days, report.skipped_orders
+            ))
+        } else if payload.starts_with("import:") {
+            // import:<import id>, the uploaded file waits in the imports table
+            let import_id: Uuid = payload
+                .split(':')
+                .nth(1)
+                .and_then(|id| id.parse().ok())
+                .ok_or("Invalid payload format")?;
+
+            let import = span("run_import", run_import(self.database()?, import_id))
+                .await
+                .map_err(|e| format!("Import {} failed: {}", import_id, e))?;
+
+            Ok(format!(
+                "Import {} {:?}: {} of {} row(s) imported, {} rejected",
+                import.id,
+                import.status,
+                import.imported_rows,
+                import.total_rows,
+                import.total_rows - import.imported_rows
+            ))
         } else if payload == "fail" {
             // Simulate failure for testing retries
             Err("Simulated failure".to_string())
@@ -95,12 +152,47 @@ impl Worker {
     }
 }
 
-// Spawn multiple workers (Bonus)
-pub fn spawn_workers(num_workers: usize, queue: Arc<JobQueue>) {
-    for id in 0..num_workers {
-        let worker = Worker::new(id, queue.clone());
-        tokio::spawn(async move {
-            worker.start().await;
-        });
+// Worker tasks loop forever, one that finished has panicked
+pub struct WorkerHandles(Vec<JoinHandle<()>>);
+
+impl WorkerHandles {
+    pub fn alive(&self) -> usize {
+        self.0.iter().filter(|handle| !handle.is_finished()).count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.len()
     }
+}
+
+// Spawn multiple workers (Bonus)
+pub fn spawn_workers(num_workers: usize, queue: Arc<JobQueue>, pool: Option<PgPool>, poll_interval: Duration) -> WorkerHandles {
+    let handles = (0..num_workers)
+        .map(|id| {
+            let worker = Worker::new(id, queue.clone(), pool.clone(), poll_interval);
+            tokio::spawn(async move {
+                worker.start().await;
+            })
+        })
+        .collect();
+    WorkerHandles(handles)
+}
+
+// Queue a purge of soft deleted rows once a day
+pub fn spawn_purge_schedule(queue: Arc<JobQueue>, retention_days: i64) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+        loop {
+            interval.tick().await;
+            let job = CreateJob {
+                payload: format!("purge_deleted:{}", retention_days),
+                priority: None,
+                max_retries: None,
+                ttl_seconds: None,
+            };
+            if let Err(e) = queue.add_job(job).await {
+                log::error!("Could not queue purge job: {}", e);
+            }
+        }
+    });
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::io::Write;
+use std::time::Instant;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use chrono::{SecondsFormat, Utc};
+use uuid::Uuid;
+use crate::config::{LogFormat, LoggingConfig};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// A client supplied id longer than this, or with odd characters, is replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    // The id of the request (or of the job a request queued) being worked on
+    static REQUEST_ID: String;
+}
+
+// RUST_LOG, when set, wins over logging.level
+pub fn init(config: &LoggingConfig) {
+    let env = env_logger::Env::default().default_filter_or(config.level.as_str());
+    let format = config.format;
+    env_logger::Builder::from_env(env)
+        .format(move |buf, record| {
+            let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            let request_id = current_request_id();
+            match format {
+                LogFormat::Json => {
+                    let line = serde_json::json!({
+                        "ts": ts,
+                        "level": record.level().as_str(),
+                        "target": record.target(),
+                        "request_id": request_id,
+                        "message": record.args().to_string(),
+                    });
+                    writeln!(buf, "{}", line)
+                }
+                LogFormat::Text => match request_id {
+                    Some(id) => writeln!(buf, "{} {:<5} {} [{}] {}", ts, record.level(), record.target(), id, record.args()),
+                    None => writeln!(buf, "{} {:<5} {} {}", ts, record.level(), record.target(), record.args()),
+                },
+            }
+        })
+        // A second init (only possible from a stray call) keeps the first logger
+        .try_init()
+        .ok();
+}
+
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// Runs `fut` with `id` attached to everything it logs
+pub async fn with_request_id<F: Future>(id: Option<String>, fut: F) -> F::Output {
+    match id {
+        Some(id) => REQUEST_ID.scope(id, fut).await,
+        None => fut.await,
+    }
+}
+
+// Times one repository call at info so the request id shows up under the default level,
+// sqlx logs the statements it ran under the same request id
+pub async fn span<T, E, F>(name: &str, fut: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: std::fmt::Debug,
+{
+    let started = Instant::now();
+    let result = fut.await;
+    let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+    match &result {
+        Ok(_) => log::info!("{} ok in {:.1}ms", name, elapsed_ms),
+        Err(e) => log::info!("{} failed in {:.1}ms: {:?}", name, elapsed_ms, e),
+    }
+    result
+}
+
+fn usable_request_id(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?.trim();
+    let usable = !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+    usable.then(|| id.to_string())
+}
+
+// Takes X-Request-Id from the client or makes one up, echoes it back and logs the request
+pub async fn request_context(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(usable_request_id)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    let started = Instant::now();
+
+    REQUEST_ID
+        .scope(id.clone(), async move {
+            let result = next.call(req).await;
+            let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+            match result {
+                Ok(mut res) => {
+                    if let Ok(value) = HeaderValue::from_str(&id) {
+                        res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                    }
+                    log::info!("{} {} {} {:.1}ms", method, path, res.status().as_u16(), elapsed_ms);
+                    Ok(res)
+                }
+                Err(e) => {
+                    log::error!("{} {} failed after {:.1}ms: {}", method, path, elapsed_ms, e);
+                    Err(e)
+                }
+            }
+        })
+        .await
+}
@@ -1,21 +1,70 @@
 use uuid::Uuid;
-use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize, Deserializer};
 use chrono::{DateTime, Utc};
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use sqlx::Type;
+use utoipa::{IntoParams, ToSchema};
+use crate::money::Money;
+use crate::pricing::BPS_DENOMINATOR;
+use crate::validation::{Validate, Validator};
+use crate::search::ItemIndex;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Database model (matches the table structure)
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct ItemDB {
+    pub id: Uuid,
+    pub name: String,
+    pub price_minor: i64,
+    pub currency: String,
+    pub quantity: i32,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub is_active: bool,
+    pub tax_category_id: Option<Uuid>,
+    pub category_id: Option<Uuid>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Item {
     pub id: Uuid,
     pub name: String,
-    pub price: f64,
+    #[serde(deserialize_with = "crate::money::stored")]
+    pub price: Money,
     pub quantity: i32,
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub is_active: bool,
+    #[serde(default)]
+    pub tax_category_id: Option<Uuid>,
+    #[serde(default)]
+    pub category_id: Option<Uuid>,
+    // Only set on soft deleted rows, which are hidden unless asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<ItemDB> for Item {
+    fn from(db: ItemDB) -> Self {
+        Item {
+            id: db.id,
+            name: db.name,
+            price: Money::from_stored(db.price_minor, db.currency),
+            quantity: db.quantity,
+            description: db.description,
+            created_at: db.created_at,
+            updated_at: db.updated_at,
+            is_active: db.is_active,
+            tax_category_id: db.tax_category_id,
+            category_id: db.category_id,
+            deleted_at: db.deleted_at,
+        }
+    }
 }
 
 
@@ -25,25 +74,199 @@ pub struct Item {
 pub struct OrderDB {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub amount: f64,
+    pub subtotal_minor: i64,
+    pub discount_minor: i64,
+    pub tax_minor: i64,
+    pub amount_minor: i64,
+    pub currency: String,
+    pub coupon_code: Option<String>,
     pub status: OrderStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl OrderDB {
+    // Build the API model once the order lines have been loaded
+    pub fn with_items(self, items: Vec<OrderLine>) -> Order {
+        self.into_order(Some(items))
+    }
+
+    // For list pages that were not asked to expand the lines
+    pub fn without_items(self) -> Order {
+        self.into_order(None)
+    }
+
+    fn into_order(self, items: Option<Vec<OrderLine>>) -> Order {
+        Order {
+            id: self.id,
+            user_id: self.user_id,
+            items,
+            subtotal: Money::from_stored(self.subtotal_minor, self.currency.clone()),
+            discount: Money::from_stored(self.discount_minor, self.currency.clone()),
+            tax: Money::from_stored(self.tax_minor, self.currency.clone()),
+            amount: Money::from_stored(self.amount_minor, self.currency),
+            coupon_code: self.coupon_code,
+            status: self.status,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            deleted_at: self.deleted_at,
+        }
+    }
 }
 
 // API model (with items populated)
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[serde(from = "StoredOrder")]
 pub struct Order {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub items: Vec<Item>,  // Populated from order_items join
-    pub amount: f64,
+    // Populated from order_items, None only on list pages that were not asked for ?expand=items
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<OrderLine>>,
+    pub subtotal: Money,
+    pub discount: Money,
+    pub tax: Money,
+    pub amount: Money,  // Total after discount and tax
+    pub coupon_code: Option<String>,
     pub status: OrderStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+// How data.json holds an order. Files written before order lines and minor units
+// have the full items, one per unit ordered, and only a dollar amount.
+#[derive(Deserialize)]
+struct StoredOrder {
+    id: Uuid,
+    user_id: Uuid,
+    #[serde(default)]
+    items: Option<Vec<StoredOrderLine>>,
+    subtotal: Option<Money>,
+    discount: Option<Money>,
+    tax: Option<Money>,
+    #[serde(deserialize_with = "crate::money::stored")]
+    amount: Money,
+    #[serde(default)]
+    coupon_code: Option<String>,
+    status: OrderStatus,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredOrderLine {
+    Line(OrderLine),
+    Item(Item),
+}
+
+impl From<StoredOrder> for Order {
+    fn from(stored: StoredOrder) -> Self {
+        let items = stored.items.map(|stored_lines| {
+            let mut lines: Vec<OrderLine> = Vec::with_capacity(stored_lines.len());
+            for stored_line in stored_lines {
+                match stored_line {
+                    StoredOrderLine::Line(line) => lines.push(line),
+                    StoredOrderLine::Item(item) => match lines.iter_mut().find(|l| l.item_id == item.id) {
+                        Some(line) => line.quantity += 1,
+                        None => lines.push(OrderLine {
+                            item_id: item.id,
+                            name: item.name,
+                            quantity: 1,
+                            unit_price: item.price,
+                            tax_rate_bps: 0,
+                        }),
+                    },
+                }
+            }
+            lines
+        });
+        let currency = stored.amount.currency.clone();
+        Order {
+            id: stored.id,
+            user_id: stored.user_id,
+            items,
+            // Old orders had no coupons or tax, the amount was the subtotal
+            subtotal: stored.subtotal.unwrap_or_else(|| stored.amount.clone()),
+            discount: stored.discount.unwrap_or_else(|| Money::zero(&currency)),
+            tax: stored.tax.unwrap_or_else(|| Money::zero(&currency)),
+            amount: stored.amount,
+            coupon_code: stored.coupon_code,
+            status: stored.status,
+            created_at: stored.created_at,
+            updated_at: stored.updated_at,
+            deleted_at: stored.deleted_at,
+        }
+    }
+}
+
+impl Order {
+    // The lines when they were loaded, empty otherwise
+    pub fn lines(&self) -> &[OrderLine] {
+        self.items.as_deref().unwrap_or_default()
+    }
+}
+
+// Order with everything that happened to it after checkout
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct OrderDetails {
+    #[serde(flatten)]
+    pub order: Order,
+    pub shipment: Option<Shipment>,
+}
+
+// Carrier hand-off for an order, delivered_at is set once the carrier confirms delivery
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct Shipment {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub carrier: String,
+    pub tracking_number: String,
+    pub shipped_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Database model (matches the table structure)
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct OrderLineDB {
+    pub item_id: Uuid,
+    pub name: String,
+    pub quantity: i32,
+    pub unit_price_minor: i64,
+    pub currency: String,
+    pub tax_rate_bps: i32,
+}
+
+// One line of an order, name and unit_price are snapshots of the item when the line was written
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct OrderLine {
+    pub item_id: Uuid,
+    pub name: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+    pub tax_rate_bps: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq)]
+impl From<OrderLineDB> for OrderLine {
+    fn from(db: OrderLineDB) -> Self {
+        OrderLine {
+            item_id: db.item_id,
+            name: db.name,
+            quantity: db.quantity,
+            unit_price: Money::from_stored(db.unit_price_minor, db.currency),
+            tax_rate_bps: db.tax_rate_bps,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq, ToSchema)]
 #[sqlx(type_name = "order_status", rename_all = "PascalCase")]
 pub enum OrderStatus {
     Pending,
@@ -53,6 +276,156 @@ pub enum OrderStatus {
     Delivered,
 }
 
+impl OrderStatus {
+    // Allowed transitions: Pending -> Paid/Cancelled, Paid -> Shipping/Cancelled, Shipping -> Delivered
+    pub fn can_transition_to(&self, next: &OrderStatus) -> bool {
+        matches!(
+            (self, next),
+            (OrderStatus::Pending, OrderStatus::Paid)
+                | (OrderStatus::Pending, OrderStatus::Cancelled)
+                | (OrderStatus::Paid, OrderStatus::Shipping)
+                | (OrderStatus::Paid, OrderStatus::Cancelled)
+                | (OrderStatus::Shipping, OrderStatus::Delivered)
+        )
+    }
+
+    // Setting the same status again is a no-op, anything else has to be in the table above
+    pub fn check_transition(&self, next: &OrderStatus) -> Result<(), String> {
+        if self == next || self.can_transition_to(next) {
+            Ok(())
+        } else {
+            Err(format!("Cannot change order status from {:?} to {:?}", self, next))
+        }
+    }
+
+    // What an order update may do on either backend. Paid, Shipping and Delivered need a payment
+    // or shipment behind them, so they are only reached through the /db/orders endpoints that record one.
+    pub fn check_update(&self, next: &OrderStatus, order_id: Uuid) -> Result<(), String> {
+        self.check_transition(next)?;
+        if self == next {
+            return Ok(());
+        }
+        match next {
+            OrderStatus::Paid => Err(format!("Orders are marked Paid by POST /db/orders/{}/pay", order_id)),
+            OrderStatus::Shipping => Err(format!("Orders are moved to Shipping by POST /db/orders/{}/shipment", order_id)),
+            OrderStatus::Delivered => Err(format!(
+                "Orders are marked Delivered by POST /db/orders/{}/shipment/delivered", order_id
+            )),
+            OrderStatus::Pending | OrderStatus::Cancelled => Ok(()),
+        }
+    }
+
+    // Stock has already left (or come back) for anything past Pending
+    pub fn check_items_editable(&self) -> Result<(), String> {
+        if *self == OrderStatus::Pending {
+            Ok(())
+        } else {
+            Err(format!("Items can only be changed while the order is Pending, it is {:?}", self))
+        }
+    }
+}
+
+// One row per status change, oldest first
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct OrderStatusHistory {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub from_status: OrderStatus,
+    pub to_status: OrderStatus,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "stock_movement_reason", rename_all = "PascalCase")]
+pub enum StockMovementReason {
+    Initial,
+    Adjustment,
+    OrderPlaced,
+    OrderUpdated,
+    OrderCancelled,
+    OrderDeleted,
+    OrderRestored,
+}
+
+// One row of the inventory ledger, quantity_change is negative when stock leaves
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct StockMovement {
+    pub id: Uuid,
+    pub item_id: Uuid,
+    pub order_id: Option<Uuid>,
+    pub quantity_change: i32,
+    pub reason: StockMovementReason,
+    pub created_at: DateTime<Utc>,
+}
+
+// Reported back when an order asks for more than is in stock
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct StockShortage {
+    pub item_id: Uuid,
+    pub requested: i32,
+    pub available: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "payment_kind", rename_all = "PascalCase")]
+pub enum PaymentKind {
+    Capture,
+    Refund,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "payment_status", rename_all = "PascalCase")]
+pub enum PaymentStatus {
+    // Written before a capture goes to the provider, settled once it answers
+    Pending,
+    Succeeded,
+    Declined,
+}
+
+// One call to the payment provider, provider_ref is only set when it went through
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PaymentDB {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub kind: PaymentKind,
+    pub status: PaymentStatus,
+    pub amount_minor: i64,
+    pub currency: String,
+    pub provider: String,
+    pub provider_ref: Option<String>,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Payment {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub kind: PaymentKind,
+    pub status: PaymentStatus,
+    pub amount: Money,
+    pub provider: String,
+    pub provider_ref: Option<String>,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<PaymentDB> for Payment {
+    fn from(db: PaymentDB) -> Self {
+        Payment {
+            id: db.id,
+            order_id: db.order_id,
+            kind: db.kind,
+            status: db.status,
+            amount: Money::from_stored(db.amount_minor, db.currency),
+            provider: db.provider,
+            provider_ref: db.provider_ref,
+            failure_reason: db.failure_reason,
+            created_at: db.created_at,
+        }
+    }
+}
+
 // Database model (matches the table structure)
 #[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
 pub struct UserDB {
@@ -62,9 +435,10 @@ pub struct UserDB {
     pub is_active: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct User {
     pub id: Uuid,
     pub name: String,
@@ -73,6 +447,8 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub is_active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 
@@ -81,6 +457,12 @@ pub struct AppState {
     pub users: HashMap<Uuid, User>,
     pub orders: HashMap<Uuid, Order>,
     pub items: HashMap<Uuid, Item>,
+    // Rebuilt from items on load, the item handlers keep it in step afterwards
+    #[serde(skip)]
+    pub item_index: ItemIndex,
+    // Where write_to_file persists the state, set on load
+    #[serde(skip)]
+    pub data_file: PathBuf,
 }
 
 pub type SharedState = Arc<Mutex<AppState>>;
@@ -88,60 +470,607 @@ pub type SharedState = Arc<Mutex<AppState>>;
 
 
 
+// For fields an update can clear: left out is None, null is Some(None) and a value is Some(Some(value))
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// Longest names, emails and codes the VARCHAR columns hold
+const NAME_MAX_LEN: usize = 255;
+const CODE_MAX_LEN: usize = 64;
+// Largest quantity one order or cart line may hold, well clear of i32 overflow
+pub const MAX_LINE_QUANTITY: i32 = 1_000_000;
+
 // to create and update an item
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateItem {
     pub name: String,
-    pub price: f64,
+    pub price: Money,
     pub quantity: i32,
     pub description: Option<String>,
+    #[serde(default)]
+    pub tax_category_id: Option<Uuid>,
+    #[serde(default)]
+    pub category_id: Option<Uuid>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Validate for CreateItem {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name).max_len("name", &self.name, NAME_MAX_LEN);
+        v.min("price.amount_minor", self.price.amount_minor, 0);
+        v.min("quantity", self.quantity as i64, 0);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateItem {
     pub name: Option<String>,
-    pub price: Option<f64>,
+    pub price: Option<Money>,
     pub quantity: Option<i32>,
     pub description: Option<String>,
     pub is_active: Option<bool>,
+    // Left out keeps the tax category, null leaves the item untaxed
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Uuid>)]
+    pub tax_category_id: Option<Option<Uuid>>,
+    // Left out keeps the category, null takes the item out of it
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Uuid>)]
+    pub category_id: Option<Option<Uuid>>,
+}
+
+impl Validate for UpdateItem {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(name) = &self.name {
+            v.not_blank("name", name).max_len("name", name, NAME_MAX_LEN);
+        }
+        if let Some(price) = &self.price {
+            v.min("price.amount_minor", price.amount_minor, 0);
+        }
+        if let Some(quantity) = self.quantity {
+            v.min("quantity", quantity as i64, 0);
+        }
+    }
 }
 
 // to create an update an order
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CreateOrderLine {
+    pub item_id: Uuid,
+    pub quantity: i32,
+}
+
+impl Validate for CreateOrderLine {
+    fn validate(&self, v: &mut Validator) {
+        v.range("quantity", self.quantity as i64, 1, MAX_LINE_QUANTITY as i64);
+    }
+}
+
+impl CreateOrderLine {
+    // Folds repeated item ids into a single line and rejects non-positive or too large quantities
+    pub fn merge(lines: &[CreateOrderLine]) -> Result<Vec<CreateOrderLine>, String> {
+        let mut merged: Vec<CreateOrderLine> = Vec::with_capacity(lines.len());
+        for line in lines {
+            if line.quantity <= 0 {
+                return Err(format!("Quantity for item {} must be greater than 0", line.item_id));
+            }
+            if line.quantity > MAX_LINE_QUANTITY {
+                return Err(format!("Quantity for item {} is too large", line.item_id));
+            }
+            match merged.iter_mut().find(|l| l.item_id == line.item_id) {
+                Some(existing) => {
+                    existing.quantity = existing
+                        .quantity
+                        .checked_add(line.quantity)
+                        .filter(|quantity| *quantity <= MAX_LINE_QUANTITY)
+                        .ok_or_else(|| format!("Quantity for item {} is too large", line.item_id))?;
+                }
+                None => merged.push(line.clone()),
+            }
+        }
+        Ok(merged)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateOrder {
     pub user_id: Uuid,
-    pub item_ids: Vec<Uuid>,
+    pub items: Vec<CreateOrderLine>,
+    #[serde(default)]
+    pub coupon_code: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Validate for CreateOrder {
+    fn validate(&self, v: &mut Validator) {
+        v.not_empty("items", &self.items).each("items", &self.items);
+        if let Some(code) = &self.coupon_code {
+            v.not_blank("coupon_code", code).max_len("coupon_code", code, CODE_MAX_LEN);
+        }
+    }
+}
+
+// The coupon is fixed when the order is created, it can't be changed or removed here.
+// New items are repriced with the coupon the order already has.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateOrder {
-    pub item_ids: Option<Vec<Uuid>>,
+    pub items: Option<Vec<CreateOrderLine>>,
     pub status: Option<OrderStatus>,
 }
 
+impl Validate for UpdateOrder {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(items) = &self.items {
+            v.not_empty("items", items).each("items", items);
+        }
+    }
+}
 
 
-#[derive(Debug, Serialize, Deserialize)]
+
+// Prices a cart the same way create_order would, without writing anything
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct QuoteRequest {
+    pub items: Vec<CreateOrderLine>,
+    #[serde(default)]
+    pub coupon_code: Option<String>,
+}
+
+impl Validate for QuoteRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.not_empty("items", &self.items).each("items", &self.items);
+        if let Some(code) = &self.coupon_code {
+            v.not_blank("coupon_code", code).max_len("coupon_code", code, CODE_MAX_LEN);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PriceBreakdown {
+    pub subtotal: Money,
+    pub discount: Money,
+    pub tax: Money,
+    pub total: Money,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Quote {
+    pub items: Vec<OrderLine>,
+    pub coupon_code: Option<String>,
+    #[serde(flatten)]
+    pub breakdown: PriceBreakdown,
+}
+
+// One cart line joined with the current item, nothing is snapshotted until checkout
+#[derive(Debug, sqlx::FromRow)]
+pub struct CartLineDB {
+    pub item_id: Uuid,
+    pub name: String,
+    pub quantity: i32,
+    pub price_minor: i64,
+    pub currency: String,
+    pub is_active: bool,
+    pub available: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CartLine {
+    pub item_id: Uuid,
+    pub name: String,
+    pub quantity: i32,
+    pub unit_price: Money,
+    pub is_active: bool,
+    pub available: i32,
+}
+
+impl From<CartLineDB> for CartLine {
+    fn from(db: CartLineDB) -> Self {
+        CartLine {
+            item_id: db.item_id,
+            name: db.name,
+            quantity: db.quantity,
+            unit_price: Money::from_stored(db.price_minor, db.currency),
+            is_active: db.is_active,
+            available: db.available,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Cart {
+    pub user_id: Uuid,
+    pub items: Vec<CartLine>,
+}
+
+// Adding an item that is already in the cart adds to its quantity
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddCartItem {
+    pub item_id: Uuid,
+    pub quantity: i32,
+}
+
+impl Validate for AddCartItem {
+    fn validate(&self, v: &mut Validator) {
+        v.range("quantity", self.quantity as i64, 1, MAX_LINE_QUANTITY as i64);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct Checkout {
+    #[serde(default)]
+    pub coupon_code: Option<String>,
+}
+
+impl Validate for Checkout {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(code) = &self.coupon_code {
+            v.not_blank("coupon_code", code).max_len("coupon_code", code, CODE_MAX_LEN);
+        }
+    }
+}
+
+// payment_token comes from the provider's client side checkout
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PayOrder {
+    pub payment_token: String,
+}
+
+impl Validate for PayOrder {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("payment_token", &self.payment_token);
+    }
+}
+
+// shipped_at and delivered_at default to now
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateShipment {
+    pub carrier: String,
+    pub tracking_number: String,
+    #[serde(default)]
+    pub shipped_at: Option<DateTime<Utc>>,
+}
+
+impl Validate for CreateShipment {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("carrier", &self.carrier).max_len("carrier", &self.carrier, CODE_MAX_LEN);
+        v.not_blank("tracking_number", &self.tracking_number)
+            .max_len("tracking_number", &self.tracking_number, NAME_MAX_LEN);
+        if let Some(shipped_at) = self.shipped_at
+            && shipped_at > Utc::now()
+        {
+            v.fail("shipped_at", "must not be in the future");
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct DeliverShipment {
+    #[serde(default)]
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+impl Validate for DeliverShipment {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(delivered_at) = self.delivered_at
+            && delivered_at > Utc::now()
+        {
+            v.fail("delivered_at", "must not be in the future");
+        }
+    }
+}
+
+// Tax rate applied to every item in the category, in basis points (10000 = 100%)
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct TaxCategory {
+    pub id: Uuid,
+    pub name: String,
+    pub rate_bps: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateTaxCategory {
+    pub name: String,
+    pub rate_bps: i32,
+}
+
+impl Validate for CreateTaxCategory {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name).max_len("name", &self.name, NAME_MAX_LEN);
+        v.range("rate_bps", self.rate_bps as i64, 0, BPS_DENOMINATOR);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "coupon_kind", rename_all = "PascalCase")]
+pub enum CouponKind {
+    Percentage,
+    Fixed,
+}
+
+// Order level discount, percent_bps is set for Percentage coupons and amount_minor/currency for Fixed ones
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct Coupon {
+    pub id: Uuid,
+    pub code: String,
+    pub kind: CouponKind,
+    pub percent_bps: Option<i32>,
+    pub amount_minor: Option<i64>,
+    pub currency: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub usage_limit: Option<i32>,
+    pub times_used: i32,
+    pub is_active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateCoupon {
+    pub code: String,
+    pub kind: CouponKind,
+    #[serde(default)]
+    pub percent_bps: Option<i32>,
+    #[serde(default)]
+    pub amount: Option<Money>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub usage_limit: Option<i32>,
+}
+
+// Which of percent_bps and amount is required depends on kind, the repository checks that
+impl Validate for CreateCoupon {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("code", &self.code).max_len("code", &self.code, CODE_MAX_LEN);
+        if let Some(bps) = self.percent_bps {
+            v.range("percent_bps", bps as i64, 1, BPS_DENOMINATOR);
+        }
+        if let Some(amount) = &self.amount {
+            v.min("amount.amount_minor", amount.amount_minor, 1);
+        }
+        if let Some(limit) = self.usage_limit {
+            v.min("usage_limit", limit as i64, 1);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateUser {
     pub name: String,
     pub email: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Validate for CreateUser {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name).max_len("name", &self.name, NAME_MAX_LEN);
+        v.email("email", &self.email).max_len("email", &self.email, NAME_MAX_LEN);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateUser {
     pub name: Option<String>,
     pub email: Option<String>,
     pub is_active: Option<bool>,
 }
 
+impl Validate for UpdateUser {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(name) = &self.name {
+            v.not_blank("name", name).max_len("name", name, NAME_MAX_LEN);
+        }
+        if let Some(email) = &self.email {
+            v.email("email", email).max_len("email", email, NAME_MAX_LEN);
+        }
+    }
+}
+
+// Most ids one bulk delete may name, the whole batch runs in one transaction
+const MAX_BULK_DELETE_IDS: usize = 1000;
+
+// Soft deletes every listed row that exists, the rest are reported as not found
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BulkDelete {
+    pub ids: Vec<Uuid>,
+}
+
+impl Validate for BulkDelete {
+    fn validate(&self, v: &mut Validator) {
+        v.not_empty("ids", &self.ids);
+        if self.ids.len() > MAX_BULK_DELETE_IDS {
+            v.fail("ids", format!("must have at most {} ids", MAX_BULK_DELETE_IDS));
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteOutcome {
+    Deleted,
+    NotFound,
+}
+
+// What happened to one id of a bulk delete
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct BulkDeleteResult {
+    pub id: Uuid,
+    pub outcome: DeleteOutcome,
+}
+
+impl BulkDeleteResult {
+    // One result per requested id, in request order with repeats dropped
+    pub fn for_ids(ids: &[Uuid], deleted: &[Uuid]) -> Vec<BulkDeleteResult> {
+        let deleted: HashSet<&Uuid> = deleted.iter().collect();
+        let mut seen: HashSet<&Uuid> = HashSet::with_capacity(ids.len());
+        ids.iter()
+            .filter(|id| seen.insert(*id))
+            .map(|id| {
+                let outcome = if deleted.contains(id) { DeleteOutcome::Deleted } else { DeleteOutcome::NotFound };
+                BulkDeleteResult { id: *id, outcome }
+            })
+            .collect()
+    }
+}
+
+// ?include_deleted=true also returns soft deleted rows, meant for admins
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeletedQuery {
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+// Query parameters shared by every /db list endpoint.
+// cursor is the next_cursor of the previous page and only makes sense with the same sort and filters.
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListParams {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    pub direction: Option<SortDirection>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+// Prices are compared in minor units, q matches anywhere in the name ignoring case.
+// category_id includes every category below it, tags is a comma separated list the item must all carry.
+#[derive(Debug, Deserialize, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemFilter {
+    pub min_price: Option<i64>,
+    pub max_price: Option<i64>,
+    pub q: Option<String>,
+    pub is_active: Option<bool>,
+    pub category_id: Option<Uuid>,
+    pub tags: Option<String>,
+}
+
+impl ItemFilter {
+    pub fn tag_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tags
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(normalize_tag)
+            .filter(|name| !name.is_empty())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+// ?q= is free text, words are matched after stemming in Postgres and as whole words in the JSON index
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    // The trimmed query and the limit, or why the request is invalid
+    pub fn validate(&self) -> Result<(&str, usize), String> {
+        let q = self.q.trim();
+        if q.is_empty() {
+            return Err("q must not be empty".to_string());
+        }
+        let limit = self.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if !(1..=MAX_SEARCH_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_SEARCH_LIMIT));
+        }
+        Ok((q, limit))
+    }
+}
+
+// A search result, snippet is HTML escaped with the matching words wrapped in <mark></mark>
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemSearchHit {
+    #[serde(flatten)]
+    pub item: Item,
+    pub rank: f32,
+    pub snippet: String,
+}
+
+// Amounts are compared in minor units, status is a comma separated set like Pending,Paid
+#[derive(Debug, Deserialize, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OrderFilter {
+    pub min_amount: Option<i64>,
+    pub max_amount: Option<i64>,
+    pub status: Option<String>,
+    pub user_id: Option<Uuid>,
+}
+
+impl OrderFilter {
+    // Validated status names, empty when no status filter was given
+    pub fn statuses(&self) -> Result<Vec<OrderStatus>, String> {
+        let Some(ref status) = self.status else {
+            return Ok(Vec::new());
+        };
+        status
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                serde_json::from_value(serde_json::Value::String(name.to_string()))
+                    .map_err(|_| format!("Unknown order status '{}'", name))
+            })
+            .collect()
+    }
+}
+
+// ?expand=items loads order lines on list endpoints, a comma separated list for future expansions
+#[derive(Debug, Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExpandQuery {
+    pub expand: Option<String>,
+}
+
+impl ExpandQuery {
+    pub fn items(&self) -> bool {
+        self.expand
+            .as_deref()
+            .is_some_and(|expand| expand.split(',').any(|part| part.trim() == "items"))
+    }
+}
+
+// One page of a list endpoint, next_cursor is None on the last page
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
 // Helper struct for status query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct StatusQuery {
     pub status: OrderStatus,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 #[serde(rename_all = "PascalCase")]
 pub enum JobStatus {
     Pending,
@@ -150,7 +1079,7 @@ pub enum JobStatus {
     Failed,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum JobPriority {
     Low,
@@ -158,7 +1087,7 @@ pub enum JobPriority {
     High,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Job {
     pub job_id: u64,
     pub status: JobStatus,
@@ -172,9 +1101,11 @@ pub struct Job {
     pub updated_at: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expires_at: Option<DateTime<Utc>>,  // Bonus feature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateJob {
     pub payload: String,
     #[serde(default)]
@@ -185,6 +1116,14 @@ pub struct CreateJob {
     pub ttl_seconds: Option<i64>,  // Time to live in seconds
 }
 
+impl Validate for CreateJob {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(ttl) = self.ttl_seconds {
+            v.min("ttl_seconds", ttl, 1);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct JobQueue {
     pub jobs: Arc<Mutex<Vec<Job>>>,
@@ -192,7 +1131,312 @@ pub struct JobQueue {
     pub max_queue_size: usize,  // Bonus feature
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct StatusJobQuery {
     pub status: JobStatus,
 }
+
+// Categories form a tree, items point at one category
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct Category {
+    pub id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CategoryNode {
+    #[serde(flatten)]
+    pub category: Category,
+    #[schema(no_recursion)]
+    pub children: Vec<CategoryNode>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateCategory {
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+}
+
+impl Validate for CreateCategory {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name).max_len("name", &self.name, NAME_MAX_LEN);
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateCategory {
+    pub name: Option<String>,
+    // Left out keeps the parent, null moves the category to the top level
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Uuid>)]
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+impl Validate for UpdateCategory {
+    fn validate(&self, v: &mut Validator) {
+        if let Some(name) = &self.name {
+            v.not_blank("name", name).max_len("name", name, NAME_MAX_LEN);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct Tag {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Used for both creating and renaming a tag
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TagRequest {
+    pub name: String,
+}
+
+impl Validate for TagRequest {
+    fn validate(&self, v: &mut Validator) {
+        v.not_blank("name", &self.name).max_len("name", &self.name, CODE_MAX_LEN);
+        if self.name.contains(',') {
+            v.fail("name", "must not contain commas");
+        }
+    }
+}
+
+// Most tags one item can carry
+const MAX_ITEM_TAGS: usize = 100;
+
+// Replaces the whole tag set of an item, an empty list removes them all
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetItemTags {
+    pub tag_ids: Vec<Uuid>,
+}
+
+impl Validate for SetItemTags {
+    fn validate(&self, v: &mut Validator) {
+        if self.tag_ids.len() > MAX_ITEM_TAGS {
+            v.fail("tag_ids", format!("must have at most {} tags", MAX_ITEM_TAGS));
+        }
+    }
+}
+
+// Tags are matched case insensitively, so they are stored trimmed and lower case
+pub fn normalize_tag(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "import_kind", rename_all = "PascalCase")]
+#[serde(rename_all = "lowercase")]
+pub enum ImportKind {
+    Users,
+    Items,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "import_format", rename_all = "PascalCase")]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Type, PartialEq, Eq, ToSchema)]
+#[sqlx(type_name = "import_status", rename_all = "PascalCase")]
+pub enum ImportStatus {
+    Pending,
+    Completed,
+    Failed,
+}
+
+// ?format=csv|ndjson, imports fall back to the Content-Type when it is missing
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FormatQuery {
+    pub format: Option<ImportFormat>,
+}
+
+#[derive(Debug, Serialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct ImportDB {
+    pub id: Uuid,
+    pub kind: ImportKind,
+    pub format: ImportFormat,
+    pub status: ImportStatus,
+    pub job_id: Option<i64>,
+    pub total_rows: i32,
+    pub imported_rows: i32,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// Rows are numbered from 1 without the CSV header line
+#[derive(Debug, Serialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct ImportRowError {
+    pub row_number: i32,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportReport {
+    #[serde(flatten)]
+    pub import: ImportDB,
+    pub errors: Vec<ImportRowError>,
+}
+
+// One user in an import file, extra columns such as id are ignored
+#[derive(Debug, Deserialize)]
+pub struct UserImportRow {
+    pub name: String,
+    pub email: String,
+    #[serde(default)]
+    pub is_active: Option<bool>,
+}
+
+// One item in an import file, the same flat columns the item export writes
+#[derive(Debug, Deserialize)]
+pub struct ItemImportRow {
+    pub name: String,
+    pub price_minor: i64,
+    pub currency: String,
+    pub quantity: i32,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub is_active: Option<bool>,
+    #[serde(default)]
+    pub tax_category_id: Option<Uuid>,
+    #[serde(default)]
+    pub category_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Up,
+    Down,
+    // Not used by this server, never fails readiness
+    Disabled,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComponentHealth {
+    pub status: ComponentStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadinessChecks {
+    pub database: ComponentHealth,
+    pub data_file: ComponentHealth,
+    pub workers: ComponentHealth,
+    pub queue: ComponentHealth,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_status_transitions() {
+        use OrderStatus::*;
+        let all = [Pending, Paid, Cancelled, Shipping, Delivered];
+        let allowed = [
+            (Pending, Paid),
+            (Pending, Cancelled),
+            (Paid, Shipping),
+            (Paid, Cancelled),
+            (Shipping, Delivered),
+        ];
+        for from in &all {
+            for to in &all {
+                let expected = allowed.contains(&(from.clone(), to.clone()));
+                assert_eq!(from.can_transition_to(to), expected, "{:?} -> {:?}", from, to);
+                // Setting the same status again is accepted but is not a transition
+                let accepted = expected || from == to;
+                assert_eq!(from.check_transition(to).is_ok(), accepted, "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn order_updates_only_cancel_or_keep_the_status() {
+        use OrderStatus::*;
+        let id = Uuid::new_v4();
+        assert!(Pending.check_update(&Cancelled, id).is_ok());
+        assert!(Paid.check_update(&Cancelled, id).is_ok());
+        assert!(Shipping.check_update(&Shipping, id).is_ok());
+        assert!(Pending.check_update(&Paid, id).unwrap_err().contains("/pay"));
+        assert!(Paid.check_update(&Shipping, id).unwrap_err().contains("/shipment"));
+        assert!(Shipping.check_update(&Delivered, id).unwrap_err().contains("/shipment/delivered"));
+        assert!(Pending.check_update(&Delivered, id).unwrap_err().starts_with("Cannot change"));
+
+        assert!(Pending.check_items_editable().is_ok());
+        for status in [Paid, Cancelled, Shipping, Delivered] {
+            assert!(status.check_items_editable().is_err(), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn update_item_tells_null_from_left_out() {
+        let id = Uuid::new_v4();
+        let update = |body: serde_json::Value| serde_json::from_value::<UpdateItem>(body).unwrap();
+
+        let left_out = update(serde_json::json!({ "name": "Widget" }));
+        assert_eq!((left_out.tax_category_id, left_out.category_id), (None, None));
+
+        let cleared = update(serde_json::json!({ "tax_category_id": null, "category_id": null }));
+        assert_eq!((cleared.tax_category_id, cleared.category_id), (Some(None), Some(None)));
+
+        let set = update(serde_json::json!({ "tax_category_id": id, "category_id": id }));
+        assert_eq!((set.tax_category_id, set.category_id), (Some(Some(id)), Some(Some(id))));
+    }
+
+    #[test]
+    fn merge_rejects_quantities_that_overflow() {
+        let item_id = Uuid::new_v4();
+        let line = |quantity| CreateOrderLine { item_id, quantity };
+
+        let merged = CreateOrderLine::merge(&[line(2), line(3)]).unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].quantity, 5);
+
+        assert!(CreateOrderLine::merge(&[line(MAX_LINE_QUANTITY), line(1)]).is_err());
+        assert!(CreateOrderLine::merge(&[line(i32::MAX), line(i32::MAX)]).is_err());
+        assert!(CreateOrderLine::merge(&[line(0)]).is_err());
+    }
+
+    #[test]
+    fn shipment_timestamps_are_not_in_the_future() {
+        let tomorrow = Utc::now() + chrono::Duration::days(1);
+        let yesterday = Utc::now() - chrono::Duration::days(1);
+        let shipment = |shipped_at| CreateShipment {
+            carrier: "UPS".to_string(),
+            tracking_number: "1Z999".to_string(),
+            shipped_at,
+        };
+
+        assert!(Validator::check(&shipment(None)).is_ok());
+        assert!(Validator::check(&shipment(Some(yesterday))).is_ok());
+        assert!(Validator::check(&shipment(Some(tomorrow))).is_err());
+        assert!(Validator::check(&DeliverShipment { delivered_at: Some(yesterday) }).is_ok());
+        assert!(Validator::check(&DeliverShipment { delivered_at: Some(tomorrow) }).is_err());
+    }
+
+    #[test]
+    fn bulk_delete_results_follow_request_order() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let results = BulkDeleteResult::for_ids(&[b, a, b, c], &[a, b]);
+        let outcomes: Vec<(Uuid, DeleteOutcome)> = results.into_iter().map(|r| (r.id, r.outcome)).collect();
+        assert_eq!(outcomes, vec![
+            (b, DeleteOutcome::Deleted),
+            (a, DeleteOutcome::Deleted),
+            (c, DeleteOutcome::NotFound),
+        ]);
+    }
+}
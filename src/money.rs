@@ -0,0 +1,172 @@
+use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
+
+// Currency used for an order that has no lines to take one from
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+// Exact money amount: integer minor units (cents for USD) plus an ISO 4217 currency code
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+#[serde(try_from = "MoneyParts")]
+pub struct Money {
+    pub amount_minor: i64,
+    pub currency: String,
+}
+
+// Wire shape of Money, checked by TryFrom before a Money is built
+#[derive(Deserialize)]
+struct MoneyParts {
+    amount_minor: i64,
+    currency: String,
+}
+
+impl TryFrom<MoneyParts> for Money {
+    type Error = String;
+
+    fn try_from(parts: MoneyParts) -> Result<Self, Self::Error> {
+        Money::new(parts.amount_minor, &parts.currency)
+    }
+}
+
+// data.json files written before minor units hold a plain number of dollars,
+// read as USD cents the way the money_minor_units migration converts the old columns
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredMoney {
+    Money(Money),
+    Dollars(f64),
+}
+
+// deserialize_with for Money fields of the JSON store, request bodies keep the strict shape
+pub fn stored<'de, D>(deserializer: D) -> Result<Money, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match StoredMoney::deserialize(deserializer)? {
+        StoredMoney::Money(money) => Ok(money),
+        StoredMoney::Dollars(dollars) => Money::from_dollars(dollars).map_err(serde::de::Error::custom),
+    }
+}
+
+impl Money {
+    // Validates the currency code, lowercase codes are accepted and normalised
+    pub fn new(amount_minor: i64, currency: &str) -> Result<Self, String> {
+        let currency = currency.trim().to_ascii_uppercase();
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(format!("Invalid currency code '{}', expected a 3 letter ISO 4217 code", currency));
+        }
+        Ok(Self { amount_minor, currency })
+    }
+
+    // For values read back from storage, where the currency was validated on the way in
+    pub fn from_stored(amount_minor: i64, currency: String) -> Self {
+        Self { amount_minor, currency }
+    }
+
+    fn from_dollars(dollars: f64) -> Result<Self, String> {
+        let cents = (dollars * 100.0).round();
+        if !cents.is_finite() || cents < i64::MIN as f64 || cents >= i64::MAX as f64 {
+            return Err(format!("Amount {} is out of range", dollars));
+        }
+        Ok(Self { amount_minor: cents as i64, currency: DEFAULT_CURRENCY.to_string() })
+    }
+
+    pub fn zero(currency: &str) -> Self {
+        Self { amount_minor: 0, currency: currency.to_string() }
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, String> {
+        if self.currency != other.currency {
+            return Err(format!(
+                "Cannot mix currencies {} and {} in one order",
+                self.currency, other.currency
+            ));
+        }
+        let amount_minor = self.amount_minor
+            .checked_add(other.amount_minor)
+            .ok_or_else(|| "Amount overflow".to_string())?;
+        Ok(Money { amount_minor, currency: self.currency.clone() })
+    }
+
+    pub fn checked_mul(&self, quantity: i32) -> Result<Money, String> {
+        let amount_minor = self.amount_minor
+            .checked_mul(quantity as i64)
+            .ok_or_else(|| "Amount overflow".to_string())?;
+        Ok(Money { amount_minor, currency: self.currency.clone() })
+    }
+
+    // Sums unit price * quantity per line, every line has to be in the same currency
+    pub fn total<'a, I>(lines: I) -> Result<Money, String>
+    where
+        I: IntoIterator<Item = (&'a Money, i32)>,
+    {
+        let mut total: Option<Money> = None;
+        for (unit_price, quantity) in lines {
+            let line_total = unit_price.checked_mul(quantity)?;
+            total = Some(match total {
+                Some(sum) => sum.checked_add(&line_total)?,
+                None => line_total,
+            });
+        }
+        Ok(total.unwrap_or_else(|| Money::zero(DEFAULT_CURRENCY)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(amount_minor: i64) -> Money {
+        Money::new(amount_minor, "USD").unwrap()
+    }
+
+    #[test]
+    fn try_from_checks_the_currency() {
+        let money: Money = serde_json::from_str(r#"{"amount_minor": 1999, "currency": "eur"}"#).unwrap();
+        assert_eq!(money, Money::from_stored(1999, "EUR".to_string()));
+
+        for currency in ["", "US", "USDX", "U1D", "€UR"] {
+            let body = serde_json::json!({ "amount_minor": 1, "currency": currency });
+            assert!(serde_json::from_value::<Money>(body).is_err(), "{:?}", currency);
+        }
+    }
+
+    #[test]
+    fn stored_reads_legacy_dollars() {
+        #[derive(Deserialize)]
+        struct Price {
+            #[serde(deserialize_with = "stored")]
+            price: Money,
+        }
+        let price = |body: serde_json::Value| serde_json::from_value::<Price>(body).map(|p| p.price);
+
+        assert_eq!(price(serde_json::json!({ "price": 19.99 })).unwrap(), usd(1999));
+        assert_eq!(price(serde_json::json!({ "price": 0.1 })).unwrap(), usd(10));
+        assert_eq!(price(serde_json::json!({ "price": 5 })).unwrap(), usd(500));
+        assert_eq!(
+            price(serde_json::json!({ "price": { "amount_minor": 250, "currency": "eur" } })).unwrap(),
+            Money::new(250, "EUR").unwrap()
+        );
+        assert!(price(serde_json::json!({ "price": 1e300 })).is_err());
+        assert!(price(serde_json::json!({ "price": "19.99" })).is_err());
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        assert_eq!(usd(150).checked_add(&usd(50)).unwrap(), usd(200));
+        assert_eq!(usd(150).checked_mul(3).unwrap(), usd(450));
+        assert!(usd(i64::MAX).checked_add(&usd(1)).is_err());
+        assert!(usd(i64::MAX).checked_mul(2).is_err());
+        assert!(usd(1).checked_add(&Money::new(1, "EUR").unwrap()).is_err());
+    }
+
+    #[test]
+    fn total_rejects_mixed_currencies() {
+        let (a, b) = (usd(100), usd(250));
+        assert_eq!(Money::total([(&a, 2), (&b, 1)]).unwrap(), usd(450));
+        assert_eq!(Money::total(std::iter::empty()).unwrap(), Money::zero(DEFAULT_CURRENCY));
+
+        let eur = Money::new(100, "EUR").unwrap();
+        let err = Money::total([(&a, 1), (&eur, 1)]).unwrap_err();
+        assert!(err.contains("USD") && err.contains("EUR"), "{}", err);
+    }
+}
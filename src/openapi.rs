@@ -0,0 +1,269 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::openapi::{OpenApi as OpenApiDoc, Ref, RefOr, Response, ResponseBuilder};
+use utoipa::openapi::content::ContentBuilder;
+use utoipa::{Modify, OpenApi, ToSchema};
+use crate::handlers::{admin_handler, health_handler, item_handler, order_handler, user_handler};
+use crate::jobs::handler;
+use crate::models::{ImportFormat, ImportStatus, JobStatus, OrderStatus, ReadinessChecks, SortDirection, StockShortage};
+use crate::repository::repo_handler;
+use crate::response::ErrorCode;
+use crate::validation::FieldError;
+
+// Where the bundled Swagger UI is mounted, it reads the spec from /openapi.json
+pub const DOCS_PATH: &str = "/docs";
+
+// The bodies below are built by ApiResponse, they only exist to describe them.
+// The spec shows the default envelope, clients on X-Response-Format: legacy get the bare data instead.
+
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct Envelope<T> {
+    pub data: T,
+    // Only some writes set it
+    pub message: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct ErrorEnvelope {
+    pub error: ErrorDetail,
+    pub request_id: Option<String>,
+}
+
+// details is an object: fields for validation_failed, items for insufficient_stock,
+// reason for payment_declined and status plus checks for not_ready
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail {
+    pub code: ErrorCode,
+    pub message: String,
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+}
+
+// Entries of details.fields when code is validation_failed
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct ValidationDetails {
+    pub fields: Vec<FieldError>,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct JobCreated {
+    pub job_id: u64,
+    pub status: JobStatus,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct ImportAccepted {
+    pub import_id: uuid::Uuid,
+    pub job_id: u64,
+    pub status: ImportStatus,
+}
+
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct LiveStatus {
+    pub status: String,
+}
+
+// status is ready, not_ready or shutting_down
+#[allow(dead_code)]
+#[derive(Serialize, ToSchema)]
+pub struct ReadyStatus {
+    pub status: String,
+    pub checks: ReadinessChecks,
+}
+
+// Every /db route answers 503 while Postgres is not configured or not reachable
+struct DatabaseUnavailable;
+
+impl Modify for DatabaseUnavailable {
+    fn modify(&self, openapi: &mut OpenApiDoc) {
+        let response: RefOr<Response> = ResponseBuilder::new()
+            .description("Postgres is not configured or not reachable, retry after the Retry-After header")
+            .content("application/json", ContentBuilder::new().schema(Some(Ref::from_schema_name(ErrorEnvelope::name()))).build())
+            .build()
+            .into();
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if !path.starts_with("/db/") {
+                continue;
+            }
+            let operations = [
+                &mut item.get, &mut item.put, &mut item.post, &mut item.delete, &mut item.patch,
+            ];
+            for operation in operations.into_iter().flatten() {
+                operation.responses.responses.insert("503".to_string(), response.clone());
+            }
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "heartbeetle-task", description = "Users, items and orders over a JSON file store (/) and Postgres (/db), plus a background job queue. Bodies come in an envelope with data or error and request_id, send X-Response-Format: legacy for the older bare bodies"),
+    paths(
+        openapi_json,
+        user_handler::create_user, user_handler::list_users, user_handler::get_user,
+        user_handler::update_user, user_handler::delete_user,
+        item_handler::create_item, item_handler::list_items, item_handler::search_items,
+        item_handler::get_item, item_handler::update_item, item_handler::delete_item,
+        order_handler::create_order, order_handler::list_orders,
+        order_handler::get_order_with_details, order_handler::update_order,
+        repo_handler::create_user, repo_handler::list_users, repo_handler::delete_users,
+        repo_handler::import_users, repo_handler::export_users, repo_handler::get_user,
+        repo_handler::update_user, repo_handler::delete_user, repo_handler::restore_user,
+        repo_handler::get_cart, repo_handler::add_cart_item, repo_handler::clear_cart,
+        repo_handler::remove_cart_item, repo_handler::checkout_cart,
+        repo_handler::create_item, repo_handler::list_items, repo_handler::list_active_items,
+        repo_handler::search_items, repo_handler::delete_items, repo_handler::import_items,
+        repo_handler::export_items, repo_handler::get_item, repo_handler::update_item,
+        repo_handler::delete_item, repo_handler::get_item_stock_movements, repo_handler::restore_item,
+        repo_handler::get_item_tags, repo_handler::set_item_tags,
+        repo_handler::create_category, repo_handler::list_categories, repo_handler::get_category_tree,
+        repo_handler::get_category, repo_handler::update_category, repo_handler::delete_category,
+        repo_handler::create_tag, repo_handler::list_tags, repo_handler::get_tag,
+        repo_handler::rename_tag, repo_handler::delete_tag,
+        repo_handler::create_order, repo_handler::list_orders, repo_handler::quote_order,
+        repo_handler::delete_orders, repo_handler::export_orders, repo_handler::get_orders_by_status,
+        repo_handler::get_order, repo_handler::get_order_with_items, repo_handler::get_order_status_history,
+        repo_handler::pay_order, repo_handler::list_order_payments, repo_handler::create_shipment,
+        repo_handler::get_shipment, repo_handler::deliver_shipment, repo_handler::update_order,
+        repo_handler::delete_order, repo_handler::restore_order, repo_handler::get_orders_by_user,
+        repo_handler::get_import,
+        repo_handler::create_tax_category, repo_handler::list_tax_categories,
+        repo_handler::create_coupon, repo_handler::list_coupons, repo_handler::get_coupon,
+        handler::create_job, handler::list_jobs, handler::list_jobs_by_status, handler::get_job,
+        admin_handler::get_config,
+        health_handler::live, health_handler::ready,
+    ),
+    // Used by query parameters or inside details, neither collects its schemas
+    components(schemas(SortDirection, OrderStatus, JobStatus, ImportFormat, ValidationDetails, StockShortage)),
+    modifiers(&DatabaseUnavailable),
+)]
+pub struct ApiDoc;
+
+// The OpenAPI 3 document for every route the server registers
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "docs",
+    summary = "This OpenAPI document",
+    responses(
+        (status = 200, description = "OK", content_type = "application/json"),
+    )
+)]
+pub async fn openapi_json(doc: web::Data<utoipa::openapi::OpenApi>) -> impl Responder {
+    HttpResponse::Ok().json(doc.get_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::*;
+
+    // Where each route function in server.rs is mounted
+    fn prefix(route_fn: &str) -> Option<&'static str> {
+        match route_fn {
+            "db_routes" => Some("/db"),
+            "json_routes" | "job_routes" | "admin_routes" | "health_routes" | "docs_routes" => Some(""),
+            _ => None,
+        }
+    }
+
+    // (method, path) of every .route() call in server.rs, read from the source
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        let mut routes = BTreeSet::new();
+        let mut mount = None;
+        for line in include_str!("server.rs").lines() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("fn ").and_then(|rest| rest.split('(').next()) {
+                mount = prefix(name);
+                continue;
+            }
+            let Some(rest) = line.strip_prefix(".route(\"") else {
+                continue;
+            };
+            let prefix = mount.unwrap_or_else(|| panic!("route outside a known route function: {}", line));
+            let (path, rest) = rest.split_once('"').expect("route path literal");
+            let method = rest
+                .split("web::")
+                .nth(1)
+                .and_then(|call| call.split("()").next())
+                .expect("web::<method>() after the route path");
+            routes.insert((method.to_string(), format!("{}{}", prefix, path)));
+        }
+        routes
+    }
+
+    fn documented_routes() -> BTreeSet<(String, String)> {
+        let doc = ApiDoc::openapi();
+        let mut routes = BTreeSet::new();
+        for (path, item) in &doc.paths.paths {
+            let methods = [
+                ("get", &item.get), ("post", &item.post), ("put", &item.put),
+                ("delete", &item.delete), ("patch", &item.patch),
+            ];
+            for (method, operation) in methods {
+                if operation.is_some() {
+                    routes.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn spec_matches_registered_routes() {
+        let registered = registered_routes();
+        let documented = documented_routes();
+        assert!(registered.len() > 80, "only found {} routes in server.rs", registered.len());
+
+        let undocumented: Vec<_> = registered.difference(&documented).collect();
+        let stale: Vec<_> = documented.difference(&registered).collect();
+        assert!(
+            undocumented.is_empty() && stale.is_empty(),
+            "routes missing from the spec: {:?}\nspec paths with no route: {:?}",
+            undocumented,
+            stale
+        );
+    }
+
+    #[test]
+    fn every_schema_reference_resolves() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).expect("spec serializes");
+        let schemas = &doc["components"]["schemas"];
+        let mut pending = vec![&doc];
+        while let Some(value) = pending.pop() {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(target)) = map.get("$ref") {
+                        let name = target.trim_start_matches("#/components/schemas/");
+                        assert!(schemas.get(name).is_some(), "{} is not in components.schemas", target);
+                    }
+                    pending.extend(map.values());
+                }
+                serde_json::Value::Array(values) => pending.extend(values),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn operation_ids_are_unique() {
+        let doc = ApiDoc::openapi();
+        let mut seen = BTreeSet::new();
+        for item in doc.paths.paths.values() {
+            let operations = [&item.get, &item.post, &item.put, &item.delete, &item.patch];
+            for operation in operations.into_iter().flatten() {
+                let id = operation.operation_id.clone().unwrap_or_default();
+                assert!(seen.insert(id.clone()), "operation id {} is used twice", id);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+use crate::money::Money;
+use crate::payments::{PaymentProvider, ProviderFuture, ProviderOutcome};
+
+// Token that makes the fake provider decline a capture
+pub const DECLINED_TOKEN: &str = "tok_declined";
+
+// Local stand-in for a real provider, approves everything except the declined token and never leaves the process
+#[derive(Debug, Default)]
+pub struct FakePaymentProvider {
+    // Outcome of every capture by idempotency key, so a retried capture is not charged twice
+    captures: Mutex<HashMap<Uuid, ProviderOutcome>>,
+}
+
+impl FakePaymentProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PaymentProvider for FakePaymentProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn capture<'a>(
+        &'a self,
+        _order_id: Uuid,
+        amount: &'a Money,
+        payment_token: &'a str,
+        idempotency_key: Uuid,
+    ) -> ProviderFuture<'a> {
+        Box::pin(async move {
+            let mut captures = self.captures.lock().unwrap_or_else(|e| e.into_inner());
+            captures
+                .entry(idempotency_key)
+                .or_insert_with(|| {
+                    if payment_token == DECLINED_TOKEN {
+                        ProviderOutcome::Declined { reason: "Card declined".to_string() }
+                    } else if amount.amount_minor < 0 {
+                        ProviderOutcome::Declined { reason: "Amount must not be negative".to_string() }
+                    } else {
+                        ProviderOutcome::Approved { provider_ref: format!("fake_ch_{}", Uuid::new_v4().simple()) }
+                    }
+                })
+                .clone()
+        })
+    }
+
+    fn refund<'a>(&'a self, provider_ref: &'a str, _amount: &'a Money) -> ProviderFuture<'a> {
+        Box::pin(async move {
+            if !provider_ref.starts_with("fake_ch_") {
+                return ProviderOutcome::Declined { reason: format!("Unknown charge {}", provider_ref) };
+            }
+            ProviderOutcome::Approved { provider_ref: format!("fake_re_{}", Uuid::new_v4().simple()) }
+        })
+    }
+}
@@ -0,0 +1,35 @@
+pub mod fake;
+
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
+use crate::money::Money;
+
+// Boxed so PaymentProvider can be shared as Arc<dyn PaymentProvider>
+pub type ProviderFuture<'a> = Pin<Box<dyn Future<Output = ProviderOutcome> + Send + 'a>>;
+
+// What the provider said about a capture or refund
+#[derive(Debug, Clone)]
+pub enum ProviderOutcome {
+    Approved { provider_ref: String },
+    Declined { reason: String },
+}
+
+// A payment service that can take money for an order and give it back
+pub trait PaymentProvider: Send + Sync {
+    // Stored on every payment row so a record can be traced back to the provider
+    fn name(&self) -> &'static str;
+
+    // payment_token is whatever the client got from the provider's checkout (card token, wallet id).
+    // A capture sent again with the same idempotency_key gets the first outcome back instead of a second charge.
+    fn capture<'a>(
+        &'a self,
+        order_id: Uuid,
+        amount: &'a Money,
+        payment_token: &'a str,
+        idempotency_key: Uuid,
+    ) -> ProviderFuture<'a>;
+
+    // provider_ref is the reference returned by the capture being refunded
+    fn refund<'a>(&'a self, provider_ref: &'a str, amount: &'a Money) -> ProviderFuture<'a>;
+}
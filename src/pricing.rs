@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use crate::models::{Coupon, CouponKind, PriceBreakdown};
+use crate::money::Money;
+
+// Rates and percentages are stored in basis points, 10_000 = 100%
+pub const BPS_DENOMINATOR: i64 = 10_000;
+
+// What the pricing engine needs to know about one order line
+#[derive(Debug, Clone)]
+pub struct PricedLine {
+    pub unit_price: Money,
+    pub quantity: i32,
+    pub tax_rate_bps: i32,
+}
+
+// Percentage of an amount in basis points, rounded half up to the nearest minor unit
+fn apply_bps(amount_minor: i64, bps: i64) -> i64 {
+    let scaled = amount_minor as i128 * bps as i128;
+    let denominator = BPS_DENOMINATOR as i128;
+    ((scaled + denominator / 2) / denominator) as i64
+}
+
+// Checks a coupon can be redeemed right now, only done when the coupon is first applied
+pub fn check_coupon(coupon: &Coupon, now: DateTime<Utc>) -> Result<(), String> {
+    if !coupon.is_active {
+        return Err(format!("Coupon {} is not active", coupon.code));
+    }
+    if let Some(expires_at) = coupon.expires_at
+        && expires_at <= now
+    {
+        return Err(format!("Coupon {} has expired", coupon.code));
+    }
+    if let Some(limit) = coupon.usage_limit
+        && coupon.times_used >= limit
+    {
+        return Err(format!("Coupon {} has reached its usage limit", coupon.code));
+    }
+    Ok(())
+}
+
+// Splits an order level discount over the lines in proportion to their value.
+// The last line takes whatever rounding left over so the shares add up to the discount exactly.
+fn spread_discount(discount_minor: i64, line_minors: &[i64]) -> Vec<i64> {
+    let subtotal: i128 = line_minors.iter().map(|minor| *minor as i128).sum();
+    let mut allocated: i64 = 0;
+    line_minors
+        .iter()
+        .enumerate()
+        .map(|(index, line_minor)| {
+            let share = if index + 1 == line_minors.len() {
+                discount_minor - allocated
+            } else if subtotal == 0 {
+                0
+            } else {
+                (discount_minor as i128 * *line_minor as i128 / subtotal) as i64
+            };
+            allocated += share;
+            share
+        })
+        .collect()
+}
+
+// Subtotal, order level discount, per line tax and total for a set of lines.
+// The discount is spread over the lines by value so each line is taxed on what is actually paid.
+pub fn price_lines(lines: &[PricedLine], coupon: Option<&Coupon>) -> Result<PriceBreakdown, String> {
+    let subtotal = Money::total(lines.iter().map(|line| (&line.unit_price, line.quantity)))?;
+    let currency = subtotal.currency.clone();
+
+    let discount_minor = match coupon {
+        None => 0,
+        Some(coupon) => match coupon.kind {
+            CouponKind::Percentage => {
+                let bps = coupon.percent_bps.unwrap_or(0).clamp(0, BPS_DENOMINATOR as i32);
+                apply_bps(subtotal.amount_minor, bps as i64)
+            }
+            CouponKind::Fixed => {
+                let coupon_currency = coupon.currency.as_deref().unwrap_or(&currency);
+                if coupon_currency != currency {
+                    return Err(format!(
+                        "Coupon {} is in {} but the order is in {}",
+                        coupon.code, coupon_currency, currency
+                    ));
+                }
+                coupon.amount_minor.unwrap_or(0).clamp(0, subtotal.amount_minor)
+            }
+        },
+    };
+
+    let line_minors = lines
+        .iter()
+        .map(|line| line.unit_price.checked_mul(line.quantity).map(|total| total.amount_minor))
+        .collect::<Result<Vec<i64>, String>>()?;
+    let shares = spread_discount(discount_minor, &line_minors);
+
+    let tax_minor = lines
+        .iter()
+        .zip(line_minors.iter().zip(&shares))
+        .map(|(line, (line_minor, share))| apply_bps(line_minor - share, line.tax_rate_bps as i64))
+        .try_fold(0_i64, |sum, tax| sum.checked_add(tax))
+        .ok_or_else(|| "Amount overflow".to_string())?;
+
+    let total_minor = subtotal.amount_minor
+        .checked_sub(discount_minor)
+        .and_then(|minor| minor.checked_add(tax_minor))
+        .ok_or_else(|| "Amount overflow".to_string())?;
+
+    Ok(PriceBreakdown {
+        discount: Money::from_stored(discount_minor, currency.clone()),
+        tax: Money::from_stored(tax_minor, currency.clone()),
+        total: Money::from_stored(total_minor, currency),
+        subtotal,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn line(amount_minor: i64, currency: &str, quantity: i32, tax_rate_bps: i32) -> PricedLine {
+        PricedLine {
+            unit_price: Money::new(amount_minor, currency).unwrap(),
+            quantity,
+            tax_rate_bps,
+        }
+    }
+
+    fn coupon(kind: CouponKind, percent_bps: Option<i32>, amount_minor: Option<i64>) -> Coupon {
+        Coupon {
+            id: Uuid::new_v4(),
+            code: "SAVE".to_string(),
+            kind,
+            percent_bps,
+            amount_minor,
+            currency: amount_minor.map(|_| "USD".to_string()),
+            expires_at: None,
+            usage_limit: None,
+            times_used: 0,
+            is_active: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn bps_round_half_up() {
+        assert_eq!(apply_bps(1000, 825), 83); // 82.5
+        assert_eq!(apply_bps(1000, 824), 82); // 82.4
+        assert_eq!(apply_bps(1, 5000), 1); // 0.5
+        assert_eq!(apply_bps(1, 4999), 0);
+        assert_eq!(apply_bps(i64::MAX, BPS_DENOMINATOR), i64::MAX);
+    }
+
+    #[test]
+    fn spread_discount_adds_up_exactly() {
+        let cases: &[(i64, &[i64])] = &[
+            (100, &[100, 100, 100]),
+            (1, &[333, 333, 334]),
+            (999, &[1, 2, 3, 5, 7, 11]),
+            (50, &[0, 0]),
+            (0, &[250, 750]),
+            (7, &[1_000_000, 1]),
+        ];
+        for (discount, lines) in cases {
+            let shares = spread_discount(*discount, lines);
+            assert_eq!(shares.len(), lines.len());
+            assert_eq!(shares.iter().sum::<i64>(), *discount, "{} over {:?}", discount, lines);
+        }
+        assert_eq!(spread_discount(100, &[100, 100, 100]), vec![33, 33, 34]);
+        assert_eq!(spread_discount(30, &[100, 200]), vec![10, 20]);
+    }
+
+    #[test]
+    fn fixed_discount_is_spread_before_tax() {
+        // Only the last line is taxed, at 100%, so the tax shows which share it got
+        let lines = [line(100, "USD", 1, 0), line(100, "USD", 1, 0), line(100, "USD", 1, 10_000)];
+        let breakdown = price_lines(&lines, Some(&coupon(CouponKind::Fixed, None, Some(100)))).unwrap();
+        assert_eq!(breakdown.subtotal.amount_minor, 300);
+        assert_eq!(breakdown.discount.amount_minor, 100);
+        assert_eq!(breakdown.tax.amount_minor, 66);
+        assert_eq!(breakdown.total.amount_minor, 266);
+    }
+
+    #[test]
+    fn percentage_discount_and_per_line_tax() {
+        let lines = [line(1999, "usd", 2, 2000), line(500, "USD", 3, 500)];
+        let breakdown = price_lines(&lines, Some(&coupon(CouponKind::Percentage, Some(1000), None))).unwrap();
+        // 3998 + 1500 = 5498, 10% off = 549.8 -> 550
+        assert_eq!(breakdown.subtotal.amount_minor, 5498);
+        assert_eq!(breakdown.discount.amount_minor, 550);
+        // shares 399 and 151: (3998 - 399) * 20% = 719.8 -> 720, (1500 - 151) * 5% = 67.45 -> 67
+        assert_eq!(breakdown.tax.amount_minor, 787);
+        assert_eq!(breakdown.total.amount_minor, 5498 - 550 + 787);
+        assert_eq!(breakdown.total.currency, "USD");
+    }
+
+    #[test]
+    fn fixed_discount_never_exceeds_the_subtotal() {
+        let lines = [line(250, "USD", 1, 1000)];
+        let breakdown = price_lines(&lines, Some(&coupon(CouponKind::Fixed, None, Some(1000)))).unwrap();
+        assert_eq!(breakdown.discount.amount_minor, 250);
+        assert_eq!(breakdown.tax.amount_minor, 0);
+        assert_eq!(breakdown.total.amount_minor, 0);
+    }
+
+    #[test]
+    fn totals_near_i64_max_overflow_into_an_error() {
+        // The line itself fits, adding 10% tax to it does not
+        let lines = [line(i64::MAX - 10, "USD", 1, 1000)];
+        assert_eq!(price_lines(&lines, None).unwrap_err(), "Amount overflow");
+
+        // Without tax the same line prices fine
+        let untaxed = [line(i64::MAX - 10, "USD", 1, 0)];
+        assert_eq!(price_lines(&untaxed, None).unwrap().total.amount_minor, i64::MAX - 10);
+
+        let lines = [line(i64::MAX / 2 + 1, "USD", 1, 0), line(i64::MAX / 2 + 1, "USD", 1, 0)];
+        assert_eq!(price_lines(&lines, None).unwrap_err(), "Amount overflow");
+        assert!(price_lines(&[line(i64::MAX, "USD", 2, 0)], None).is_err());
+    }
+
+    #[test]
+    fn mixed_currencies_are_rejected() {
+        let lines = [line(100, "USD", 1, 0), line(100, "EUR", 1, 0)];
+        assert!(price_lines(&lines, None).is_err());
+
+        let eur = [line(100, "EUR", 1, 0)];
+        let err = price_lines(&eur, Some(&coupon(CouponKind::Fixed, None, Some(10)))).unwrap_err();
+        assert!(err.contains("EUR") && err.contains("USD"), "{}", err);
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::HashSet;
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream};
+use serde::Serialize;
+use sqlx::{PgPool, PgConnection, Error};
+use uuid::Uuid;
+use crate::bulk::{parse_rows, csv_line, CsvRecord};
+use crate::models::{
+    ImportDB, ImportReport, ImportRowError, ImportKind, ImportFormat, ImportStatus,
+    UserImportRow, ItemImportRow, UserDB, ItemDB, Order, OrderDB, OrderStatus, StockMovementReason,
+};
+use crate::money::Money;
+use crate::repository::error::RepoError;
+use crate::repository::items_db::record_stock_movement;
+use crate::repository::order_db::attach_order_lines;
+use crate::validation::is_valid_email;
+
+// Rows read per query while streaming an export
+const EXPORT_BATCH: i64 = 500;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExportKind {
+    Users,
+    Items,
+    Orders,
+}
+
+// Counts and rejected rows of one import run
+struct ImportOutcome {
+    status: ImportStatus,
+    total_rows: i32,
+    imported_rows: i32,
+    errors: Vec<ImportRowError>,
+}
+
+impl ImportOutcome {
+    fn new(total_rows: usize) -> Self {
+        Self {
+            status: ImportStatus::Completed,
+            total_rows: total_rows as i32,
+            imported_rows: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    // The file could not be read at all, reported as row 0
+    fn unreadable(message: String) -> Self {
+        Self {
+            status: ImportStatus::Failed,
+            total_rows: 0,
+            imported_rows: 0,
+            errors: vec![ImportRowError { row_number: 0, message }],
+        }
+    }
+
+    fn reject(&mut self, row_number: i32, message: String) {
+        self.errors.push(ImportRowError { row_number, message });
+    }
+}
+
+pub struct BulkRepository {
+    pool: PgPool
+}
+
+impl BulkRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    // Stores an uploaded file until a worker picks up its import job
+    pub async fn create_import(
+        &self,
+        kind: ImportKind,
+        format: ImportFormat,
+        body: &str,
+    ) -> Result<ImportDB, Error> {
+        let import = sqlx::query_as!(
+            ImportDB,
+            r#"
+            INSERT INTO imports (kind, format, body)
+            VALUES ($1, $2, $3)
+            RETURNING
+                id,
+                kind as "kind: ImportKind",
+                format as "format: ImportFormat",
+                status as "status: ImportStatus",
+                job_id,
+                total_rows,
+                imported_rows,
+                created_at,
+                finished_at
+            "#,
+            kind as ImportKind,
+            format as ImportFormat,
+            body
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(import)
+    }
+
+    pub async fn set_import_job(
+        &self,
+        id: Uuid,
+        job_id: u64,
+    ) -> Result<(), Error> {
+        sqlx::query!("UPDATE imports SET job_id = $1 WHERE id = $2", job_id as i64, id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Drops an import that never made it onto the queue
+    pub async fn delete_import(
+        &self,
+        id: Uuid,
+    ) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM imports WHERE id = $1", id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_import(
+        &self,
+        id: Uuid,
+    ) -> Result<ImportReport, Error> {
+        let import = sqlx::query_as!(
+            ImportDB,
+            r#"
+            SELECT
+                id,
+                kind as "kind: ImportKind",
+                format as "format: ImportFormat",
+                status as "status: ImportStatus",
+                job_id,
+                total_rows,
+                imported_rows,
+                created_at,
+                finished_at
+            FROM imports
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let errors = sqlx::query_as!(
+            ImportRowError,
+            r#"
+            SELECT row_number, message
+            FROM import_errors
+            WHERE import_id = $1
+            ORDER BY row_number
+            "#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ImportReport { import, errors })
+    }
+
+    // Streams a whole table in id order, one batch in memory at a time
+    pub fn export(
+        &self,
+        kind: ExportKind,
+        format: ImportFormat,
+    ) -> impl Stream<Item = Result<Bytes, Error>> + 'static {
+        let pool = self.pool.clone();
+        // (after, header written, finished)
+        stream::unfold((None, false, false), move |(after, header, finished)| {
+            let pool = pool.clone();
+            async move {
+                if finished {
+                    return None;
+                }
+                match export_batch(&pool, kind, format, after).await {
+                    Ok((mut chunk, last, count)) => {
+                        if !header && format == ImportFormat::Csv {
+                            chunk.insert_str(0, &csv_line(&export_header(kind)));
+                        }
+                        let done = count < EXPORT_BATCH as usize;
+                        Some((Ok(Bytes::from(chunk)), (last, true, done)))
+                    }
+                    Err(e) => {
+                        log::error!("DB error exporting {:?}: {:?}", kind, e);
+                        Some((Err(e), (after, true, true)))
+                    }
+                }
+            }
+        })
+    }
+}
+
+// Imports every valid row of a pending import and records the rejected ones, all in one transaction
+// so a failed run leaves the import pending for the job retry.
+pub async fn run_import(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<ImportDB, RepoError> {
+    let mut tx = pool.begin().await?;
+
+    let pending = sqlx::query!(
+        r#"
+        SELECT kind as "kind: ImportKind", format as "format: ImportFormat", body
+        FROM imports
+        WHERE id = $1 AND status = 'Pending'
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let outcome = match pending.kind {
+        ImportKind::Users => import_users(&mut tx, pending.format, &pending.body).await?,
+        ImportKind::Items => import_items(&mut tx, pending.format, &pending.body).await?,
+    };
+
+    let (row_numbers, messages): (Vec<i32>, Vec<String>) = outcome.errors
+        .into_iter()
+        .map(|error| (error.row_number, error.message))
+        .unzip();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO import_errors (import_id, row_number, message)
+        SELECT $1, row_number, message
+        FROM UNNEST($2::int[], $3::text[]) AS e(row_number, message)
+        "#,
+        id,
+        &row_numbers,
+        &messages
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let import = sqlx::query_as!(
+        ImportDB,
+        r#"
+        UPDATE imports
+        SET
+            status = $2,
+            total_rows = $3,
+            imported_rows = $4,
+            body = '',
+            finished_at = now()
+        WHERE id = $1
+        RETURNING
+            id,
+            kind as "kind: ImportKind",
+            format as "format: ImportFormat",
+            status as "status: ImportStatus",
+            job_id,
+            total_rows,
+            imported_rows,
+            created_at,
+            finished_at
+        "#,
+        id,
+        outcome.status as ImportStatus,
+        outcome.total_rows,
+        outcome.imported_rows
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(import)
+}
+
+async fn import_users(
+    conn: &mut PgConnection,
+    format: ImportFormat,
+    body: &str,
+) -> Result<ImportOutcome, Error> {
+    let rows = match parse_rows::<UserImportRow>(format, body) {
+        Ok(rows) => rows,
+        Err(message) => return Ok(ImportOutcome::unreadable(message)),
+    };
+    let mut outcome = ImportOutcome::new(rows.len());
+
+    let mut seen = HashSet::new();
+    let mut valid = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let row_number = index as i32 + 1;
+        match row.and_then(validate_user_row) {
+            Ok(row) if !seen.insert(row.email.clone()) => {
+                outcome.reject(row_number, format!("Email {} appears more than once in the file", row.email));
+            }
+            Ok(row) => valid.push((row_number, row)),
+            Err(message) => outcome.reject(row_number, message),
+        }
+    }
+
+    for (row_number, row) in valid {
+        // Emails of soft deleted users are still taken, the constraint covers them too
+        let inserted = sqlx::query_scalar!(
+            r#"
+            INSERT INTO users (name, email, is_active)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
+            "#,
+            row.name,
+            row.email,
+            row.is_active.unwrap_or(true)
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        match inserted {
+            Some(_) => outcome.imported_rows += 1,
+            None => outcome.reject(row_number, format!("A user with email {} already exists", row.email)),
+        }
+    }
+
+    outcome.errors.sort_by_key(|error| error.row_number);
+    Ok(outcome)
+}
+
+async fn import_items(
+    conn: &mut PgConnection,
+    format: ImportFormat,
+    body: &str,
+) -> Result<ImportOutcome, Error> {
+    let rows = match parse_rows::<ItemImportRow>(format, body) {
+        Ok(rows) => rows,
+        Err(message) => return Ok(ImportOutcome::unreadable(message)),
+    };
+    let mut outcome = ImportOutcome::new(rows.len());
+
+    let mut valid = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        match row.and_then(validate_item_row) {
+            Ok(row) => valid.push((index as i32 + 1, row)),
+            Err(message) => outcome.reject(index as i32 + 1, message),
+        }
+    }
+
+    // Unknown references would abort the transaction on the foreign key, check them up front
+    let category_ids: Vec<Uuid> = valid.iter().filter_map(|(_, row)| row.category_id).collect();
+    let known_categories: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT id FROM categories WHERE id = ANY($1)",
+        &category_ids
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    let tax_category_ids: Vec<Uuid> = valid.iter().filter_map(|(_, row)| row.tax_category_id).collect();
+    let known_tax_categories: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT id FROM tax_categories WHERE id = ANY($1)",
+        &tax_category_ids
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    for (row_number, row) in valid {
+        if let Some(category_id) = row.category_id
+            && !known_categories.contains(&category_id)
+        {
+            outcome.reject(row_number, format!("Category {} does not exist", category_id));
+            continue;
+        }
+        if let Some(tax_category_id) = row.tax_category_id
+            && !known_tax_categories.contains(&tax_category_id)
+        {
+            outcome.reject(row_number, format!("Tax category {} does not exist", tax_category_id));
+            continue;
+        }
+
+        let item_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO items (name, price_minor, currency, quantity, description, is_active, tax_category_id, category_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+            "#,
+            row.name,
+            row.price_minor,
+            row.currency,
+            row.quantity,
+            row.description,
+            row.is_active.unwrap_or(true),
+            row.tax_category_id,
+            row.category_id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if row.quantity != 0 {
+            record_stock_movement(conn, item_id, None, row.quantity, StockMovementReason::Initial).await?;
+        }
+        outcome.imported_rows += 1;
+    }
+
+    outcome.errors.sort_by_key(|error| error.row_number);
+    Ok(outcome)
+}
+
+fn validate_user_row(row: UserImportRow) -> Result<UserImportRow, String> {
+    let name = row.name.trim().to_string();
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    let email = row.email.trim().to_string();
+    if !is_valid_email(&email) {
+        return Err(format!("{} is not a valid email address", email));
+    }
+    Ok(UserImportRow { name, email, ..row })
+}
+
+fn validate_item_row(row: ItemImportRow) -> Result<ItemImportRow, String> {
+    let name = row.name.trim().to_string();
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if row.price_minor < 0 {
+        return Err("price_minor must not be negative".to_string());
+    }
+    if row.quantity < 0 {
+        return Err("quantity must not be negative".to_string());
+    }
+    let currency = Money::new(row.price_minor, &row.currency)?.currency;
+    Ok(ItemImportRow { name, currency, ..row })
+}
+
+fn export_header(kind: ExportKind) -> Vec<String> {
+    let header = match kind {
+        ExportKind::Users => UserDB::HEADER,
+        ExportKind::Items => ItemDB::HEADER,
+        ExportKind::Orders => Order::HEADER,
+    };
+    header.iter().map(|column| column.to_string()).collect()
+}
+
+// One batch of rows after the given id rendered in the export format, with the last id and the row count
+async fn export_batch(
+    pool: &PgPool,
+    kind: ExportKind,
+    format: ImportFormat,
+    after: Option<Uuid>,
+) -> Result<(String, Option<Uuid>, usize), Error> {
+    match kind {
+        ExportKind::Users => {
+            let users = sqlx::query_as!(
+                UserDB,
+                r#"
+                SELECT id, name, email, is_active, created_at, updated_at, deleted_at
+                FROM users
+                WHERE deleted_at IS NULL AND ($1::uuid IS NULL OR id > $1)
+                ORDER BY id
+                LIMIT $2
+                "#,
+                after,
+                EXPORT_BATCH
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let last = users.last().map(|user| user.id);
+            Ok((render(&users, format), last, users.len()))
+        }
+        ExportKind::Items => {
+            let items = sqlx::query_as!(
+                ItemDB,
+                r#"
+                SELECT
+                    id,
+                    name,
+                    price_minor,
+                    currency,
+                    quantity,
+                    description,
+                    created_at,
+                    updated_at,
+                    is_active,
+                    tax_category_id,
+                    category_id,
+                    deleted_at
+                FROM items
+                WHERE deleted_at IS NULL AND ($1::uuid IS NULL OR id > $1)
+                ORDER BY id
+                LIMIT $2
+                "#,
+                after,
+                EXPORT_BATCH
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let last = items.last().map(|item| item.id);
+            Ok((render(&items, format), last, items.len()))
+        }
+        ExportKind::Orders => {
+            let orders_db = sqlx::query_as!(
+                OrderDB,
+                r#"
+                SELECT
+                    id,
+                    user_id,
+                    subtotal_minor,
+                    discount_minor,
+                    tax_minor,
+                    amount_minor,
+                    currency,
+                    coupon_code,
+                    status as "status: OrderStatus",
+                    created_at,
+                    updated_at,
+                    deleted_at
+                FROM orders
+                WHERE deleted_at IS NULL AND ($1::uuid IS NULL OR id > $1)
+                ORDER BY id
+                LIMIT $2
+                "#,
+                after,
+                EXPORT_BATCH
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let last = orders_db.last().map(|order| order.id);
+            let count = orders_db.len();
+            let mut conn = pool.acquire().await?;
+            // CSV has one line per order, only NDJSON carries the order lines
+            let orders = attach_order_lines(&mut conn, orders_db, format == ImportFormat::Ndjson).await?;
+            Ok((render(&orders, format), last, count))
+        }
+    }
+}
+
+fn render<T: CsvRecord + Serialize>(rows: &[T], format: ImportFormat) -> String {
+    let mut chunk = String::new();
+    for row in rows {
+        match format {
+            ImportFormat::Csv => chunk.push_str(&csv_line(&row.csv_fields())),
+            ImportFormat::Ndjson => {
+                // Serializing these plain structs cannot fail
+                chunk.push_str(&serde_json::to_string(row).unwrap_or_default());
+                chunk.push('\n');
+            }
+        }
+    }
+    chunk
+}
@@ -0,0 +1,265 @@
+use sqlx::{PgPool, PgConnection, Error};
+use uuid::Uuid;
+use crate::models::{Cart, CartLine, CartLineDB, AddCartItem, Order, CreateOrder, CreateOrderLine, MAX_LINE_QUANTITY};
+use crate::repository::error::RepoError;
+use crate::repository::order_db::OrderRepository;
+
+pub struct CartRepository {
+    pool: PgPool
+}
+
+impl CartRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    // A user without a cart row simply has an empty cart
+    pub async fn get_cart(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Cart, RepoError> {
+        let mut conn = self.pool.acquire().await?;
+        ensure_user_exists(&mut conn, user_id).await?;
+
+        let items = get_cart_lines(&mut conn, user_id).await?;
+
+        Ok(Cart { user_id, items })
+    }
+
+    pub async fn add_item(
+        &self,
+        user_id: Uuid,
+        req: &AddCartItem,
+    ) -> Result<Cart, RepoError> {
+        if req.quantity <= 0 {
+            return Err(RepoError::Validation(format!(
+                "Quantity for item {} must be greater than 0", req.item_id
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        ensure_user_exists(&mut tx, user_id).await?;
+
+        let is_active = sqlx::query_scalar!(
+            r#"
+            SELECT is_active FROM items
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            req.item_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if !is_active {
+            return Err(RepoError::Validation(format!(
+                "Item {} is not available", req.item_id
+            )));
+        }
+
+        let cart_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO carts (user_id)
+            VALUES ($1)
+            ON CONFLICT (user_id) DO UPDATE SET updated_at = now()
+            RETURNING id
+            "#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // The line is left alone when the sum would pass the cap, written so the addition can't overflow
+        let added = sqlx::query!(
+            r#"
+            INSERT INTO cart_items (cart_id, item_id, quantity)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (cart_id, item_id) DO UPDATE SET quantity = cart_items.quantity + EXCLUDED.quantity
+            WHERE cart_items.quantity <= $4 - EXCLUDED.quantity
+            "#,
+            cart_id,
+            req.item_id,
+            req.quantity,
+            MAX_LINE_QUANTITY
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if added.rows_affected() == 0 {
+            return Err(RepoError::Validation(format!(
+                "Quantity for item {} in the cart can't be more than {}", req.item_id, MAX_LINE_QUANTITY
+            )));
+        }
+
+        let items = get_cart_lines(&mut tx, user_id).await?;
+
+        tx.commit().await?;
+
+        Ok(Cart { user_id, items })
+    }
+
+    pub async fn remove_item(
+        &self,
+        user_id: Uuid,
+        item_id: Uuid,
+    ) -> Result<Cart, RepoError> {
+        let mut conn = self.pool.acquire().await?;
+
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM cart_items
+            USING carts
+            WHERE cart_items.cart_id = carts.id
+                AND carts.user_id = $1
+                AND cart_items.item_id = $2
+            "#,
+            user_id,
+            item_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound);
+        }
+
+        let items = get_cart_lines(&mut conn, user_id).await?;
+
+        Ok(Cart { user_id, items })
+    }
+
+    pub async fn clear(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Cart, RepoError> {
+        let mut conn = self.pool.acquire().await?;
+        ensure_user_exists(&mut conn, user_id).await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM cart_items
+            USING carts
+            WHERE cart_items.cart_id = carts.id
+                AND carts.user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(Cart { user_id, items: Vec::new() })
+    }
+
+    // Turns the cart into an order and empties it, both happen or neither does
+    pub async fn checkout(
+        &self,
+        user_id: Uuid,
+        coupon_code: Option<String>,
+        orders: &OrderRepository,
+    ) -> Result<Order, RepoError> {
+        let mut tx = self.pool.begin().await?;
+        ensure_user_exists(&mut tx, user_id).await?;
+
+        // Lock the cart so two checkouts of the same cart cannot both place an order
+        let cart_id = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM carts
+            WHERE user_id = $1
+            FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let lines = match cart_id {
+            Some(_) => get_cart_lines(&mut tx, user_id).await?,
+            None => Vec::new(),
+        };
+        if lines.is_empty() {
+            return Err(RepoError::Validation("Cart is empty".to_string()));
+        }
+
+        let inactive: Vec<String> = lines.iter()
+            .filter(|line| !line.is_active)
+            .map(|line| line.name.clone())
+            .collect();
+        if !inactive.is_empty() {
+            return Err(RepoError::Conflict(format!(
+                "Items no longer available: {}", inactive.join(", ")
+            )));
+        }
+
+        // Stock is checked against locked item rows while the order lines are written
+        let order = OrderRepository::create_order_in_tx(&mut tx, &CreateOrder {
+            user_id,
+            items: lines.iter()
+                .map(|line| CreateOrderLine { item_id: line.item_id, quantity: line.quantity })
+                .collect(),
+            coupon_code,
+        }).await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM cart_items
+            USING carts
+            WHERE cart_items.cart_id = carts.id
+                AND carts.user_id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(orders.get_order(order.id, false).await?)
+    }
+}
+
+async fn ensure_user_exists(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<(), RepoError> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL) as "exists!"
+        "#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    if exists { Ok(()) } else { Err(RepoError::NotFound) }
+}
+
+// Cart lines with the item's current name, price, active flag and stock
+async fn get_cart_lines(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Vec<CartLine>, Error> {
+    let lines = sqlx::query_as!(
+        CartLineDB,
+        r#"
+        SELECT
+            ci.item_id,
+            i.name,
+            ci.quantity,
+            i.price_minor,
+            i.currency,
+            i.is_active AND i.deleted_at IS NULL as "is_active!",
+            i.quantity as available
+        FROM cart_items ci
+        JOIN carts c ON c.id = ci.cart_id
+        JOIN items i ON i.id = ci.item_id
+        WHERE c.user_id = $1
+        ORDER BY ci.added_at, ci.item_id
+        "#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(lines.into_iter().map(CartLine::from).collect())
+}
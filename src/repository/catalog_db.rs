@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use sqlx::{PgPool, Error};
+use uuid::Uuid;
+use crate::models::{Category, CategoryNode, CreateCategory, UpdateCategory, Tag, TagRequest, SetItemTags, normalize_tag};
+use crate::repository::error::RepoError;
+
+// Categories and tags used to browse items
+pub struct CatalogRepository {
+    pool: PgPool
+}
+
+impl CatalogRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    pub async fn create_category(
+        &self,
+        req: &CreateCategory,
+    ) -> Result<Category, RepoError> {
+        let name = req.name.trim();
+        if name.is_empty() {
+            return Err(RepoError::Validation("name must not be empty".to_string()));
+        }
+
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+            INSERT INTO categories (name, parent_id)
+            VALUES ($1, $2)
+            RETURNING id, parent_id, name, created_at, updated_at
+            "#,
+            name,
+            req.parent_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| category_write_error(e, name, req.parent_id))?;
+
+        Ok(category)
+    }
+
+    pub async fn get_category(
+        &self,
+        id: Uuid,
+    ) -> Result<Category, Error> {
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+            SELECT id, parent_id, name, created_at, updated_at
+            FROM categories
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(category)
+    }
+
+    pub async fn list_categories(&self) -> Result<Vec<Category>, Error> {
+        let categories = sqlx::query_as!(
+            Category,
+            r#"
+            SELECT id, parent_id, name, created_at, updated_at
+            FROM categories
+            ORDER BY name, id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(categories)
+    }
+
+    // All categories nested under their parents, roots first, siblings by name
+    pub async fn category_tree(&self) -> Result<Vec<CategoryNode>, Error> {
+        let categories = self.list_categories().await?;
+
+        let mut children: HashMap<Option<Uuid>, Vec<Category>> = HashMap::new();
+        for category in categories {
+            children.entry(category.parent_id).or_default().push(category);
+        }
+
+        Ok(build_nodes(None, &mut children))
+    }
+
+    pub async fn update_category(
+        &self,
+        id: Uuid,
+        req: &UpdateCategory,
+    ) -> Result<Category, RepoError> {
+        let name = req.name.as_deref().map(str::trim);
+        if name.is_some_and(str::is_empty) {
+            return Err(RepoError::Validation("name must not be empty".to_string()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let current = sqlx::query!("SELECT name FROM categories WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        // Moving a category below itself or one of its descendants would cut the branch off the tree
+        if let Some(Some(parent_id)) = req.parent_id {
+            let cycle = sqlx::query_scalar!(
+                r#"
+                WITH RECURSIVE subtree AS (
+                    SELECT id FROM categories WHERE id = $1
+                    UNION ALL
+                    SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+                )
+                SELECT EXISTS(SELECT 1 FROM subtree WHERE id = $2) as "cycle!"
+                "#,
+                id,
+                parent_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if cycle {
+                return Err(RepoError::Validation(
+                    "A category cannot be moved below itself or one of its descendants".to_string()
+                ));
+            }
+        }
+
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+            UPDATE categories
+            SET
+                name = COALESCE($1, name),
+                parent_id = CASE WHEN $2 THEN $3 ELSE parent_id END,
+                updated_at = now()
+            WHERE id = $4
+            RETURNING id, parent_id, name, created_at, updated_at
+            "#,
+            name,
+            req.parent_id.is_some(),
+            req.parent_id.flatten(),
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| category_write_error(e, name.unwrap_or(&current.name), req.parent_id.flatten()))?;
+
+        tx.commit().await?;
+
+        Ok(category)
+    }
+
+    // Items in the category are left without one, a category that still has children cannot go
+    pub async fn delete_category(
+        &self,
+        id: Uuid,
+    ) -> Result<Category, RepoError> {
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+            DELETE FROM categories
+            WHERE id = $1
+            RETURNING id, parent_id, name, created_at, updated_at
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            Error::Database(ref db) if db.is_foreign_key_violation() => RepoError::Conflict(format!(
+                "Category {} still has subcategories, move or delete them first", id
+            )),
+            other => other.into(),
+        })?;
+
+        Ok(category)
+    }
+
+    pub async fn create_tag(
+        &self,
+        req: &TagRequest,
+    ) -> Result<Tag, RepoError> {
+        let name = validate_tag_name(&req.name)?;
+
+        let tag = sqlx::query_as!(
+            Tag,
+            r#"
+            INSERT INTO tags (name)
+            VALUES ($1)
+            RETURNING id, name, created_at
+            "#,
+            name
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| tag_write_error(e, &name))?;
+
+        Ok(tag)
+    }
+
+    pub async fn get_tag(
+        &self,
+        id: Uuid,
+    ) -> Result<Tag, Error> {
+        let tag = sqlx::query_as!(
+            Tag,
+            "SELECT id, name, created_at FROM tags WHERE id = $1",
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(tag)
+    }
+
+    pub async fn list_tags(&self) -> Result<Vec<Tag>, Error> {
+        let tags = sqlx::query_as!(
+            Tag,
+            "SELECT id, name, created_at FROM tags ORDER BY name"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+
+    pub async fn rename_tag(
+        &self,
+        id: Uuid,
+        req: &TagRequest,
+    ) -> Result<Tag, RepoError> {
+        let name = validate_tag_name(&req.name)?;
+
+        let tag = sqlx::query_as!(
+            Tag,
+            r#"
+            UPDATE tags
+            SET name = $1
+            WHERE id = $2
+            RETURNING id, name, created_at
+            "#,
+            name,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| tag_write_error(e, &name))?;
+
+        Ok(tag)
+    }
+
+    // Also removes the tag from every item carrying it
+    pub async fn delete_tag(
+        &self,
+        id: Uuid,
+    ) -> Result<Tag, Error> {
+        let tag = sqlx::query_as!(
+            Tag,
+            "DELETE FROM tags WHERE id = $1 RETURNING id, name, created_at",
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(tag)
+    }
+
+    pub async fn get_item_tags(
+        &self,
+        item_id: Uuid,
+    ) -> Result<Vec<Tag>, Error> {
+        // Make sure the item exists so an unknown id is a 404 rather than an empty list
+        sqlx::query!("SELECT id FROM items WHERE id = $1 AND deleted_at IS NULL", item_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        self.item_tags(item_id).await
+    }
+
+    pub async fn set_item_tags(
+        &self,
+        item_id: Uuid,
+        req: &SetItemTags,
+    ) -> Result<Vec<Tag>, RepoError> {
+        let mut tag_ids = req.tag_ids.clone();
+        tag_ids.sort();
+        tag_ids.dedup();
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("SELECT id FROM items WHERE id = $1 AND deleted_at IS NULL FOR UPDATE", item_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let known = sqlx::query_scalar!("SELECT id FROM tags WHERE id = ANY($1)", &tag_ids)
+            .fetch_all(&mut *tx)
+            .await?;
+        let unknown: Vec<String> = tag_ids.iter()
+            .filter(|id| !known.contains(id))
+            .map(|id| id.to_string())
+            .collect();
+        if !unknown.is_empty() {
+            return Err(RepoError::Validation(format!("Unknown tags: {}", unknown.join(", "))));
+        }
+
+        sqlx::query!("DELETE FROM item_tags WHERE item_id = $1", item_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO item_tags (item_id, tag_id)
+            SELECT $1, tag_id FROM UNNEST($2::uuid[]) AS tag_id
+            "#,
+            item_id,
+            &tag_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(self.item_tags(item_id).await?)
+    }
+
+    async fn item_tags(&self, item_id: Uuid) -> Result<Vec<Tag>, Error> {
+        let tags = sqlx::query_as!(
+            Tag,
+            r#"
+            SELECT t.id, t.name, t.created_at
+            FROM item_tags it
+            JOIN tags t ON t.id = it.tag_id
+            WHERE it.item_id = $1
+            ORDER BY t.name
+            "#,
+            item_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tags)
+    }
+}
+
+fn build_nodes(
+    parent_id: Option<Uuid>,
+    children: &mut HashMap<Option<Uuid>, Vec<Category>>,
+) -> Vec<CategoryNode> {
+    let categories = children.remove(&parent_id).unwrap_or_default();
+    categories
+        .into_iter()
+        .map(|category| {
+            let nested = build_nodes(Some(category.id), children);
+            CategoryNode { category, children: nested }
+        })
+        .collect()
+}
+
+fn category_write_error(e: Error, name: &str, parent_id: Option<Uuid>) -> RepoError {
+    match e {
+        Error::Database(ref db) if db.is_unique_violation() => RepoError::Conflict(format!(
+            "A category named {} already exists at this level", name
+        )),
+        Error::Database(ref db) if db.is_foreign_key_violation() => RepoError::Validation(format!(
+            "Parent category {} does not exist", parent_id.unwrap_or_default()
+        )),
+        other => other.into(),
+    }
+}
+
+fn validate_tag_name(name: &str) -> Result<String, RepoError> {
+    let name = normalize_tag(name);
+    if name.is_empty() || name.contains(',') {
+        return Err(RepoError::Validation("Tag names must be non-empty and cannot contain commas".to_string()));
+    }
+    Ok(name)
+}
+
+fn tag_write_error(e: Error, name: &str) -> RepoError {
+    match e {
+        Error::Database(ref db) if db.is_unique_violation() => RepoError::Conflict(format!(
+            "Tag {} already exists", name
+        )),
+        other => other.into(),
+    }
+}
@@ -1,13 +1,130 @@
-use sqlx::{Pool, Postgres, PgPool, Error};
-use std::env;
-use uuid::Uuid;
-use dotenvy::dotenv;
-
-pub async fn get_db_pool() -> Pool<Postgres> {
-    dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL not set");
-    // let database_url = "".to_string();  // explicitly set my database url for the sake of demonstrating th etask, normally it would be in the .env
-    PgPool::connect(&database_url)
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use log::LevelFilter;
+use sqlx::{ConnectOptions, PgPool};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::migrate::Migrator;
+use tokio::time::sleep;
+use crate::config::DatabaseConfig;
+
+// Every migration under ./migrations, embedded at build time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+// How often a connected server checks that Postgres still answers
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+fn pool_options(config: &DatabaseConfig) -> PgPoolOptions {
+    // 0 turns the idle timeout and max lifetime off
+    let optional = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(optional(config.idle_timeout_secs))
+        .max_lifetime(optional(config.max_lifetime_secs))
+}
+
+// Every statement is logged with its timing at debug, slow ones at warn
+fn connect_options(config: &DatabaseConfig, url: &str) -> Result<PgConnectOptions, sqlx::Error> {
+    Ok(PgConnectOptions::from_str(url)?
+        .log_statements(LevelFilter::Debug)
+        .log_slow_statements(LevelFilter::Warn, Duration::from_millis(config.slow_statement_ms)))
+}
+
+fn missing_url() -> sqlx::Error {
+    sqlx::Error::Configuration("database.url (or DATABASE_URL) is required to use Postgres".into())
+}
+
+// Connects right away, for the commands that cannot do anything without Postgres
+pub async fn connect(config: &DatabaseConfig) -> Result<PgPool, sqlx::Error> {
+    let url = config.url.as_deref().ok_or_else(missing_url)?;
+    pool_options(config).connect_with(connect_options(config, url)?).await
+}
+
+// The server's handle on Postgres, which may not be configured or may be down for a while
+#[derive(Clone)]
+pub struct Database {
+    pool: Option<PgPool>,
+    connected: Arc<AtomicBool>,
+}
+
+impl Database {
+    pub fn disabled() -> Self {
+        Self {
+            pool: None,
+            connected: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // The pool opens no connection yet, spawn_supervisor brings it up
+    pub fn lazy(config: &DatabaseConfig) -> Result<Self, sqlx::Error> {
+        let Some(url) = config.url.as_deref() else {
+            return Ok(Self::disabled());
+        };
+        Ok(Self {
+            pool: Some(pool_options(config).connect_lazy_with(connect_options(config, url)?)),
+            connected: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn pool(&self) -> Option<&PgPool> {
+        self.pool.as_ref()
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    // Reachable and migrated
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    // Waits for Postgres with exponential backoff and migrates it once it answers, then keeps
+    // checking it so the /db routes go back to 503 while it is down
+    pub fn spawn_supervisor(&self, max_backoff: Duration) {
+        let Some(pool) = self.pool.clone() else {
+            return;
+        };
+        let connected = self.connected.clone();
+
+        tokio::spawn(async move {
+            let mut migrated = false;
+            let mut backoff = Duration::from_secs(1);
+            loop {
+                match check(&pool, &mut migrated).await {
+                    Ok(()) => {
+                        if !connected.swap(true, Ordering::Relaxed) {
+                            log::info!("Connected to Postgres");
+                        }
+                        backoff = Duration::from_secs(1);
+                        sleep(CHECK_INTERVAL).await;
+                    }
+                    Err(e) => {
+                        if connected.swap(false, Ordering::Relaxed) {
+                            log::error!("Lost connection to Postgres");
+                        }
+                        log::warn!("Postgres unavailable ({}), retrying in {}s", e, backoff.as_secs());
+                        sleep(backoff).await;
+                        backoff = (backoff * 2).min(max_backoff);
+                    }
+                }
+            }
+        });
+    }
+}
+
+async fn check(pool: &PgPool, migrated: &mut bool) -> Result<(), String> {
+    sqlx::query!("SELECT 1 AS one")
+        .fetch_one(pool)
         .await
-        .expect("Failed to connect to Postgres")
-}
\ No newline at end of file
+        .map_err(|e| e.to_string())?;
+
+    if !*migrated {
+        MIGRATOR.run(pool).await.map_err(|e| format!("migrations failed: {}", e))?;
+        *migrated = true;
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+use crate::models::StockShortage;
+
+// Errors returned by repository methods that enforce domain rules on top of plain SQL
+#[derive(Debug)]
+pub enum RepoError {
+    NotFound,
+    Validation(String),
+    Conflict(String),
+    InsufficientStock(Vec<StockShortage>),
+    PaymentDeclined(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RepoError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => RepoError::NotFound,
+            other => RepoError::Database(other),
+        }
+    }
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::NotFound => write!(f, "Row not found"),
+            RepoError::Validation(msg) => write!(f, "{}", msg),
+            RepoError::Conflict(msg) => write!(f, "{}", msg),
+            RepoError::InsufficientStock(items) => write!(f, "Insufficient stock for {} item(s)", items.len()),
+            RepoError::PaymentDeclined(reason) => write!(f, "Payment declined: {}", reason),
+            RepoError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
@@ -1,6 +1,16 @@
-use sqlx::{Pool, PgPool, Error};
+use sqlx::{PgPool, PgConnection, Error, QueryBuilder};
 use uuid::Uuid;
-use crate::models::{Item, CreateItem, UpdateItem};
+use crate::models::{Item, ItemDB, CreateItem, UpdateItem, StockMovement, StockMovementReason, BulkDeleteResult, ListParams, ItemFilter, Page, SearchQuery, ItemSearchHit};
+use crate::repository::error::RepoError;
+use crate::repository::pagination::{PageRequest, SortField, SortKind, contains_pattern};
+use crate::search::mark_headline;
+
+const ITEM_SORTS: &[SortField] = &[
+    SortField { name: "created_at", column: "created_at", kind: SortKind::Timestamp },
+    SortField { name: "name", column: "name", kind: SortKind::Text },
+    SortField { name: "price", column: "price_minor", kind: SortKind::Integer },
+    SortField { name: "quantity", column: "quantity", kind: SortKind::Integer },
+];
 
 pub struct ItemRepository {
     pool: PgPool
@@ -17,40 +27,78 @@ impl ItemRepository {
         &self,
         req: &CreateItem
     ) -> Result<Item, Error> {
+        let mut tx = self.pool.begin().await?;
+
         let item = sqlx::query_as!(
-            Item,
+            ItemDB,
             r#"
-            INSERT INTO items (name, price, quantity, description)
-            VALUES ($1, $2, $3, $4)
-            RETURNING *
+            INSERT INTO items (name, price_minor, currency, quantity, description, tax_category_id, category_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING
+                id,
+                name,
+                price_minor,
+                currency,
+                quantity,
+                description,
+                created_at,
+                updated_at,
+                is_active,
+                tax_category_id,
+                category_id,
+                deleted_at
             "#,
             req.name,
-            req.price,
+            req.price.amount_minor,
+            req.price.currency,
             req.quantity,
-            req.description
+            req.description,
+            req.tax_category_id,
+            req.category_id
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(item)
+        if item.quantity != 0 {
+            record_stock_movement(&mut tx, item.id, None, item.quantity, StockMovementReason::Initial).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(item.into())
     }
 
     pub async fn get_item(
         &self,
         id: Uuid,
+        include_deleted: bool,
     ) -> Result<Item, Error> {
         let item = sqlx::query_as!(
-            Item,
+            ItemDB,
             r#"
-            SELECT * FROM items
-            WHERE id = $1
+            SELECT
+                id,
+                name,
+                price_minor,
+                currency,
+                quantity,
+                description,
+                created_at,
+                updated_at,
+                is_active,
+                tax_category_id,
+                category_id,
+                deleted_at
+            FROM items
+            WHERE id = $1 AND ($2 OR deleted_at IS NULL)
             "#,
-            id
+            id,
+            include_deleted
         )
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(item)
+        Ok(item.into())
     }
 
     pub async fn update_item(
@@ -58,74 +106,351 @@ impl ItemRepository {
         id: Uuid,
         req: &UpdateItem
     ) -> Result<Item, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Lock the row so the ledger sees the quantity we are replacing
+        let previous = sqlx::query!(
+            "SELECT quantity FROM items WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
         let item = sqlx::query_as!(
-            Item,
+            ItemDB,
             r#"
             UPDATE items
             SET
                 name = COALESCE($1, name),
-                price = COALESCE($2, price),
-                quantity = COALESCE($3, quantity),
-                description = COALESCE($4, description),
-                is_active = COALESCE($5, is_active),
+                price_minor = COALESCE($2, price_minor),
+                currency = COALESCE($3, currency),
+                quantity = COALESCE($4, quantity),
+                description = COALESCE($5, description),
+                is_active = COALESCE($6, is_active),
+                tax_category_id = CASE WHEN $7 THEN $8 ELSE tax_category_id END,
+                category_id = CASE WHEN $9 THEN $10 ELSE category_id END,
                 updated_at = now()
-            WHERE id = $6
-            RETURNING *
+            WHERE id = $11
+            RETURNING
+                id,
+                name,
+                price_minor,
+                currency,
+                quantity,
+                description,
+                created_at,
+                updated_at,
+                is_active,
+                tax_category_id,
+                category_id,
+                deleted_at
             "#,
             req.name.as_ref(),
-            req.price,
+            req.price.as_ref().map(|price| price.amount_minor),
+            req.price.as_ref().map(|price| price.currency.as_str()),
             req.quantity,
             req.description.as_ref(),
             req.is_active,
+            req.tax_category_id.is_some(),
+            req.tax_category_id.flatten(),
+            req.category_id.is_some(),
+            req.category_id.flatten(),
             id
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(item)
+        let change = item.quantity - previous.quantity;
+        if change != 0 {
+            record_stock_movement(&mut tx, item.id, None, change, StockMovementReason::Adjustment).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(item.into())
     }
 
+    // Soft delete, ordered items keep their row so order lines still point at something.
+    // The purge job only removes items no order refers to.
     pub async fn delete_item(
         &self,
         id: Uuid,
-    ) -> Result<(), Error> {
-        sqlx::query!(
-            "DELETE FROM items WHERE id = $1",
+    ) -> Result<Item, RepoError> {
+        let item = sqlx::query_as!(
+            ItemDB,
+            r#"
+            UPDATE items
+            SET deleted_at = now()
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING
+                id,
+                name,
+                price_minor,
+                currency,
+                quantity,
+                description,
+                created_at,
+                updated_at,
+                is_active,
+                tax_category_id,
+                category_id,
+                deleted_at
+            "#,
             id
         )
-        .execute(&self.pool)
+        .fetch_one(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(item.into())
     }
 
-    pub async fn list_items(&self) -> Result<Vec<Item>, Error> {
-        let items = sqlx::query_as!(
-            Item,
+    pub async fn delete_items(&self, ids: &[Uuid]) -> Result<Vec<BulkDeleteResult>, Error> {
+        let deleted = sqlx::query_scalar!(
             r#"
-            SELECT * FROM items
-            ORDER BY created_at DESC
-            "#
+            UPDATE items
+            SET deleted_at = now()
+            WHERE id = ANY($1) AND deleted_at IS NULL
+            RETURNING id
+            "#,
+            ids
         )
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(items)
+        Ok(BulkDeleteResult::for_ids(ids, &deleted))
+    }
+
+    pub async fn restore_item(
+        &self,
+        id: Uuid,
+    ) -> Result<Item, Error> {
+        let item = sqlx::query_as!(
+            ItemDB,
+            r#"
+            UPDATE items
+            SET deleted_at = NULL, updated_at = now()
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING
+                id,
+                name,
+                price_minor,
+                currency,
+                quantity,
+                description,
+                created_at,
+                updated_at,
+                is_active,
+                tax_category_id,
+                category_id,
+                deleted_at
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(item.into())
+    }
+
+    pub async fn list_items(
+        &self,
+        params: &ListParams,
+        filter: &ItemFilter,
+    ) -> Result<Page<Item>, RepoError> {
+        let page = PageRequest::new(params, ITEM_SORTS)?;
+
+        let mut qb = QueryBuilder::new(
+            "SELECT id, name, price_minor, currency, quantity, description, created_at, updated_at, \
+             is_active, tax_category_id, category_id, deleted_at FROM items WHERE true"
+        );
+        page.push_conditions(&mut qb);
+        if let Some(min) = filter.min_price {
+            qb.push(" AND price_minor >= ").push_bind(min);
+        }
+        if let Some(max) = filter.max_price {
+            qb.push(" AND price_minor <= ").push_bind(max);
+        }
+        if let Some(ref q) = filter.q
+            && !q.trim().is_empty()
+        {
+            qb.push(" AND name ILIKE ").push_bind(contains_pattern(q.trim()));
+        }
+        if let Some(is_active) = filter.is_active {
+            qb.push(" AND is_active = ").push_bind(is_active);
+        }
+        if let Some(category_id) = filter.category_id {
+            qb.push(
+                " AND category_id IN (\
+                 WITH RECURSIVE subtree AS (\
+                 SELECT id FROM categories WHERE id = "
+            )
+            .push_bind(category_id)
+            .push(
+                " UNION ALL \
+                 SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id\
+                 ) SELECT id FROM subtree)"
+            );
+        }
+        let tags = filter.tag_names();
+        if !tags.is_empty() {
+            // Every requested tag has to be on the item
+            let count = tags.len() as i64;
+            qb.push(
+                " AND id IN (\
+                 SELECT it.item_id FROM item_tags it JOIN tags t ON t.id = it.tag_id \
+                 WHERE t.name = ANY("
+            )
+            .push_bind(tags)
+            .push(") GROUP BY it.item_id HAVING COUNT(*) = ")
+            .push_bind(count)
+            .push(")");
+        }
+        page.push_order_and_limit(&mut qb);
+
+        let items: Vec<ItemDB> = qb.build_query_as().fetch_all(&self.pool).await?;
+        let (items, next_cursor) = page.finish(items, |item, column| {
+            let value = match column {
+                "name" => item.name.clone(),
+                "price_minor" => item.price_minor.to_string(),
+                "quantity" => item.quantity.to_string(),
+                _ => item.created_at.to_rfc3339(),
+            };
+            (item.id, value)
+        });
+
+        Ok(Page {
+            results: items.into_iter().map(Item::from).collect(),
+            next_cursor,
+        })
+    }
+
+    // Full-text search over name and description, best match first
+    pub async fn search_items(
+        &self,
+        query: &SearchQuery,
+    ) -> Result<Vec<ItemSearchHit>, RepoError> {
+        let (q, limit) = query.validate().map_err(RepoError::Validation)?;
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                i.id,
+                i.name,
+                i.price_minor,
+                i.currency,
+                i.quantity,
+                i.description,
+                i.created_at,
+                i.updated_at,
+                i.is_active,
+                i.tax_category_id,
+                i.category_id,
+                i.deleted_at,
+                ts_rank(i.search_vector, query) as "rank!",
+                ts_headline(
+                    'english',
+                    translate(i.name || ' ' || coalesce(i.description, ''), chr(2) || chr(3), ''),
+                    query,
+                    'StartSel=' || chr(2) || ', StopSel=' || chr(3) || ', MaxWords=20, MinWords=5'
+                ) as "snippet!"
+            FROM items i, websearch_to_tsquery('english', $1) query
+            WHERE i.search_vector @@ query AND i.deleted_at IS NULL
+            ORDER BY "rank!" DESC, i.id
+            LIMIT $2
+            "#,
+            q,
+            limit as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ItemSearchHit {
+                item: Item::from(ItemDB {
+                    id: row.id,
+                    name: row.name,
+                    price_minor: row.price_minor,
+                    currency: row.currency,
+                    quantity: row.quantity,
+                    description: row.description,
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                    is_active: row.is_active,
+                    tax_category_id: row.tax_category_id,
+                    category_id: row.category_id,
+                    deleted_at: row.deleted_at,
+                }),
+                rank: row.rank,
+                // Matches come back between MATCH_START and MATCH_STOP, the text around them is escaped here
+                snippet: mark_headline(&row.snippet),
+            })
+            .collect())
     }
 
     // Optional: Get only active items
-    pub async fn list_active_items(&self) -> Result<Vec<Item>, Error> {
-        let items = sqlx::query_as!(
-            Item,
+    pub async fn list_active_items(
+        &self,
+        params: &ListParams,
+        filter: &ItemFilter,
+    ) -> Result<Page<Item>, RepoError> {
+        let filter = ItemFilter { is_active: Some(true), ..filter.clone() };
+        self.list_items(params, &filter).await
+    }
+
+    // Get the inventory ledger of an item, oldest first
+    pub async fn get_stock_movements(
+        &self,
+        id: Uuid,
+    ) -> Result<Vec<StockMovement>, Error> {
+        // Make sure the item exists so an unknown id is a 404 rather than an empty list
+        sqlx::query!("SELECT id FROM items WHERE id = $1", id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let movements = sqlx::query_as!(
+            StockMovement,
             r#"
-            SELECT * FROM items
-            WHERE is_active = true
-            ORDER BY created_at DESC
-            "#
+            SELECT
+                id,
+                item_id as "item_id!",
+                order_id,
+                quantity_change,
+                reason as "reason: StockMovementReason",
+                created_at
+            FROM inventory_ledger
+            WHERE item_id = $1
+            ORDER BY created_at ASC
+            "#,
+            id
         )
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(items)
+        Ok(movements)
     }
-}
\ No newline at end of file
+}
+
+// Append a row to the inventory ledger, callers run this in the same transaction as the stock change
+pub async fn record_stock_movement(
+    conn: &mut PgConnection,
+    item_id: Uuid,
+    order_id: Option<Uuid>,
+    quantity_change: i32,
+    reason: StockMovementReason,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO inventory_ledger (item_id, order_id, quantity_change, reason)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        item_id,
+        order_id,
+        quantity_change,
+        reason as StockMovementReason
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
@@ -2,4 +2,14 @@ pub mod items_db;
 pub mod order_db;
 pub mod users_db;
 pub mod db;
-pub mod repo_handler;
\ No newline at end of file
+pub mod repo_handler;
+pub mod error;
+pub mod pricing_db;
+pub mod cart_db;
+pub mod payments_db;
+pub mod shipments_db;
+pub mod purge_db;
+pub mod pagination;
+pub mod catalog_db;
+pub mod bulk_db;
+pub mod transfer_db;
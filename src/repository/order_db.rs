@@ -1,6 +1,22 @@
-use sqlx::{PgPool, Error};
+use std::collections::HashMap;
+use sqlx::{PgPool, PgConnection, Postgres, Transaction, Error, QueryBuilder};
 use uuid::Uuid;
-use crate::models::{Order, OrderDB, CreateOrder, UpdateOrder, OrderStatus, Item};
+use crate::models::{Order, OrderDB, CreateOrder, CreateOrderLine, UpdateOrder, OrderStatus, OrderStatusHistory, OrderLine, OrderLineDB, StockMovementReason, StockShortage, Coupon, PriceBreakdown, QuoteRequest, Quote, OrderDetails, BulkDeleteResult, ListParams, OrderFilter, Page};
+use crate::repository::error::RepoError;
+use crate::repository::items_db::record_stock_movement;
+use crate::repository::pricing_db::find_coupon;
+use crate::repository::payments_db::{capture_in_flight, refund_order};
+use crate::repository::shipments_db::find_shipment;
+use crate::payments::PaymentProvider;
+use crate::money::{Money, DEFAULT_CURRENCY};
+use crate::pricing::{self, PricedLine};
+use crate::repository::pagination::{PageRequest, SortField, SortKind};
+use chrono::Utc;
+
+const ORDER_SORTS: &[SortField] = &[
+    SortField { name: "created_at", column: "created_at", kind: SortKind::Timestamp },
+    SortField { name: "amount", column: "amount_minor", kind: SortKind::Integer },
+];
 
 
 pub struct OrderRepository {
@@ -17,101 +33,82 @@ impl OrderRepository {
     pub async fn create_order(
         &self,
         req: &CreateOrder
-    ) -> Result<Order, Error> {
+    ) -> Result<Order, RepoError> {
         // Start a transaction
         let mut tx = self.pool.begin().await?;
 
+        let final_order = Self::create_order_in_tx(&mut tx, req).await?;
+
+        // Commit transaction
+        tx.commit().await?;
+
+        let items = self.get_order_items(final_order.id).await?;
+    
+        Ok(final_order.with_items(items))
+    }
+
+    // Create an order inside a transaction owned by the caller, so other writes can commit or roll back with it
+    pub async fn create_order_in_tx(
+        tx: &mut Transaction<'_, Postgres>,
+        req: &CreateOrder
+    ) -> Result<OrderDB, RepoError> {
+        let lines = CreateOrderLine::merge(&req.items).map_err(RepoError::Validation)?;
+
+        // The user has to exist and not be soft deleted, FOR SHARE holds off a delete until the order commits
+        sqlx::query!(
+            "SELECT id FROM users WHERE id = $1 AND deleted_at IS NULL FOR SHARE",
+            req.user_id
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+
         // 1. Create the order
         let order = sqlx::query_as!(
             OrderDB,
             r#"
-            INSERT INTO orders (user_id, amount)
-            VALUES ($1, $2)
+            INSERT INTO orders (user_id, subtotal_minor, amount_minor, currency)
+            VALUES ($1, $2, $2, $3)
             RETURNING
                 id,
                 user_id,
-                amount,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
                 status as "status: OrderStatus", -- default calculated in the db as pending
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             "#,
             req.user_id,
-            0.0, // Amount will be calculated
+            0_i64, // Amount and currency will be calculated from the lines
+            DEFAULT_CURRENCY,
         )
-        .fetch_one(&mut *tx)
+        .fetch_one(&mut **tx)
         .await?;
 
-        // 2. Insert order items and calculate total
-        let mut total_amount = 0.0;
-        for item_id in &req.item_ids {
-            // Get item price
-            let item = sqlx::query!(
-                r#"
-                SELECT price FROM items
-                WHERE id = $1 AND is_active = true
-                "#,
-                item_id
-            )
-            .fetch_one(&mut *tx)
-            .await?;
-
-            total_amount += item.price;
-
-            // Insert into order_items junction table
-            sqlx::query!(
-                r#"
-                INSERT INTO order_items (order_id, item_id)
-                VALUES ($1, $2)
-                "#,
-                order.id,
-                item_id
-            )
-            .execute(&mut *tx)
-            .await?;
-        }
+        // 2. Reserve stock and insert order lines
+        let priced_lines = Self::insert_order_lines(tx, order.id, &lines, StockMovementReason::OrderPlaced).await?;
 
-        // 3. Update order with calculated amount
-        let final_order = sqlx::query_as!(
-            OrderDB,
-            r#"
-            UPDATE orders
-            SET amount = $1
-            WHERE id = $2
-            RETURNING
-                id,
-                user_id,
-                amount,
-                status as "status: OrderStatus",
-                created_at,
-                updated_at
-            "#,
-            total_amount,
-            order.id
-        )
-        .fetch_one(&mut *tx)
-        .await?;
+        // 3. Redeem the coupon, if any, and price the order
+        let coupon = match req.coupon_code {
+            Some(ref code) => Some(Self::redeem_coupon(tx, code).await?),
+            None => None,
+        };
+        let breakdown = pricing::price_lines(&priced_lines, coupon.as_ref()).map_err(RepoError::Validation)?;
 
-        // Commit transaction
-        tx.commit().await?;
+        // 4. Update order with calculated amounts
+        let final_order = Self::save_pricing(tx, order.id, &breakdown, coupon.map(|c| c.code)).await?;
 
-        let items = self.get_order_items(final_order.id).await?;
-    
-        Ok(
-            Order {
-                id: final_order.id,
-                user_id: final_order.user_id,
-                items,
-                amount: final_order.amount,
-                status: final_order.status,
-                created_at: final_order.created_at,
-                updated_at: final_order.updated_at,
-            }
-        )
+        Ok(final_order)
     }
 
     pub async fn get_order(
         &self,
         id: Uuid,
+        include_deleted: bool,
     ) -> Result<Order, Error> {
         let order = sqlx::query_as!(
             OrderDB,
@@ -119,76 +116,85 @@ impl OrderRepository {
             SELECT 
                 id,
                 user_id,
-                amount,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
                 status as "status: OrderStatus",
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             FROM orders
-            WHERE id = $1
+            WHERE id = $1 AND ($2 OR deleted_at IS NULL)
             "#,
-            id
+            id,
+            include_deleted
         )
         .fetch_one(&self.pool)
         .await?;
 
         let items = self.get_order_items(order.id).await?;
     
-        Ok(
-            Order {
-                id: order.id,
-                user_id: order.user_id,
-                items,
-                amount: order.amount,
-                status: order.status,
-                created_at: order.created_at,
-                updated_at: order.updated_at,
-            }
-        )
+        Ok(order.with_items(items))
     }
 
     // Get order with item details
     pub async fn get_order_with_items(
         &self,
         id: Uuid,
-    ) -> Result<Order, Error> {
-        // Get the order
-        let order = self.get_order(id).await?;
+        include_deleted: bool,
+    ) -> Result<OrderDetails, Error> {
+        // Order lines carry their own name and price snapshot, so this is the order as stored
+        let order = self.get_order(id, include_deleted).await?;
 
-        // Get associated items
-        let items = sqlx::query_as!(
-            Item,
-            r#"
-            SELECT i.*
-            FROM items i
-            INNER JOIN order_items oi ON i.id = oi.item_id
-            WHERE oi.order_id = $1
-            "#,
-            id
-        )
-        .fetch_all(&self.pool)
-        .await?;
+        let mut conn = self.pool.acquire().await?;
+        let shipment = find_shipment(&mut conn, id).await?;
 
-        Ok(Order {
-            id: order.id,
-            user_id: order.user_id,
-            items,
-            amount: order.amount,
-            status: order.status,
-            created_at: order.created_at,
-            updated_at: order.updated_at,
-        })
+        Ok(OrderDetails { order, shipment })
     }
 
     pub async fn update_order(
         &self,
         id: Uuid,
-        req: &UpdateOrder
-    ) -> Result<Order, Error> {
+        req: &UpdateOrder,
+        provider: &dyn PaymentProvider,
+    ) -> Result<Order, RepoError> {
         let mut tx = self.pool.begin().await?;
 
+        // Lock the order row so concurrent updates see each other's status changes
+        let current = sqlx::query!(
+            r#"
+            SELECT status as "status: OrderStatus", coupon_code
+            FROM orders
+            WHERE id = $1 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let changes_status = req.status.as_ref().is_some_and(|next| *next != current.status);
+        if (req.items.is_some() || changes_status) && capture_in_flight(&mut tx, id).await? {
+            return Err(RepoError::Conflict(format!(
+                "A payment for order {} is in progress, try again once it has been recorded", id
+            )));
+        }
+
+        // Reject illegal transitions before touching anything
+        if let Some(ref next) = req.status {
+            current.status.check_update(next, id).map_err(RepoError::Conflict)?;
+        }
+
         // Update items if provided
-        if let Some(ref item_ids) = req.item_ids {
-            // Delete existing order_items
+        if let Some(ref items) = req.items {
+            let lines = CreateOrderLine::merge(items).map_err(RepoError::Validation)?;
+            current.status.check_items_editable().map_err(RepoError::Conflict)?;
+
+            // Return the old lines to stock, then delete existing order_items
+            Self::release_order_stock(&mut tx, id, StockMovementReason::OrderUpdated).await?;
             sqlx::query!(
                 "DELETE FROM order_items WHERE order_id = $1",
                 id
@@ -196,235 +202,765 @@ impl OrderRepository {
             .execute(&mut *tx)
             .await?;
 
-            // Calculate new amount and insert new items
-            let mut new_amount = 0.0;
-            for item_id in item_ids {
-                let item = sqlx::query!(
-                    r#"
-                    SELECT price FROM items
-                    WHERE id = $1 AND is_active = true
-                    "#,
-                    item_id
-                )
-                .fetch_one(&mut *tx)
-                .await?;
-
-                new_amount += item.price;
-
-                sqlx::query!(
-                    r#"
-                    INSERT INTO order_items (order_id, item_id)
-                    VALUES ($1, $2)
-                    "#,
-                    id,
-                    item_id
-                )
-                .execute(&mut *tx)
-                .await?;
+            // Reserve stock and insert new lines
+            let priced_lines = Self::insert_order_lines(&mut tx, id, &lines, StockMovementReason::OrderUpdated).await?;
+
+            // Reprice with the coupon already on the order, it was checked and counted when it was redeemed
+            let coupon = match current.coupon_code {
+                Some(ref code) => Some(find_coupon(&mut tx, code).await?),
+                None => None,
+            };
+            let breakdown = pricing::price_lines(&priced_lines, coupon.as_ref()).map_err(RepoError::Validation)?;
+            Self::save_pricing(&mut tx, id, &breakdown, current.coupon_code.clone()).await?;
+        }
+
+        if req.status == Some(OrderStatus::Cancelled) && current.status != OrderStatus::Cancelled {
+            // A paid order gets its money back first, nothing else has changed yet when it is Paid
+            if current.status == OrderStatus::Paid {
+                match refund_order(&mut tx, id, provider).await {
+                    Ok(_) => {}
+                    Err(RepoError::PaymentDeclined(reason)) => {
+                        // Keep the declined refund attempt, the order stays Paid
+                        tx.commit().await?;
+                        return Err(RepoError::PaymentDeclined(reason));
+                    }
+                    Err(e) => return Err(e),
+                }
             }
 
-            // Update amount
-            sqlx::query!(
-                "UPDATE orders SET amount = $1, updated_at = now() WHERE id = $2",
-                new_amount,
-                id
-            )
-            .execute(&mut *tx)
-            .await?;
+            // Cancelling gives the reserved stock back
+            Self::release_order_stock(&mut tx, id, StockMovementReason::OrderCancelled).await?;
         }
 
         // Update the order
+        let next = req.status.clone().unwrap_or_else(|| current.status.clone());
+        let order = Self::set_status(&mut tx, id, &current.status, &next).await?;
+
+        tx.commit().await?;
+
+        // Convert OrderDB to Order by fetching items for each
+        let items = self.get_order_items(order.id).await?;
+    
+        Ok(order.with_items(items))
+    }
+
+    // Moves the order to a new status and records the transition, the caller has already checked it is allowed
+    pub async fn set_status(
+        tx: &mut Transaction<'_, Postgres>,
+        id: Uuid,
+        from: &OrderStatus,
+        to: &OrderStatus,
+    ) -> Result<OrderDB, Error> {
         let order = sqlx::query_as!(
             OrderDB,
             r#"
             UPDATE orders
             SET
-                status = COALESCE($1, status),
+                status = $1,
                 updated_at = now()
             WHERE id = $2
             RETURNING
                 id,
                 user_id,
-                amount,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
                 status as "status: OrderStatus",
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             "#,
-            req.status.as_ref() as Option<&OrderStatus>,
+            to.clone() as OrderStatus,
             id
         )
-        .fetch_one(&mut *tx)
+        .fetch_one(&mut **tx)
         .await?;
 
-        tx.commit().await?;
+        // Record the transition
+        if from != to {
+            sqlx::query!(
+                r#"
+                INSERT INTO order_status_history (order_id, from_status, to_status)
+                VALUES ($1, $2, $3)
+                "#,
+                id,
+                from.clone() as OrderStatus,
+                to.clone() as OrderStatus
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
 
-        // Convert OrderDB to Order by fetching items for each
-        let items = self.get_order_items(order.id).await?;
-    
-        Ok(
-            Order {
-                id: order.id,
-                user_id: order.user_id,
-                items,
-                amount: order.amount,
-                status: order.status,
-                created_at: order.created_at,
-                updated_at: order.updated_at,
+        Ok(order)
+    }
+
+    // Price a cart exactly like create_order would, without reserving stock or redeeming the coupon
+    pub async fn quote(
+        &self,
+        req: &QuoteRequest
+    ) -> Result<Quote, RepoError> {
+        let lines = CreateOrderLine::merge(&req.items).map_err(RepoError::Validation)?;
+        let item_ids: Vec<Uuid> = lines.iter().map(|line| line.item_id).collect();
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                i.id,
+                i.name,
+                i.price_minor,
+                i.currency,
+                COALESCE(t.rate_bps, 0) as "tax_rate_bps!"
+            FROM items i
+            LEFT JOIN tax_categories t ON t.id = i.tax_category_id
+            WHERE i.id = ANY($1) AND i.is_active = true AND i.deleted_at IS NULL
+            "#,
+            &item_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut items = Vec::with_capacity(lines.len());
+        let mut priced_lines = Vec::with_capacity(lines.len());
+        for line in &lines {
+            let row = rows
+                .iter()
+                .find(|row| row.id == line.item_id)
+                .ok_or(RepoError::NotFound)?;
+            let unit_price = Money::from_stored(row.price_minor, row.currency.clone());
+
+            priced_lines.push(PricedLine {
+                unit_price: unit_price.clone(),
+                quantity: line.quantity,
+                tax_rate_bps: row.tax_rate_bps,
+            });
+            items.push(OrderLine {
+                item_id: row.id,
+                name: row.name.clone(),
+                quantity: line.quantity,
+                unit_price,
+                tax_rate_bps: row.tax_rate_bps,
+            });
+        }
+
+        let coupon = match req.coupon_code {
+            Some(ref code) => {
+                let mut conn = self.pool.acquire().await?;
+                let coupon = match find_coupon(&mut conn, code).await {
+                    Ok(coupon) => coupon,
+                    Err(Error::RowNotFound) => {
+                        return Err(RepoError::Validation(format!("Coupon {} does not exist", code)));
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                pricing::check_coupon(&coupon, Utc::now()).map_err(RepoError::Validation)?;
+                Some(coupon)
             }
+            None => None,
+        };
+
+        let breakdown = pricing::price_lines(&priced_lines, coupon.as_ref()).map_err(RepoError::Validation)?;
+
+        Ok(Quote {
+            items,
+            coupon_code: coupon.map(|c| c.code),
+            breakdown,
+        })
+    }
+
+    // Get the status transitions of an order, oldest first
+    pub async fn get_status_history(
+        &self,
+        order_id: Uuid,
+    ) -> Result<Vec<OrderStatusHistory>, Error> {
+        // Make sure the order exists so an unknown id is a 404 rather than an empty list
+        sqlx::query!("SELECT id FROM orders WHERE id = $1", order_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let history = sqlx::query_as!(
+            OrderStatusHistory,
+            r#"
+            SELECT
+                id,
+                order_id,
+                from_status as "from_status: OrderStatus",
+                to_status as "to_status: OrderStatus",
+                changed_at
+            FROM order_status_history
+            WHERE order_id = $1
+            ORDER BY changed_at ASC
+            "#,
+            order_id
         )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(history)
     }
 
+    // Soft delete, a Pending order gives its reserved stock back so nothing is held by an order nobody sees
     pub async fn delete_order(
         &self,
         id: Uuid,
-    ) -> Result<(), Error> {
-        // order_items will be deleted automatically due to CASCADE
-        sqlx::query!(
-            "DELETE FROM orders WHERE id = $1",
+    ) -> Result<Order, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let order = sqlx::query_as!(
+            OrderDB,
+            r#"
+            UPDATE orders
+            SET deleted_at = now()
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING
+                id,
+                user_id,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
+                status as "status: OrderStatus",
+                created_at,
+                updated_at,
+                deleted_at
+            "#,
             id
         )
-        .execute(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(())
+        if order.status == OrderStatus::Pending {
+            Self::release_order_stock(&mut tx, order.id, StockMovementReason::OrderDeleted).await?;
+        }
+
+        tx.commit().await?;
+
+        let items = self.get_order_items(order.id).await?;
+
+        Ok(order.with_items(items))
     }
 
-    pub async fn list_orders(&self) -> Result<Vec<Order>, Error> {
-        let orders_db = sqlx::query_as!(
+    pub async fn delete_orders(&self, ids: &[Uuid]) -> Result<Vec<BulkDeleteResult>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let deleted = sqlx::query!(
+            r#"
+            UPDATE orders
+            SET deleted_at = now()
+            WHERE id = ANY($1) AND deleted_at IS NULL
+            RETURNING id, status as "status: OrderStatus"
+            "#,
+            ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for order in deleted.iter().filter(|order| order.status == OrderStatus::Pending) {
+            Self::release_order_stock(&mut tx, order.id, StockMovementReason::OrderDeleted).await?;
+        }
+
+        tx.commit().await?;
+
+        let deleted: Vec<Uuid> = deleted.into_iter().map(|order| order.id).collect();
+        Ok(BulkDeleteResult::for_ids(ids, &deleted))
+    }
+
+    // A Pending order takes its stock again, it stays deleted when the stock is no longer there
+    pub async fn restore_order(
+        &self,
+        id: Uuid,
+    ) -> Result<Order, RepoError> {
+        let mut tx = self.pool.begin().await?;
+
+        let order = sqlx::query_as!(
             OrderDB,
             r#"
-            SELECT 
+            UPDATE orders
+            SET deleted_at = NULL, updated_at = now()
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING
                 id,
                 user_id,
-                amount,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
                 status as "status: OrderStatus",
                 created_at,
-                updated_at
-            FROM orders
-            ORDER BY created_at DESC
-            "#
+                updated_at,
+                deleted_at
+            "#,
+            id
         )
-        .fetch_all(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        // Convert OrderDB to Order by fetching items for each
-        let mut orders = Vec::new();
-        for order_db in orders_db {
-            let items = self.get_order_items(order_db.id).await?;
-            orders.push(Order {
-                id: order_db.id,
-                user_id: order_db.user_id,
-                items,
-                amount: order_db.amount,
-                status: order_db.status,
-                created_at: order_db.created_at,
-                updated_at: order_db.updated_at,
-            });
+        if order.status == OrderStatus::Pending {
+            Self::reserve_order_stock(&mut tx, order.id, StockMovementReason::OrderRestored).await?;
         }
-    
-        Ok(orders)
+
+        tx.commit().await?;
+
+        let items = self.get_order_items(order.id).await?;
+
+        Ok(order.with_items(items))
     }
 
-    // Get orders by user
-    pub async fn get_orders_by_user(
+    pub async fn list_orders(
         &self,
-        user_id: Uuid
-    ) -> Result<Vec<Order>, Error> {
-        let orders_db = sqlx::query_as!(
-            OrderDB,
+        params: &ListParams,
+        filter: &OrderFilter,
+        expand_items: bool,
+    ) -> Result<Page<Order>, RepoError> {
+        let page = PageRequest::new(params, ORDER_SORTS)?;
+        let statuses = filter.statuses().map_err(RepoError::Validation)?;
+
+        let mut qb = QueryBuilder::new(
             r#"
             SELECT
                 id,
                 user_id,
-                amount,
-                status as "status: OrderStatus",
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
+                status,
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             FROM orders
-            WHERE user_id = $1
-            ORDER BY created_at DESC
-            "#,
-            user_id
-        )
-        .fetch_all(&self.pool)
-        .await?;
-    
-        // Convert OrderDB to Order by fetching items for each
-        let mut orders = Vec::new();
-        for order_db in orders_db {
-            let items = self.get_order_items(order_db.id).await?;
-            orders.push(Order {
-                id: order_db.id,
-                user_id: order_db.user_id,
-                items,
-                amount: order_db.amount,
-                status: order_db.status,
-                created_at: order_db.created_at,
-                updated_at: order_db.updated_at,
-            });
+            WHERE true"#
+        );
+        page.push_conditions(&mut qb);
+        if let Some(min) = filter.min_amount {
+            qb.push(" AND amount_minor >= ").push_bind(min);
         }
-    
-        Ok(orders)
+        if let Some(max) = filter.max_amount {
+            qb.push(" AND amount_minor <= ").push_bind(max);
+        }
+        if !statuses.is_empty() {
+            let names: Vec<String> = statuses.iter().map(|status| format!("{:?}", status)).collect();
+            qb.push(" AND status::text = ANY(").push_bind(names).push(")");
+        }
+        if let Some(user_id) = filter.user_id {
+            qb.push(" AND user_id = ").push_bind(user_id);
+        }
+        page.push_order_and_limit(&mut qb);
+
+        let orders_db: Vec<OrderDB> = qb.build_query_as().fetch_all(&self.pool).await?;
+        let (orders_db, next_cursor) = page.finish(orders_db, |order, column| {
+            let value = match column {
+                "amount_minor" => order.amount_minor.to_string(),
+                _ => order.created_at.to_rfc3339(),
+            };
+            (order.id, value)
+        });
+
+        let mut conn = self.pool.acquire().await?;
+        let orders = attach_order_lines(&mut conn, orders_db, expand_items).await?;
+
+        Ok(Page { results: orders, next_cursor })
+    }
+
+    // Get orders by user
+    pub async fn get_orders_by_user(
+        &self,
+        user_id: Uuid,
+        params: &ListParams,
+        filter: &OrderFilter,
+        expand_items: bool,
+    ) -> Result<Page<Order>, RepoError> {
+        let filter = OrderFilter { user_id: Some(user_id), ..filter.clone() };
+        self.list_orders(params, &filter, expand_items).await
     }
     
     // Helper method to get items for an order
-    async fn get_order_items(&self, order_id: Uuid) -> Result<Vec<Item>, Error> {
+    async fn get_order_items(&self, order_id: Uuid) -> Result<Vec<OrderLine>, Error> {
         let items = sqlx::query_as!(
-            Item,
+            OrderLineDB,
             r#"
-            SELECT i.*
-            FROM items i
-            INNER JOIN order_items oi ON i.id = oi.item_id
-            WHERE oi.order_id = $1
+            SELECT item_id, name, quantity, unit_price_minor, currency, tax_rate_bps
+            FROM order_items
+            WHERE order_id = $1
             "#,
             order_id
         )
         .fetch_all(&self.pool)
         .await?;
     
-        Ok(items)
+        Ok(items.into_iter().map(OrderLine::from).collect())
     }
 
-    // Get orders by status
-    pub async fn get_orders_by_status(
-        &self,
-        status: OrderStatus
-    ) -> Result<Vec<Order>, Error> {
-        let orders_db = sqlx::query_as!(
+    // Helper method to reserve stock and write order lines inside a transaction, returns the lines for pricing
+    async fn insert_order_lines(
+        tx: &mut Transaction<'_, Postgres>,
+        order_id: Uuid,
+        lines: &[CreateOrderLine],
+        reason: StockMovementReason,
+    ) -> Result<Vec<PricedLine>, RepoError> {
+        let item_ids: Vec<Uuid> = lines.iter().map(|line| line.item_id).collect();
+
+        // Lock every item row up front, in id order so concurrent orders can't deadlock
+        let stock = sqlx::query!(
+            r#"
+            SELECT
+                i.id,
+                i.name,
+                i.price_minor,
+                i.currency,
+                i.quantity,
+                COALESCE(t.rate_bps, 0) as "tax_rate_bps!"
+            FROM items i
+            LEFT JOIN tax_categories t ON t.id = i.tax_category_id
+            WHERE i.id = ANY($1) AND i.is_active = true AND i.deleted_at IS NULL
+            ORDER BY i.id
+            FOR UPDATE OF i
+            "#,
+            &item_ids
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        // Check every line before writing anything so the caller gets the full list of short items
+        let mut priced_lines = Vec::with_capacity(lines.len());
+        let mut shortages = Vec::new();
+        for line in lines {
+            let item = stock
+                .iter()
+                .find(|row| row.id == line.item_id)
+                .ok_or(RepoError::NotFound)?;
+
+            if item.quantity < line.quantity {
+                shortages.push(StockShortage {
+                    item_id: line.item_id,
+                    requested: line.quantity,
+                    available: item.quantity,
+                });
+            }
+            priced_lines.push(PricedLine {
+                unit_price: Money::from_stored(item.price_minor, item.currency.clone()),
+                quantity: line.quantity,
+                tax_rate_bps: item.tax_rate_bps,
+            });
+        }
+
+        // Rejects orders that mix currencies
+        Money::total(priced_lines.iter().map(|line| (&line.unit_price, line.quantity)))
+            .map_err(RepoError::Validation)?;
+
+        if !shortages.is_empty() {
+            return Err(RepoError::InsufficientStock(shortages));
+        }
+
+        for (line, priced) in lines.iter().zip(&priced_lines) {
+            let name = stock
+                .iter()
+                .find(|row| row.id == line.item_id)
+                .map(|row| row.name.as_str())
+                .unwrap_or_default();
+
+            sqlx::query!(
+                r#"
+                UPDATE items
+                SET quantity = quantity - $1, updated_at = now()
+                WHERE id = $2
+                "#,
+                line.quantity,
+                line.item_id
+            )
+            .execute(&mut **tx)
+            .await?;
+
+            // Insert into order_items junction table with the name, price and tax rate as they are right now
+            sqlx::query!(
+                r#"
+                INSERT INTO order_items (order_id, item_id, name, quantity, unit_price_minor, currency, tax_rate_bps)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+                order_id,
+                line.item_id,
+                name,
+                line.quantity,
+                priced.unit_price.amount_minor,
+                priced.unit_price.currency,
+                priced.tax_rate_bps
+            )
+            .execute(&mut **tx)
+            .await?;
+
+            record_stock_movement(tx, line.item_id, Some(order_id), -line.quantity, reason.clone()).await?;
+        }
+
+        Ok(priced_lines)
+    }
+
+    // Helper method to check a coupon and count one use of it, inside a transaction
+    async fn redeem_coupon(
+        tx: &mut Transaction<'_, Postgres>,
+        code: &str,
+    ) -> Result<Coupon, RepoError> {
+        let coupon = match find_coupon(tx, code).await {
+            Ok(coupon) => coupon,
+            Err(Error::RowNotFound) => {
+                return Err(RepoError::Validation(format!("Coupon {} does not exist", code)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        pricing::check_coupon(&coupon, Utc::now()).map_err(RepoError::Validation)?;
+
+        // The usage limit is checked again here so two orders can't both take the last use
+        let redeemed = sqlx::query!(
+            r#"
+            UPDATE coupons
+            SET times_used = times_used + 1, updated_at = now()
+            WHERE id = $1 AND (usage_limit IS NULL OR times_used < usage_limit)
+            "#,
+            coupon.id
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        if redeemed.rows_affected() == 0 {
+            return Err(RepoError::Validation(format!("Coupon {} has reached its usage limit", coupon.code)));
+        }
+
+        Ok(coupon)
+    }
+
+    // Helper method to write a price breakdown onto an order, inside a transaction
+    async fn save_pricing(
+        tx: &mut Transaction<'_, Postgres>,
+        order_id: Uuid,
+        breakdown: &PriceBreakdown,
+        coupon_code: Option<String>,
+    ) -> Result<OrderDB, Error> {
+        let order = sqlx::query_as!(
             OrderDB,
             r#"
-            SELECT 
+            UPDATE orders
+            SET
+                subtotal_minor = $1,
+                discount_minor = $2,
+                tax_minor = $3,
+                amount_minor = $4,
+                currency = $5,
+                coupon_code = $6,
+                updated_at = now()
+            WHERE id = $7
+            RETURNING
                 id,
                 user_id,
-                amount,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
                 status as "status: OrderStatus",
                 created_at,
-                updated_at
-            FROM orders
-            WHERE status = $1
-            ORDER BY created_at DESC
+                updated_at,
+                deleted_at
             "#,
-            status as OrderStatus
+            breakdown.subtotal.amount_minor,
+            breakdown.discount.amount_minor,
+            breakdown.tax.amount_minor,
+            breakdown.total.amount_minor,
+            breakdown.total.currency,
+            coupon_code,
+            order_id
         )
-        .fetch_all(&self.pool)
+        .fetch_one(&mut **tx)
         .await?;
 
-        // Convert OrderDB to Order by fetching items for each
-        let mut orders = Vec::new();
-        for order_db in orders_db {
-            let items = self.get_order_items(order_db.id).await?;
-            orders.push(Order {
-                id: order_db.id,
-                user_id: order_db.user_id,
-                items,
-                amount: order_db.amount,
-                status: order_db.status,
-                created_at: order_db.created_at,
-                updated_at: order_db.updated_at,
-            });
+        Ok(order)
+    }
+
+    // Helper method to put the stock held by an order's lines back, inside a transaction
+    async fn release_order_stock(
+        tx: &mut Transaction<'_, Postgres>,
+        order_id: Uuid,
+        reason: StockMovementReason,
+    ) -> Result<(), Error> {
+        // Same lock order as insert_order_lines
+        sqlx::query!(
+            r#"
+            SELECT i.id
+            FROM items i
+            INNER JOIN order_items oi ON i.id = oi.item_id
+            WHERE oi.order_id = $1
+            ORDER BY i.id
+            FOR UPDATE OF i
+            "#,
+            order_id
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE items i
+            SET quantity = i.quantity + oi.quantity, updated_at = now()
+            FROM order_items oi
+            WHERE oi.order_id = $1 AND oi.item_id = i.id
+            "#,
+            order_id
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO inventory_ledger (item_id, order_id, quantity_change, reason)
+            SELECT item_id, order_id, quantity, $2
+            FROM order_items
+            WHERE order_id = $1
+            "#,
+            order_id,
+            reason as StockMovementReason
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    // Helper method to take the stock for an order's existing lines again, inside a transaction
+    async fn reserve_order_stock(
+        tx: &mut Transaction<'_, Postgres>,
+        order_id: Uuid,
+        reason: StockMovementReason,
+    ) -> Result<(), RepoError> {
+        // Same lock order as insert_order_lines
+        let stock = sqlx::query!(
+            r#"
+            SELECT i.id, i.quantity as available, oi.quantity as requested
+            FROM items i
+            INNER JOIN order_items oi ON i.id = oi.item_id
+            WHERE oi.order_id = $1
+            ORDER BY i.id
+            FOR UPDATE OF i
+            "#,
+            order_id
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        let shortages: Vec<StockShortage> = stock
+            .iter()
+            .filter(|row| row.available < row.requested)
+            .map(|row| StockShortage {
+                item_id: row.id,
+                requested: row.requested,
+                available: row.available,
+            })
+            .collect();
+        if !shortages.is_empty() {
+            return Err(RepoError::InsufficientStock(shortages));
         }
 
-        Ok(orders)
+        sqlx::query!(
+            r#"
+            UPDATE items i
+            SET quantity = i.quantity - oi.quantity, updated_at = now()
+            FROM order_items oi
+            WHERE oi.order_id = $1 AND oi.item_id = i.id
+            "#,
+            order_id
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO inventory_ledger (item_id, order_id, quantity_change, reason)
+            SELECT item_id, order_id, -quantity, $2
+            FROM order_items
+            WHERE order_id = $1
+            "#,
+            order_id,
+            reason as StockMovementReason
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
     }
+
+    // Get orders by status
+    pub async fn get_orders_by_status(
+        &self,
+        status: OrderStatus,
+        params: &ListParams,
+        filter: &OrderFilter,
+        expand_items: bool,
+    ) -> Result<Page<Order>, RepoError> {
+        let filter = OrderFilter { status: Some(format!("{:?}", status)), ..filter.clone() };
+        self.list_orders(params, &filter, expand_items).await
+    }
+}
+
+// Lines of many orders in one query, keyed by order id
+pub async fn load_order_lines(
+    conn: &mut PgConnection,
+    order_ids: &[Uuid],
+) -> Result<HashMap<Uuid, Vec<OrderLine>>, Error> {
+    if order_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT order_id, item_id, name, quantity, unit_price_minor, currency, tax_rate_bps
+        FROM order_items
+        WHERE order_id = ANY($1)
+        "#,
+        order_ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut lines: HashMap<Uuid, Vec<OrderLine>> = HashMap::with_capacity(order_ids.len());
+    for row in rows {
+        lines.entry(row.order_id).or_default().push(OrderLine::from(OrderLineDB {
+            item_id: row.item_id,
+            name: row.name,
+            quantity: row.quantity,
+            unit_price_minor: row.unit_price_minor,
+            currency: row.currency,
+            tax_rate_bps: row.tax_rate_bps,
+        }));
+    }
+
+    Ok(lines)
+}
+
+// Turns order rows into API orders, loading all their lines with one query when expand_items is set
+pub async fn attach_order_lines(
+    conn: &mut PgConnection,
+    orders_db: Vec<OrderDB>,
+    expand_items: bool,
+) -> Result<Vec<Order>, Error> {
+    if !expand_items {
+        return Ok(orders_db.into_iter().map(OrderDB::without_items).collect());
+    }
+
+    let ids: Vec<Uuid> = orders_db.iter().map(|order| order.id).collect();
+    let mut lines = load_order_lines(conn, &ids).await?;
+
+    Ok(orders_db
+        .into_iter()
+        .map(|order| {
+            let items = lines.remove(&order.id).unwrap_or_default();
+            order.with_items(items)
+        })
+        .collect())
 }
 
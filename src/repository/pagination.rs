@@ -0,0 +1,304 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+use crate::models::{ListParams, SortDirection};
+use crate::repository::error::RepoError;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortKind {
+    Timestamp,
+    Text,
+    Integer,
+}
+
+// A field clients can sort by and the column behind it
+#[derive(Debug)]
+pub struct SortField {
+    pub name: &'static str,
+    pub column: &'static str,
+    pub kind: SortKind,
+}
+
+// Position of the last row of a page, handed out hex encoded so clients treat it as opaque
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    value: String,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        json.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn decode(raw: &str) -> Option<Cursor> {
+        if !raw.len().is_multiple_of(2) || !raw.is_ascii() {
+            return None;
+        }
+        let bytes: Option<Vec<u8>> = (0..raw.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&raw[i..i + 2], 16).ok())
+            .collect();
+        serde_json::from_slice(&bytes?).ok()
+    }
+}
+
+// Cursor value parsed into the type of the sort column so it binds as a real parameter
+#[derive(Debug, Clone)]
+enum CursorValue {
+    Timestamp(DateTime<Utc>),
+    Text(String),
+    Integer(i64),
+}
+
+// Validated limit, sort and cursor for one list query
+#[derive(Debug)]
+pub struct PageRequest {
+    limit: i64,
+    field: &'static SortField,
+    direction: SortDirection,
+    after: Option<(CursorValue, Uuid)>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    include_deleted: bool,
+}
+
+impl PageRequest {
+    // fields[0] is the default sort
+    pub fn new(params: &ListParams, fields: &'static [SortField]) -> Result<Self, RepoError> {
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(RepoError::Validation(format!("limit must be between 1 and {}", MAX_LIMIT)));
+        }
+
+        let field = match params.sort {
+            None => &fields[0],
+            Some(ref name) => fields.iter().find(|f| f.name == name).ok_or_else(|| {
+                let names: Vec<&str> = fields.iter().map(|f| f.name).collect();
+                RepoError::Validation(format!("sort must be one of {}", names.join(", ")))
+            })?,
+        };
+
+        let after = match params.cursor {
+            None => None,
+            Some(ref raw) => {
+                let invalid = || RepoError::Validation("Invalid cursor".to_string());
+                let cursor = Cursor::decode(raw).ok_or_else(invalid)?;
+                if cursor.sort != field.name {
+                    return Err(RepoError::Validation(format!(
+                        "Cursor was issued for sort {}, not {}", cursor.sort, field.name
+                    )));
+                }
+                let value = match field.kind {
+                    SortKind::Timestamp => CursorValue::Timestamp(
+                        DateTime::parse_from_rfc3339(&cursor.value).map_err(|_| invalid())?.with_timezone(&Utc)
+                    ),
+                    SortKind::Text => CursorValue::Text(cursor.value),
+                    SortKind::Integer => CursorValue::Integer(cursor.value.parse().map_err(|_| invalid())?),
+                };
+                Some((value, cursor.id))
+            }
+        };
+
+        Ok(Self {
+            limit,
+            field,
+            direction: params.direction.unwrap_or_default(),
+            after,
+            created_after: params.created_after,
+            created_before: params.created_before,
+            include_deleted: params.include_deleted,
+        })
+    }
+
+    // Appends the created_at range, soft delete and cursor conditions, the query must already have a WHERE
+    pub fn push_conditions(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        if !self.include_deleted {
+            qb.push(" AND deleted_at IS NULL");
+        }
+        if let Some(after) = self.created_after {
+            qb.push(" AND created_at >= ").push_bind(after);
+        }
+        if let Some(before) = self.created_before {
+            qb.push(" AND created_at < ").push_bind(before);
+        }
+        if let Some((ref value, id)) = self.after {
+            let op = match self.direction {
+                SortDirection::Asc => ">",
+                SortDirection::Desc => "<",
+            };
+            // Row comparison with id as tie breaker, so equal sort values never skip or repeat rows
+            qb.push(format_args!(" AND ({}, id) {} (", self.field.column, op));
+            match value {
+                CursorValue::Timestamp(v) => qb.push_bind(*v),
+                CursorValue::Text(v) => qb.push_bind(v.clone()),
+                CursorValue::Integer(v) => qb.push_bind(*v),
+            };
+            qb.push(", ").push_bind(id).push(")");
+        }
+    }
+
+    // Fetches one row more than the limit to know whether there is a next page
+    pub fn push_order_and_limit(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        let direction = match self.direction {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        };
+        qb.push(format_args!(" ORDER BY {} {}, id {}", self.field.column, direction, direction));
+        qb.push(" LIMIT ").push_bind(self.limit + 1);
+    }
+
+    // Trims the extra row and builds next_cursor from the last row kept.
+    // key returns the id and the sort column value of a row as text.
+    pub fn finish<T, K>(&self, mut rows: Vec<T>, key: K) -> (Vec<T>, Option<String>)
+    where
+        K: Fn(&T, &str) -> (Uuid, String),
+    {
+        if rows.len() as i64 <= self.limit {
+            return (rows, None);
+        }
+        rows.truncate(self.limit as usize);
+        let next_cursor = rows.last().map(|row| {
+            let (id, value) = key(row, self.field.column);
+            Cursor { sort: self.field.name.to_string(), value, id }.encode()
+        });
+        (rows, next_cursor)
+    }
+}
+
+// Escapes LIKE wildcards so user input only ever matches literally
+pub fn contains_pattern(q: &str) -> String {
+    let escaped = q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &[SortField] = &[
+        SortField { name: "created_at", column: "created_at", kind: SortKind::Timestamp },
+        SortField { name: "name", column: "name", kind: SortKind::Text },
+        SortField { name: "price", column: "price_minor", kind: SortKind::Integer },
+    ];
+
+    fn params(sort: Option<&str>, cursor: Option<String>) -> ListParams {
+        ListParams {
+            sort: sort.map(str::to_string),
+            cursor,
+            ..ListParams::default()
+        }
+    }
+
+    fn cursor(sort: &str, value: &str) -> String {
+        Cursor { sort: sort.to_string(), value: value.to_string(), id: Uuid::nil() }.encode()
+    }
+
+    fn rejected(params: &ListParams) -> String {
+        match PageRequest::new(params, FIELDS) {
+            Err(RepoError::Validation(msg)) => msg,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let id = Uuid::new_v4();
+        let encoded = Cursor { sort: "name".to_string(), value: "wid\"get ü".to_string(), id }.encode();
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let decoded = Cursor::decode(&encoded).unwrap();
+        assert_eq!((decoded.sort.as_str(), decoded.value.as_str(), decoded.id), ("name", "wid\"get ü", id));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        let valid = cursor("created_at", "2026-01-01T00:00:00Z");
+        for raw in [
+            "".to_string(),
+            "abc".to_string(),
+            "zz".to_string(),
+            "éé".to_string(),
+            valid[..valid.len() - 2].to_string(),
+            "7b7d".to_string(), // {}
+        ] {
+            assert!(Cursor::decode(&raw).is_none(), "{:?}", raw);
+            assert_eq!(rejected(&params(None, Some(raw))), "Invalid cursor");
+        }
+        assert!(PageRequest::new(&params(None, Some(valid)), FIELDS).is_ok());
+    }
+
+    #[test]
+    fn tampered_cursors_are_rejected() {
+        // Issued for one sort and replayed with another
+        let by_name = cursor("name", "widget");
+        assert!(rejected(&params(Some("price"), Some(by_name))).contains("issued for sort name"));
+
+        // Values edited into something the sort column can't hold
+        assert_eq!(rejected(&params(None, Some(cursor("created_at", "yesterday")))), "Invalid cursor");
+        assert_eq!(rejected(&params(Some("price"), Some(cursor("price", "12.50")))), "Invalid cursor");
+        assert!(PageRequest::new(&params(Some("price"), Some(cursor("price", "1250"))), FIELDS).is_ok());
+    }
+
+    #[test]
+    fn limit_bounds() {
+        let with_limit = |limit| ListParams { limit: Some(limit), ..ListParams::default() };
+        assert_eq!(PageRequest::new(&ListParams::default(), FIELDS).unwrap().limit, DEFAULT_LIMIT);
+        assert_eq!(PageRequest::new(&with_limit(1), FIELDS).unwrap().limit, 1);
+        assert_eq!(PageRequest::new(&with_limit(MAX_LIMIT), FIELDS).unwrap().limit, MAX_LIMIT);
+        for limit in [0, -1, MAX_LIMIT + 1] {
+            assert_eq!(rejected(&with_limit(limit)), format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+    }
+
+    #[test]
+    fn sort_fields() {
+        assert_eq!(PageRequest::new(&params(None, None), FIELDS).unwrap().field.name, "created_at");
+        assert_eq!(PageRequest::new(&params(Some("price"), None), FIELDS).unwrap().field.column, "price_minor");
+        assert_eq!(rejected(&params(Some("price_minor"), None)), "sort must be one of created_at, name, price");
+        assert_eq!(rejected(&params(Some("Name"), None)), "sort must be one of created_at, name, price");
+    }
+
+    #[test]
+    fn order_and_limit_sql() {
+        let page = PageRequest::new(&params(Some("price"), Some(cursor("price", "10"))), FIELDS).unwrap();
+        let mut qb = QueryBuilder::new("SELECT * FROM items WHERE true");
+        page.push_conditions(&mut qb);
+        page.push_order_and_limit(&mut qb);
+        assert_eq!(
+            qb.sql(),
+            "SELECT * FROM items WHERE true AND deleted_at IS NULL AND (price_minor, id) < ($1, $2) \
+             ORDER BY price_minor DESC, id DESC LIMIT $3"
+        );
+    }
+
+    #[test]
+    fn finish_only_hands_out_a_cursor_when_there_is_more() {
+        let page = PageRequest::new(&ListParams { limit: Some(2), ..params(Some("name"), None) }, FIELDS).unwrap();
+        let key = |row: &(Uuid, &str), _: &str| (row.0, row.1.to_string());
+
+        let (rows, next) = page.finish(vec![(Uuid::nil(), "a"), (Uuid::nil(), "b")], key);
+        assert_eq!((rows.len(), next), (2, None));
+
+        let last = Uuid::new_v4();
+        let (rows, next) = page.finish(vec![(Uuid::nil(), "a"), (last, "b"), (Uuid::nil(), "c")], key);
+        assert_eq!(rows.len(), 2);
+        let next = Cursor::decode(&next.unwrap()).unwrap();
+        assert_eq!((next.sort.as_str(), next.value.as_str(), next.id), ("name", "b", last));
+    }
+
+    #[test]
+    fn contains_pattern_escapes_wildcards() {
+        assert_eq!(contains_pattern("widget"), "%widget%");
+        assert_eq!(contains_pattern("50%_off"), "%50\\%\\_off%");
+        assert_eq!(contains_pattern("a\\b"), "%a\\\\b%");
+        assert_eq!(contains_pattern("\\%"), "%\\\\\\%%");
+        assert_eq!(contains_pattern(""), "%%");
+    }
+}
@@ -0,0 +1,344 @@
+use sqlx::{PgPool, PgConnection, Error};
+use uuid::Uuid;
+use crate::models::{Payment, PaymentDB, PaymentKind, PaymentStatus, PayOrder, OrderStatus};
+use crate::money::Money;
+use crate::payments::{PaymentProvider, ProviderOutcome};
+use crate::repository::error::RepoError;
+use crate::repository::order_db::OrderRepository;
+
+pub struct PaymentRepository {
+    pool: PgPool
+}
+
+impl PaymentRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    // Captures the order total and marks the order Paid, a declined attempt is still recorded.
+    // No transaction is open while the provider is called: a Pending payment with an idempotency key
+    // is committed first, and the outcome is recorded in a second transaction once the provider answers.
+    pub async fn pay_order(
+        &self,
+        order_id: Uuid,
+        req: &PayOrder,
+        provider: &dyn PaymentProvider,
+    ) -> Result<Payment, RepoError> {
+        let pending = self.start_capture(order_id, provider.name()).await?;
+
+        let amount = Money::from_stored(pending.amount_minor, pending.currency);
+        let outcome = provider
+            .capture(order_id, &amount, &req.payment_token, pending.idempotency_key)
+            .await;
+
+        self.settle_capture(order_id, pending.id, &outcome).await
+    }
+
+    // Writes the Pending capture for a Pending order. When an earlier request never recorded the
+    // provider's answer its Pending row is reused, so the retry sends the same idempotency key.
+    async fn start_capture(&self, order_id: Uuid, provider: &str) -> Result<PendingCapture, RepoError> {
+        let mut tx = self.pool.begin().await?;
+
+        let order = sqlx::query!(
+            r#"
+            SELECT status as "status: OrderStatus", amount_minor, currency
+            FROM orders
+            WHERE id = $1 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+            order_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if order.status != OrderStatus::Pending {
+            return Err(RepoError::Conflict(format!(
+                "Only Pending orders can be paid, this one is {:?}", order.status
+            )));
+        }
+
+        let existing = sqlx::query_as!(
+            PendingCapture,
+            r#"
+            SELECT id, amount_minor, currency, idempotency_key as "idempotency_key!"
+            FROM payments
+            WHERE order_id = $1 AND kind = 'Capture' AND status = 'Pending'
+            "#,
+            order_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let pending = match existing {
+            Some(pending) => pending,
+            None => {
+                sqlx::query_as!(
+                    PendingCapture,
+                    r#"
+                    INSERT INTO payments (order_id, kind, status, amount_minor, currency, provider, idempotency_key)
+                    VALUES ($1, 'Capture', 'Pending', $2, $3, $4, gen_random_uuid())
+                    RETURNING id, amount_minor, currency, idempotency_key as "idempotency_key!"
+                    "#,
+                    order_id,
+                    order.amount_minor,
+                    order.currency,
+                    provider
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+        Ok(pending)
+    }
+
+    // Records what the provider said about a Pending capture and marks the order Paid when it was approved
+    async fn settle_capture(
+        &self,
+        order_id: Uuid,
+        payment_id: Uuid,
+        outcome: &ProviderOutcome,
+    ) -> Result<Payment, RepoError> {
+        let mut tx = self.pool.begin().await?;
+
+        let order = sqlx::query!(
+            r#"SELECT status as "status: OrderStatus" FROM orders WHERE id = $1 FOR UPDATE"#,
+            order_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let (status, provider_ref, failure_reason) = outcome_columns(outcome);
+        let settled = sqlx::query_as!(
+            PaymentDB,
+            r#"
+            UPDATE payments
+            SET status = $2, provider_ref = $3, failure_reason = $4
+            WHERE id = $1 AND status = 'Pending'
+            RETURNING
+                id,
+                order_id,
+                kind as "kind: PaymentKind",
+                status as "status: PaymentStatus",
+                amount_minor,
+                currency,
+                provider,
+                provider_ref,
+                failure_reason,
+                created_at
+            "#,
+            payment_id,
+            status as PaymentStatus,
+            provider_ref,
+            failure_reason
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        // A retry of the same attempt got here first, the provider gave it the same outcome
+        let Some(payment) = settled else {
+            let payment = find_payment(&mut tx, payment_id).await?;
+            tx.commit().await?;
+            return match payment.status {
+                PaymentStatus::Declined => Err(RepoError::PaymentDeclined(payment.failure_reason.unwrap_or_default())),
+                _ => Ok(payment),
+            };
+        };
+
+        match outcome {
+            ProviderOutcome::Approved { .. } => {
+                if order.status != OrderStatus::Pending {
+                    // Keep the capture on record so it can be refunded
+                    tx.commit().await?;
+                    return Err(RepoError::Conflict(format!(
+                        "Order {} became {:?} while it was being paid, the capture {} needs a refund",
+                        order_id, order.status, payment.id
+                    )));
+                }
+                OrderRepository::set_status(&mut tx, order_id, &order.status, &OrderStatus::Paid).await?;
+                tx.commit().await?;
+                Ok(payment.into())
+            }
+            ProviderOutcome::Declined { reason } => {
+                // Keep the attempt, the order stays Pending
+                tx.commit().await?;
+                Err(RepoError::PaymentDeclined(reason.clone()))
+            }
+        }
+    }
+
+    pub async fn list_payments(
+        &self,
+        order_id: Uuid,
+    ) -> Result<Vec<Payment>, Error> {
+        // Make sure the order exists so an unknown id is a 404 rather than an empty list
+        sqlx::query!("SELECT id FROM orders WHERE id = $1", order_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let payments = sqlx::query_as!(
+            PaymentDB,
+            r#"
+            SELECT
+                id,
+                order_id,
+                kind as "kind: PaymentKind",
+                status as "status: PaymentStatus",
+                amount_minor,
+                currency,
+                provider,
+                provider_ref,
+                failure_reason,
+                created_at
+            FROM payments
+            WHERE order_id = $1
+            ORDER BY created_at ASC
+            "#,
+            order_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(payments.into_iter().map(Payment::from).collect())
+    }
+}
+
+// Refunds the successful capture of an order through the provider that took it.
+// The caller holds the order row lock and decides whether to keep the attempt when it is declined.
+pub async fn refund_order(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    provider: &dyn PaymentProvider,
+) -> Result<Payment, RepoError> {
+    let capture = sqlx::query!(
+        r#"
+        SELECT amount_minor, currency, provider_ref as "provider_ref!"
+        FROM payments
+        WHERE order_id = $1
+            AND kind = 'Capture'
+            AND status = 'Succeeded'
+            AND provider_ref IS NOT NULL
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        order_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| RepoError::Conflict(format!(
+        "Order {} has no captured payment to refund", order_id
+    )))?;
+
+    let amount = Money::from_stored(capture.amount_minor, capture.currency);
+    let outcome = provider.refund(&capture.provider_ref, &amount).await;
+    let payment = insert_payment(conn, order_id, PaymentKind::Refund, &amount, provider.name(), &outcome).await?;
+
+    match outcome {
+        ProviderOutcome::Approved { .. } => Ok(payment),
+        ProviderOutcome::Declined { reason } => Err(RepoError::PaymentDeclined(reason)),
+    }
+}
+
+// A capture that has been sent, or is about to be sent, to the provider
+struct PendingCapture {
+    id: Uuid,
+    amount_minor: i64,
+    currency: String,
+    idempotency_key: Uuid,
+}
+
+// While a capture is Pending the order's items and status stay as they are,
+// the provider may already have charged the amount it was sent
+pub async fn capture_in_flight(conn: &mut PgConnection, order_id: Uuid) -> Result<bool, Error> {
+    let pending = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM payments
+            WHERE order_id = $1 AND kind = 'Capture' AND status = 'Pending'
+        ) as "pending!"
+        "#,
+        order_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(pending)
+}
+
+async fn find_payment(conn: &mut PgConnection, id: Uuid) -> Result<Payment, Error> {
+    let payment = sqlx::query_as!(
+        PaymentDB,
+        r#"
+        SELECT
+            id,
+            order_id,
+            kind as "kind: PaymentKind",
+            status as "status: PaymentStatus",
+            amount_minor,
+            currency,
+            provider,
+            provider_ref,
+            failure_reason,
+            created_at
+        FROM payments
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(payment.into())
+}
+
+fn outcome_columns(outcome: &ProviderOutcome) -> (PaymentStatus, Option<&str>, Option<&str>) {
+    match outcome {
+        ProviderOutcome::Approved { provider_ref } => (PaymentStatus::Succeeded, Some(provider_ref.as_str()), None),
+        ProviderOutcome::Declined { reason } => (PaymentStatus::Declined, None, Some(reason.as_str())),
+    }
+}
+
+async fn insert_payment(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    kind: PaymentKind,
+    amount: &Money,
+    provider: &str,
+    outcome: &ProviderOutcome,
+) -> Result<Payment, Error> {
+    let (status, provider_ref, failure_reason) = outcome_columns(outcome);
+
+    let payment = sqlx::query_as!(
+        PaymentDB,
+        r#"
+        INSERT INTO payments (order_id, kind, status, amount_minor, currency, provider, provider_ref, failure_reason)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING
+            id,
+            order_id,
+            kind as "kind: PaymentKind",
+            status as "status: PaymentStatus",
+            amount_minor,
+            currency,
+            provider,
+            provider_ref,
+            failure_reason,
+            created_at
+        "#,
+        order_id,
+        kind as PaymentKind,
+        status as PaymentStatus,
+        amount.amount_minor,
+        amount.currency,
+        provider,
+        provider_ref,
+        failure_reason
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(payment.into())
+}
@@ -0,0 +1,191 @@
+use sqlx::{PgPool, PgConnection, Error};
+use crate::models::{TaxCategory, CreateTaxCategory, Coupon, CouponKind, CreateCoupon};
+use crate::pricing::BPS_DENOMINATOR;
+use crate::repository::error::RepoError;
+
+pub struct PricingRepository {
+    pool: PgPool
+}
+
+impl PricingRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    pub async fn create_tax_category(
+        &self,
+        req: &CreateTaxCategory
+    ) -> Result<TaxCategory, RepoError> {
+        if req.rate_bps < 0 || req.rate_bps as i64 > BPS_DENOMINATOR {
+            return Err(RepoError::Validation(format!(
+                "rate_bps must be between 0 and {}", BPS_DENOMINATOR
+            )));
+        }
+
+        let category = sqlx::query_as!(
+            TaxCategory,
+            r#"
+            INSERT INTO tax_categories (name, rate_bps)
+            VALUES ($1, $2)
+            RETURNING id, name, rate_bps, created_at, updated_at
+            "#,
+            req.name,
+            req.rate_bps
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            Error::Database(ref db) if db.is_unique_violation() => RepoError::Conflict(format!(
+                "Tax category {} already exists", req.name
+            )),
+            other => other.into(),
+        })?;
+
+        Ok(category)
+    }
+
+    pub async fn list_tax_categories(&self) -> Result<Vec<TaxCategory>, Error> {
+        let categories = sqlx::query_as!(
+            TaxCategory,
+            r#"
+            SELECT id, name, rate_bps, created_at, updated_at
+            FROM tax_categories
+            ORDER BY name
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(categories)
+    }
+
+    pub async fn create_coupon(
+        &self,
+        req: &CreateCoupon
+    ) -> Result<Coupon, RepoError> {
+        // Only the value that matches the kind is stored
+        let (percent_bps, amount) = match req.kind {
+            CouponKind::Percentage => match req.percent_bps {
+                Some(bps) if bps > 0 && bps as i64 <= BPS_DENOMINATOR => (Some(bps), None),
+                _ => return Err(RepoError::Validation(format!(
+                    "Percentage coupons need percent_bps between 1 and {}", BPS_DENOMINATOR
+                ))),
+            },
+            CouponKind::Fixed => match req.amount {
+                Some(ref amount) if amount.amount_minor > 0 => (None, Some(amount)),
+                _ => return Err(RepoError::Validation(
+                    "Fixed coupons need a positive amount".to_string()
+                )),
+            },
+        };
+        if let Some(limit) = req.usage_limit
+            && limit <= 0
+        {
+            return Err(RepoError::Validation("usage_limit must be greater than 0".to_string()));
+        }
+
+        let coupon = sqlx::query_as!(
+            Coupon,
+            r#"
+            INSERT INTO coupons (code, kind, percent_bps, amount_minor, currency, expires_at, usage_limit)
+            VALUES (upper($1), $2, $3, $4, $5, $6, $7)
+            RETURNING
+                id,
+                code,
+                kind as "kind: CouponKind",
+                percent_bps,
+                amount_minor,
+                currency,
+                expires_at,
+                usage_limit,
+                times_used,
+                is_active,
+                created_at,
+                updated_at
+            "#,
+            req.code.trim(),
+            req.kind.clone() as CouponKind,
+            percent_bps,
+            amount.map(|a| a.amount_minor),
+            amount.map(|a| a.currency.as_str()),
+            req.expires_at,
+            req.usage_limit
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            Error::Database(ref db) if db.is_unique_violation() => RepoError::Conflict(format!(
+                "Coupon {} already exists", req.code.trim().to_uppercase()
+            )),
+            other => other.into(),
+        })?;
+
+        Ok(coupon)
+    }
+
+    pub async fn get_coupon(&self, code: &str) -> Result<Coupon, Error> {
+        let mut conn = self.pool.acquire().await?;
+        find_coupon(&mut conn, code).await
+    }
+
+    pub async fn list_coupons(&self) -> Result<Vec<Coupon>, Error> {
+        let coupons = sqlx::query_as!(
+            Coupon,
+            r#"
+            SELECT
+                id,
+                code,
+                kind as "kind: CouponKind",
+                percent_bps,
+                amount_minor,
+                currency,
+                expires_at,
+                usage_limit,
+                times_used,
+                is_active,
+                created_at,
+                updated_at
+            FROM coupons
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(coupons)
+    }
+}
+
+// Look up a coupon by code, codes are stored upper case
+pub async fn find_coupon(
+    conn: &mut PgConnection,
+    code: &str,
+) -> Result<Coupon, Error> {
+    let coupon = sqlx::query_as!(
+        Coupon,
+        r#"
+        SELECT
+            id,
+            code,
+            kind as "kind: CouponKind",
+            percent_bps,
+            amount_minor,
+            currency,
+            expires_at,
+            usage_limit,
+            times_used,
+            is_active,
+            created_at,
+            updated_at
+        FROM coupons
+        WHERE code = upper($1)
+        "#,
+        code.trim()
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(coupon)
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgPool, Error};
+use uuid::Uuid;
+
+// How many soft deleted rows a purge removed for good
+#[derive(Debug, Serialize)]
+pub struct PurgeReport {
+    pub orders: u64,
+    pub items: u64,
+    pub users: u64,
+    // Pending orders that still hold reserved stock, left in place
+    pub skipped_orders: u64,
+}
+
+// Hard deletes rows soft deleted before the cutoff.
+// Orders go first so items and users they pointed at can follow, anything still referenced is kept.
+// Inventory ledger rows outlive their item and order, they only lose the reference.
+// A Pending order whose ledger shows stock still taken is never removed, that stock would be lost with it.
+pub async fn purge_deleted(
+    pool: &PgPool,
+    deleted_before: DateTime<Utc>,
+) -> Result<PurgeReport, Error> {
+    let mut tx = pool.begin().await?;
+
+    let held: Vec<Uuid> = sqlx::query_scalar!(
+        r#"
+        SELECT o.id
+        FROM orders o
+        WHERE o.deleted_at < $1
+            AND o.status = 'Pending'
+            AND (SELECT COALESCE(SUM(l.quantity_change), 0) FROM inventory_ledger l WHERE l.order_id = o.id) < 0
+        "#,
+        deleted_before
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for id in &held {
+        log::warn!("Not purging order {}, it still holds reserved stock", id);
+    }
+
+    let orders = sqlx::query!(
+        "DELETE FROM orders WHERE deleted_at < $1 AND NOT (id = ANY($2))",
+        deleted_before,
+        &held
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let items = sqlx::query!(
+        r#"
+        DELETE FROM items
+        WHERE deleted_at < $1
+            AND NOT EXISTS (SELECT 1 FROM order_items WHERE order_items.item_id = items.id)
+        "#,
+        deleted_before
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let users = sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE deleted_at < $1
+            AND NOT EXISTS (SELECT 1 FROM orders WHERE orders.user_id = users.id)
+        "#,
+        deleted_before
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(PurgeReport { orders, items, users, skipped_orders: held.len() as u64 })
+}
@@ -1,100 +1,210 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use uuid::Uuid;
 use crate::repository::items_db::ItemRepository;
 use crate::repository::order_db::OrderRepository;
 use crate::repository::users_db::UserRepository;
-use crate::models::{CreateUser, UpdateUser, CreateItem, UpdateItem, CreateOrder, UpdateOrder, OrderStatus, StatusQuery};
+use crate::repository::pricing_db::PricingRepository;
+use crate::repository::cart_db::CartRepository;
+use crate::repository::payments_db::PaymentRepository;
+use crate::repository::shipments_db::ShipmentRepository;
+use crate::repository::catalog_db::CatalogRepository;
+use crate::repository::bulk_db::{BulkRepository, ExportKind};
+use crate::payments::PaymentProvider;
+use crate::repository::error::RepoError;
+use crate::logging::span;
+use crate::models::{User, Item, Order, OrderDetails, OrderStatusHistory, StockMovement, ItemSearchHit, Page, Quote, Cart, Payment, Shipment, TaxCategory, Coupon, Category, CategoryNode, Tag, BulkDeleteResult, ImportReport};
+use crate::models::{CreateUser, UpdateUser, CreateItem, UpdateItem, CreateOrder, UpdateOrder, StatusQuery, QuoteRequest, CreateTaxCategory, CreateCoupon, AddCartItem, Checkout, PayOrder, CreateShipment, DeliverShipment, DeletedQuery, BulkDelete, ListParams, ItemFilter, OrderFilter, ExpandQuery, SearchQuery, CreateCategory, UpdateCategory, TagRequest, SetItemTags, JobQueue, CreateJob, ImportKind, ImportFormat, FormatQuery};
+use crate::openapi::{Envelope, ErrorEnvelope, ImportAccepted};
+use crate::validation::{Valid, ValidOrDefault};
+use crate::response::{ApiResponse, ErrorCode};
 
 
 // user db handler
+#[utoipa::path(
+    post,
+    path = "/db/users",
+    tag = "users",
+    request_body = CreateUser,
+    summary = "Create a user",
+    responses(
+        (status = 201, description = "Created", body = Envelope<User>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_user(
     repo: web::Data<UserRepository>,
-    req: web::Json<CreateUser>,
+    req: Valid<CreateUser>,
 ) -> impl Responder {
-    match repo.create_user(&req).await {
-        Ok(user) => HttpResponse::Created().json(user),
+    match span("UserRepository::create_user", repo.create_user(&req)).await {
+        Ok(user) => ApiResponse::created(user),
         Err(e) => {
-            eprintln!("DB error creating user: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to create user"
-            }))
+            log::error!("DB error creating user: {:?}", e);
+            ApiResponse::internal("Failed to create user")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/users/{id}",
+    tag = "users",
+    params(DeletedQuery),
+    summary = "Get a user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_user(
     repo: web::Data<UserRepository>,
     path: web::Path<Uuid>,
+    query: web::Query<DeletedQuery>,
 ) -> impl Responder {
     let user_id = path.into_inner();
     
-    match repo.get_user(user_id).await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error fetching user: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error"
-            }))
+    match span("UserRepository::get_user", repo.get_user(user_id, query.include_deleted)).await {
+        Ok(user) => ApiResponse::ok(user),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("User with id {} not found", user_id)),
+        Err(e) => {
+            log::error!("DB error fetching user: {:?}", e);
+            ApiResponse::internal("Database error")
         }
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/db/users/{id}",
+    tag = "users",
+    request_body = UpdateUser,
+    summary = "Update a user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn update_user(
     repo: web::Data<UserRepository>,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateUser>,
+    req: Valid<UpdateUser>,
 ) -> impl Responder {
     let user_id = path.into_inner();
     
-    match repo.update_user(user_id, &req).await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error updating user: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to update user"
-            }))
+    match span("UserRepository::update_user", repo.update_user(user_id, &req)).await {
+        Ok(user) => ApiResponse::ok(user),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("User with id {} not found", user_id)),
+        Err(e) => {
+            log::error!("DB error updating user: {:?}", e);
+            ApiResponse::internal("Failed to update user")
         }
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/db/users/{id}",
+    tag = "users",
+    summary = "Soft delete a user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn delete_user(
     repo: web::Data<UserRepository>,
     path: web::Path<Uuid>,
 ) -> impl Responder {
     let user_id = path.into_inner();
     
-    match repo.delete_user(user_id).await {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "message": "User deleted successfully"
-        })),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("User with id {} not found", user_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error deleting user: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to delete user"
-            }))
+    match span("UserRepository::delete_user", repo.delete_user(user_id)).await {
+        Ok(user) => ApiResponse::ok(user),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("User with id {} not found", user_id)),
+        Err(e) => {
+            log::error!("DB error deleting user: {:?}", e);
+            ApiResponse::internal("Failed to delete user")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/users/bulk-delete",
+    tag = "users",
+    request_body = BulkDelete,
+    summary = "Soft delete several users",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<BulkDeleteResult>>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn delete_users(
+    repo: web::Data<UserRepository>,
+    req: Valid<BulkDelete>,
+) -> impl Responder {
+    match span("UserRepository::delete_users", repo.delete_users(&req.ids)).await {
+        Ok(results) => ApiResponse::ok(results),
+        Err(e) => {
+            log::error!("DB error deleting users: {:?}", e);
+            ApiResponse::internal("Failed to delete users")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/users/{id}/restore",
+    tag = "users",
+    summary = "Restore a soft deleted user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<User>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn restore_user(
+    repo: web::Data<UserRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+
+    match span("UserRepository::restore_user", repo.restore_user(user_id)).await {
+        Ok(user) => ApiResponse::ok(user),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("No deleted user with id {}", user_id)),
+        Err(e) => {
+            log::error!("DB error restoring user: {:?}", e);
+            ApiResponse::internal("Failed to restore user")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/users",
+    tag = "users",
+    params(ListParams, ExpandQuery),
+    summary = "List users a page at a time",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Page<User>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn list_users(
     repo: web::Data<UserRepository>,
+    params: web::Query<ListParams>,
+    expand: web::Query<ExpandQuery>,
 ) -> impl Responder {
-    match repo.list_users().await {
-        Ok(users) => HttpResponse::Ok().json(users),
+    match span("UserRepository::list_users", repo.list_users(&params, expand.items())).await {
+        Ok(page) => ApiResponse::ok(page),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
         Err(e) => {
-            eprintln!("DB error listing users: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch users"
-            }))
+            log::error!("DB error listing users: {:?}", e);
+            ApiResponse::internal("Failed to fetch users")
         }
     }
 }
@@ -107,258 +217,1627 @@ pub async fn list_users(
 
 
 // item db handler
+#[utoipa::path(
+    post,
+    path = "/db/items",
+    tag = "items",
+    request_body = CreateItem,
+    summary = "Create an item",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Item>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_item(
     repo: web::Data<ItemRepository>,
-    req: web::Json<CreateItem>,
+    req: Valid<CreateItem>,
 ) -> impl Responder {
-    match repo.create_item(&req).await {
-        Ok(item) => HttpResponse::Created().json(item),
+    match span("ItemRepository::create_item", repo.create_item(&req)).await {
+        Ok(item) => ApiResponse::created(item),
         Err(e) => {
-            eprintln!("DB error creating item: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to create item"
-            }))
+            log::error!("DB error creating item: {:?}", e);
+            ApiResponse::internal("Failed to create item")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/items/{id}",
+    tag = "items",
+    params(DeletedQuery),
+    summary = "Get an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_item(
     repo: web::Data<ItemRepository>,
     path: web::Path<Uuid>,
+    query: web::Query<DeletedQuery>,
 ) -> impl Responder {
     let item_id = path.into_inner();
     
-    match repo.get_item(item_id).await {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Item with id {} not found", item_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error fetching item: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error"
-            }))
+    match span("ItemRepository::get_item", repo.get_item(item_id, query.include_deleted)).await {
+        Ok(item) => ApiResponse::ok(item),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Item with id {} not found", item_id)),
+        Err(e) => {
+            log::error!("DB error fetching item: {:?}", e);
+            ApiResponse::internal("Database error")
         }
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/db/items/{id}",
+    tag = "items",
+    request_body = UpdateItem,
+    summary = "Update an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn update_item(
     repo: web::Data<ItemRepository>,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateItem>,
+    req: Valid<UpdateItem>,
 ) -> impl Responder {
     let item_id = path.into_inner();
     
-    match repo.update_item(item_id, &req).await {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Item with id {} not found", item_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error updating item: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to update item"
-            }))
+    match span("ItemRepository::update_item", repo.update_item(item_id, &req)).await {
+        Ok(item) => ApiResponse::ok(item),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Item with id {} not found", item_id)),
+        Err(e) => {
+            log::error!("DB error updating item: {:?}", e);
+            ApiResponse::internal("Failed to update item")
         }
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/db/items/{id}",
+    tag = "items",
+    summary = "Soft delete an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn delete_item(
     repo: web::Data<ItemRepository>,
     path: web::Path<Uuid>,
 ) -> impl Responder {
     let item_id = path.into_inner();
     
-    match repo.delete_item(item_id).await {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "message": "Item deleted successfully"
-        })),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Item with id {} not found", item_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error deleting item: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to delete item"
-            }))
+    match span("ItemRepository::delete_item", repo.delete_item(item_id)).await {
+        Ok(item) => ApiResponse::ok(item),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Item with id {} not found", item_id)),
+        Err(e) => {
+            log::error!("DB error deleting item: {:?}", e);
+            ApiResponse::internal("Failed to delete item")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/items/bulk-delete",
+    tag = "items",
+    request_body = BulkDelete,
+    summary = "Soft delete several items",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<BulkDeleteResult>>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn delete_items(
+    repo: web::Data<ItemRepository>,
+    req: Valid<BulkDelete>,
+) -> impl Responder {
+    match span("ItemRepository::delete_items", repo.delete_items(&req.ids)).await {
+        Ok(results) => ApiResponse::ok(results),
+        Err(e) => {
+            log::error!("DB error deleting items: {:?}", e);
+            ApiResponse::internal("Failed to delete items")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/items/{id}/restore",
+    tag = "items",
+    summary = "Restore a soft deleted item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Item>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn restore_item(
+    repo: web::Data<ItemRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+
+    match span("ItemRepository::restore_item", repo.restore_item(item_id)).await {
+        Ok(item) => ApiResponse::ok(item),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("No deleted item with id {}", item_id)),
+        Err(e) => {
+            log::error!("DB error restoring item: {:?}", e);
+            ApiResponse::internal("Failed to restore item")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/items/{id}/ledger",
+    tag = "items",
+    summary = "Inventory ledger of an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<StockMovement>>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_item_stock_movements(
+    repo: web::Data<ItemRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+
+    match span("ItemRepository::get_stock_movements", repo.get_stock_movements(item_id)).await {
+        Ok(movements) => ApiResponse::ok(movements),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Item with id {} not found", item_id)),
+        Err(e) => {
+            log::error!("DB error fetching item stock movements: {:?}", e);
+            ApiResponse::internal("Database error")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/items",
+    tag = "items",
+    params(ListParams, ItemFilter),
+    summary = "List items a page at a time",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Page<Item>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn list_items(
     repo: web::Data<ItemRepository>,
+    params: web::Query<ListParams>,
+    filter: web::Query<ItemFilter>,
 ) -> impl Responder {
-    match repo.list_items().await {
-        Ok(items) => HttpResponse::Ok().json(items),
+    match span("ItemRepository::list_items", repo.list_items(&params, &filter)).await {
+        Ok(page) => ApiResponse::ok(page),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
         Err(e) => {
-            eprintln!("DB error listing items: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch items"
-            }))
+            log::error!("DB error listing items: {:?}", e);
+            ApiResponse::internal("Failed to fetch items")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/items/search",
+    tag = "items",
+    params(SearchQuery),
+    summary = "Full text search over item names and descriptions",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<ItemSearchHit>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn search_items(
+    repo: web::Data<ItemRepository>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    match span("ItemRepository::search_items", repo.search_items(&query)).await {
+        Ok(hits) => ApiResponse::ok(hits),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(e) => {
+            log::error!("DB error searching items: {:?}", e);
+            ApiResponse::internal("Failed to search items")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/items/active",
+    tag = "items",
+    params(ListParams, ItemFilter),
+    summary = "List active items a page at a time",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Page<Item>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn list_active_items(
     repo: web::Data<ItemRepository>,
+    params: web::Query<ListParams>,
+    filter: web::Query<ItemFilter>,
 ) -> impl Responder {
-    match repo.list_active_items().await {
-        Ok(items) => HttpResponse::Ok().json(items),
+    match span("ItemRepository::list_active_items", repo.list_active_items(&params, &filter)).await {
+        Ok(page) => ApiResponse::ok(page),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
         Err(e) => {
-            eprintln!("DB error listing active items: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch active items"
-            }))
+            log::error!("DB error listing active items: {:?}", e);
+            ApiResponse::internal("Failed to fetch active items")
         }
     }
 }
 
 
 // order db handler
+#[utoipa::path(
+    post,
+    path = "/db/orders",
+    tag = "orders",
+    request_body = CreateOrder,
+    summary = "Create an order and reserve its stock",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Order>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "User unknown or soft deleted, or an item unknown, inactive or soft deleted", body = ErrorEnvelope),
+        (status = 409, description = "Insufficient stock, items lists the shortages", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn create_order(
     repo: web::Data<OrderRepository>,
-    req: web::Json<CreateOrder>,
+    req: Valid<CreateOrder>,
 ) -> impl Responder {
-    match repo.create_order(&req).await {
-        Ok(order) => HttpResponse::Created().json(order),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": "User or one or more items not found"
-        })),
+    match span("OrderRepository::create_order", repo.create_order(&req)).await {
+        Ok(order) => ApiResponse::created(order),
+        Err(RepoError::NotFound) => ApiResponse::not_found("User or one or more items not found"),
+        Err(RepoError::InsufficientStock(items)) => ApiResponse::error(ErrorCode::InsufficientStock, "Insufficient stock for one or more items")
+            .details(serde_json::json!({ "items": items })),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
         Err(e) => {
-            eprintln!("DB error creating order: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to create order"
-            }))
+            log::error!("DB error creating order: {:?}", e);
+            ApiResponse::internal("Failed to create order")
         }
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/db/orders/quote",
+    tag = "orders",
+    request_body = QuoteRequest,
+    summary = "Price a cart without writing anything",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Quote>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Item not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn quote_order(
+    repo: web::Data<OrderRepository>,
+    req: Valid<QuoteRequest>,
+) -> impl Responder {
+    match span("OrderRepository::quote", repo.quote(&req)).await {
+        Ok(quote) => ApiResponse::ok(quote),
+        Err(RepoError::NotFound) => ApiResponse::not_found("One or more items not found"),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(e) => {
+            log::error!("DB error quoting order: {:?}", e);
+            ApiResponse::internal("Failed to quote order")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/orders/{id}",
+    tag = "orders",
+    params(DeletedQuery),
+    summary = "Get an order",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Order>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_order(
     repo: web::Data<OrderRepository>,
     path: web::Path<Uuid>,
+    query: web::Query<DeletedQuery>,
 ) -> impl Responder {
     let order_id = path.into_inner();
     
-    match repo.get_order(order_id).await {
-        Ok(order) => HttpResponse::Ok().json(order),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Order with id {} not found", order_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error fetching order: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error"
-            }))
+    match span("OrderRepository::get_order", repo.get_order(order_id, query.include_deleted)).await {
+        Ok(order) => ApiResponse::ok(order),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(e) => {
+            log::error!("DB error fetching order: {:?}", e);
+            ApiResponse::internal("Database error")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/orders/{id}/details",
+    tag = "orders",
+    params(DeletedQuery),
+    summary = "Get an order with its lines and shipment",
+    responses(
+        (status = 200, description = "OK", body = Envelope<OrderDetails>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_order_with_items(
     repo: web::Data<OrderRepository>,
     path: web::Path<Uuid>,
+    query: web::Query<DeletedQuery>,
 ) -> impl Responder {
     let order_id = path.into_inner();
     
-    match repo.get_order_with_items(order_id).await {
-        Ok(order) => HttpResponse::Ok().json(order),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Order with id {} not found", order_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error fetching order with items: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error"
-            }))
+    match span("OrderRepository::get_order_with_items", repo.get_order_with_items(order_id, query.include_deleted)).await {
+        Ok(order) => ApiResponse::ok(order),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(e) => {
+            log::error!("DB error fetching order with items: {:?}", e);
+            ApiResponse::internal("Database error")
         }
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/db/orders/{id}",
+    tag = "orders",
+    request_body = UpdateOrder,
+    summary = "Update an order's lines or status, cancelling a paid order refunds it",
+    description = "The coupon an order was created with cannot be changed or removed, new lines are repriced with it.",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Order>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 402, description = "The refund was declined", body = ErrorEnvelope),
+        (status = 404, description = "Order or item not found", body = ErrorEnvelope),
+        (status = 409, description = "Insufficient stock or a status change that is not allowed", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn update_order(
     repo: web::Data<OrderRepository>,
+    provider: web::Data<dyn PaymentProvider>,
     path: web::Path<Uuid>,
-    req: web::Json<UpdateOrder>,
+    req: Valid<UpdateOrder>,
 ) -> impl Responder {
     let order_id = path.into_inner();
     
-    match repo.update_order(order_id, &req).await {
-        Ok(order) => HttpResponse::Ok().json(order),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": "Order or one or more items not found"
-        })),
-        Err(e) => {
-            eprintln!("DB error updating order: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to update order"
-            }))
+    match span("OrderRepository::update_order", repo.update_order(order_id, &req, provider.get_ref())).await {
+        Ok(order) => ApiResponse::ok(order),
+        Err(RepoError::NotFound) => ApiResponse::not_found("Order or one or more items not found"),
+        Err(RepoError::InsufficientStock(items)) => ApiResponse::error(ErrorCode::InsufficientStock, "Insufficient stock for one or more items")
+            .details(serde_json::json!({ "items": items })),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(RepoError::PaymentDeclined(reason)) => ApiResponse::error(ErrorCode::PaymentDeclined, "Refund was declined, the order is still Paid")
+            .details(serde_json::json!({ "reason": reason })),
+        Err(e) => {
+            log::error!("DB error updating order: {:?}", e);
+            ApiResponse::internal("Failed to update order")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/orders/{id}/history",
+    tag = "orders",
+    summary = "Status changes of an order, oldest first",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<OrderStatusHistory>>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_order_status_history(
+    repo: web::Data<OrderRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+
+    match span("OrderRepository::get_status_history", repo.get_status_history(order_id)).await {
+        Ok(history) => ApiResponse::ok(history),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(e) => {
+            log::error!("DB error fetching order status history: {:?}", e);
+            ApiResponse::internal("Database error")
         }
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/db/orders/{id}",
+    tag = "orders",
+    summary = "Soft delete an order, a Pending order gives its reserved stock back",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Order>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn delete_order(
     repo: web::Data<OrderRepository>,
     path: web::Path<Uuid>,
 ) -> impl Responder {
     let order_id = path.into_inner();
     
-    match repo.delete_order(order_id).await {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "message": "Order deleted successfully"
-        })),
-        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Order with id {} not found", order_id)
-        })),
-        Err(e) => {
-            eprintln!("DB error deleting order: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to delete order"
-            }))
+    match span("OrderRepository::delete_order", repo.delete_order(order_id)).await {
+        Ok(order) => ApiResponse::ok(order),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(e) => {
+            log::error!("DB error deleting order: {:?}", e);
+            ApiResponse::internal("Failed to delete order")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/orders/bulk-delete",
+    tag = "orders",
+    request_body = BulkDelete,
+    summary = "Soft delete several orders",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<BulkDeleteResult>>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn delete_orders(
+    repo: web::Data<OrderRepository>,
+    req: Valid<BulkDelete>,
+) -> impl Responder {
+    match span("OrderRepository::delete_orders", repo.delete_orders(&req.ids)).await {
+        Ok(results) => ApiResponse::ok(results),
+        Err(e) => {
+            log::error!("DB error deleting orders: {:?}", e);
+            ApiResponse::internal("Failed to delete orders")
         }
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/db/orders/{id}/restore",
+    tag = "orders",
+    summary = "Restore a soft deleted order, a Pending order reserves its stock again",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Order>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "A Pending order's items are no longer in stock", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn restore_order(
+    repo: web::Data<OrderRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+
+    match span("OrderRepository::restore_order", repo.restore_order(order_id)).await {
+        Ok(order) => ApiResponse::ok(order),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("No deleted order with id {}", order_id)),
+        Err(RepoError::InsufficientStock(items)) => ApiResponse::error(ErrorCode::InsufficientStock, "Insufficient stock for one or more items")
+            .details(serde_json::json!({ "items": items })),
+        Err(e) => {
+            log::error!("DB error restoring order: {:?}", e);
+            ApiResponse::internal("Failed to restore order")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/orders",
+    tag = "orders",
+    params(ListParams, OrderFilter, ExpandQuery),
+    summary = "List orders a page at a time",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Page<Order>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn list_orders(
     repo: web::Data<OrderRepository>,
+    params: web::Query<ListParams>,
+    filter: web::Query<OrderFilter>,
+    expand: web::Query<ExpandQuery>,
 ) -> impl Responder {
-    match repo.list_orders().await {
-        Ok(orders) => HttpResponse::Ok().json(orders),
+    match span("OrderRepository::list_orders", repo.list_orders(&params, &filter, expand.items())).await {
+        Ok(page) => ApiResponse::ok(page),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
         Err(e) => {
-            eprintln!("DB error listing orders: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch orders"
-            }))
+            log::error!("DB error listing orders: {:?}", e);
+            ApiResponse::internal("Failed to fetch orders")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/orders/user/{user_id}",
+    tag = "orders",
+    params(ListParams, OrderFilter, ExpandQuery),
+    summary = "List the orders of one user",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Page<Order>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_orders_by_user(
     repo: web::Data<OrderRepository>,
     path: web::Path<Uuid>,
+    params: web::Query<ListParams>,
+    filter: web::Query<OrderFilter>,
+    expand: web::Query<ExpandQuery>,
 ) -> impl Responder {
     let user_id = path.into_inner();
     
-    match repo.get_orders_by_user(user_id).await {
-        Ok(orders) => HttpResponse::Ok().json(orders),
+    match span("OrderRepository::get_orders_by_user", repo.get_orders_by_user(user_id, &params, &filter, expand.items())).await {
+        Ok(page) => ApiResponse::ok(page),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
         Err(e) => {
-            eprintln!("DB error fetching orders by user: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch orders"
-            }))
+            log::error!("DB error fetching orders by user: {:?}", e);
+            ApiResponse::internal("Failed to fetch orders")
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/orders/status",
+    tag = "orders",
+    params(StatusQuery, ListParams, ExpandQuery),
+    summary = "List orders in one status",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Page<Order>>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
 pub async fn get_orders_by_status(
     repo: web::Data<OrderRepository>,
     query: web::Query<StatusQuery>,
+    params: web::Query<ListParams>,
+    filter: web::Query<OrderFilter>,
+    expand: web::Query<ExpandQuery>,
+) -> impl Responder {
+    match span("OrderRepository::get_orders_by_status", repo.get_orders_by_status(query.status.clone(), &params, &filter, expand.items())).await {
+        Ok(page) => ApiResponse::ok(page),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(e) => {
+            log::error!("DB error fetching orders by status: {:?}", e);
+            ApiResponse::internal("Failed to fetch orders")
+        }
+    }
+}
+
+
+
+// pricing db handler
+#[utoipa::path(
+    post,
+    path = "/db/tax-categories",
+    tag = "pricing",
+    request_body = CreateTaxCategory,
+    summary = "Create a tax category",
+    responses(
+        (status = 201, description = "Created", body = Envelope<TaxCategory>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn create_tax_category(
+    repo: web::Data<PricingRepository>,
+    req: Valid<CreateTaxCategory>,
+) -> impl Responder {
+    match span("PricingRepository::create_tax_category", repo.create_tax_category(&req)).await {
+        Ok(category) => ApiResponse::created(category),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error creating tax category: {:?}", e);
+            ApiResponse::internal("Failed to create tax category")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/tax-categories",
+    tag = "pricing",
+    summary = "List tax categories",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<TaxCategory>>),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn list_tax_categories(
+    repo: web::Data<PricingRepository>,
+) -> impl Responder {
+    match span("PricingRepository::list_tax_categories", repo.list_tax_categories()).await {
+        Ok(categories) => ApiResponse::ok(categories),
+        Err(e) => {
+            log::error!("DB error listing tax categories: {:?}", e);
+            ApiResponse::internal("Failed to fetch tax categories")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/coupons",
+    tag = "pricing",
+    request_body = CreateCoupon,
+    summary = "Create a coupon",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Coupon>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn create_coupon(
+    repo: web::Data<PricingRepository>,
+    req: Valid<CreateCoupon>,
+) -> impl Responder {
+    match span("PricingRepository::create_coupon", repo.create_coupon(&req)).await {
+        Ok(coupon) => ApiResponse::created(coupon),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error creating coupon: {:?}", e);
+            ApiResponse::internal("Failed to create coupon")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/coupons/{code}",
+    tag = "pricing",
+    summary = "Get a coupon by code",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Coupon>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_coupon(
+    repo: web::Data<PricingRepository>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let code = path.into_inner();
+
+    match span("PricingRepository::get_coupon", repo.get_coupon(&code)).await {
+        Ok(coupon) => ApiResponse::ok(coupon),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Coupon {} not found", code)),
+        Err(e) => {
+            log::error!("DB error fetching coupon: {:?}", e);
+            ApiResponse::internal("Database error")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/coupons",
+    tag = "pricing",
+    summary = "List coupons",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Coupon>>),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn list_coupons(
+    repo: web::Data<PricingRepository>,
+) -> impl Responder {
+    match span("PricingRepository::list_coupons", repo.list_coupons()).await {
+        Ok(coupons) => ApiResponse::ok(coupons),
+        Err(e) => {
+            log::error!("DB error listing coupons: {:?}", e);
+            ApiResponse::internal("Failed to fetch coupons")
+        }
+    }
+}
+
+
+// cart db handler
+#[utoipa::path(
+    get,
+    path = "/db/users/{id}/cart/items",
+    tag = "carts",
+    summary = "Get a user's cart",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Cart>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_cart(
+    repo: web::Data<CartRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+
+    match span("CartRepository::get_cart", repo.get_cart(user_id)).await {
+        Ok(cart) => ApiResponse::ok(cart),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("User with id {} not found", user_id)),
+        Err(e) => {
+            log::error!("DB error fetching cart: {:?}", e);
+            ApiResponse::internal("Failed to fetch cart")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/users/{id}/cart/items",
+    tag = "carts",
+    request_body = AddCartItem,
+    summary = "Add an item to a cart",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Cart>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "User or item not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn add_cart_item(
+    repo: web::Data<CartRepository>,
+    path: web::Path<Uuid>,
+    req: Valid<AddCartItem>,
+) -> impl Responder {
+    match span("CartRepository::add_item", repo.add_item(path.into_inner(), &req)).await {
+        Ok(cart) => ApiResponse::ok(cart),
+        Err(RepoError::NotFound) => ApiResponse::not_found("User or item not found"),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(e) => {
+            log::error!("DB error adding cart item: {:?}", e);
+            ApiResponse::internal("Failed to add item to cart")
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/db/users/{id}/cart/items/{item_id}",
+    tag = "carts",
+    summary = "Remove an item from a cart",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Cart>),
+        (status = 404, description = "The item is not in the cart", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn remove_cart_item(
+    repo: web::Data<CartRepository>,
+    path: web::Path<(Uuid, Uuid)>,
 ) -> impl Responder {
-    match repo.get_orders_by_status(query.status.clone()).await {
-        Ok(orders) => HttpResponse::Ok().json(orders),
+    let (user_id, item_id) = path.into_inner();
+
+    match span("CartRepository::remove_item", repo.remove_item(user_id, item_id)).await {
+        Ok(cart) => ApiResponse::ok(cart),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Item {} is not in the cart", item_id)),
         Err(e) => {
-            eprintln!("DB error fetching orders by status: {:?}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to fetch orders"
+            log::error!("DB error removing cart item: {:?}", e);
+            ApiResponse::internal("Failed to remove item from cart")
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/db/users/{id}/cart/items",
+    tag = "carts",
+    summary = "Empty a cart",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Cart>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn clear_cart(
+    repo: web::Data<CartRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+
+    match span("CartRepository::clear", repo.clear(user_id)).await {
+        Ok(cart) => ApiResponse::ok(cart),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("User with id {} not found", user_id)),
+        Err(e) => {
+            log::error!("DB error clearing cart: {:?}", e);
+            ApiResponse::internal("Failed to clear cart")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/users/{id}/cart/checkout",
+    tag = "carts",
+    request_body(content = Option<Checkout>, description = "Optional, only carries a coupon code"),
+    summary = "Turn a cart into an order",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Order>),
+        (status = 400, description = "Invalid request, or a body that is malformed or failed validation", body = ErrorEnvelope),
+        (status = 404, description = "User or item not found", body = ErrorEnvelope),
+        (status = 409, description = "Insufficient stock or the cart is empty", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn checkout_cart(
+    repo: web::Data<CartRepository>,
+    orders: web::Data<OrderRepository>,
+    path: web::Path<Uuid>,
+    req: ValidOrDefault<Checkout>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    // The body is optional, it only carries a coupon code
+    let coupon_code = req.into_inner().coupon_code;
+
+    match span("CartRepository::checkout", repo.checkout(user_id, coupon_code, &orders)).await {
+        Ok(order) => ApiResponse::created(order),
+        Err(RepoError::NotFound) => ApiResponse::not_found("User or one or more items not found"),
+        Err(RepoError::InsufficientStock(items)) => ApiResponse::error(ErrorCode::InsufficientStock, "Insufficient stock for one or more items")
+            .details(serde_json::json!({ "items": items })),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error checking out cart: {:?}", e);
+            ApiResponse::internal("Failed to check out cart")
+        }
+    }
+}
+
+
+// payment db handler
+#[utoipa::path(
+    post,
+    path = "/db/orders/{id}/pay",
+    tag = "payments",
+    request_body = PayOrder,
+    summary = "Capture payment for a pending order",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Payment>),
+        (status = 400, description = "The body failed validation", body = ErrorEnvelope),
+        (status = 402, description = "The payment was declined", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "The order is not pending, or it changed while the capture was out", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn pay_order(
+    repo: web::Data<PaymentRepository>,
+    provider: web::Data<dyn PaymentProvider>,
+    path: web::Path<Uuid>,
+    req: Valid<PayOrder>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+
+    match span("PaymentRepository::pay_order", repo.pay_order(order_id, &req, provider.get_ref())).await {
+        Ok(payment) => ApiResponse::created(payment),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(RepoError::PaymentDeclined(reason)) => ApiResponse::error(ErrorCode::PaymentDeclined, "Payment was declined")
+            .details(serde_json::json!({ "reason": reason })),
+        Err(e) => {
+            log::error!("DB error paying order: {:?}", e);
+            ApiResponse::internal("Failed to pay order")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/orders/{id}/payments",
+    tag = "payments",
+    summary = "Payments and refunds of an order",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Payment>>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn list_order_payments(
+    repo: web::Data<PaymentRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+
+    match span("PaymentRepository::list_payments", repo.list_payments(order_id)).await {
+        Ok(payments) => ApiResponse::ok(payments),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(e) => {
+            log::error!("DB error fetching payments: {:?}", e);
+            ApiResponse::internal("Failed to fetch payments")
+        }
+    }
+}
+
+
+// shipment db handler
+#[utoipa::path(
+    post,
+    path = "/db/orders/{id}/shipment",
+    tag = "shipments",
+    request_body = CreateShipment,
+    summary = "Ship a paid order",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Shipment>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn create_shipment(
+    repo: web::Data<ShipmentRepository>,
+    path: web::Path<Uuid>,
+    req: Valid<CreateShipment>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+
+    match span("ShipmentRepository::create_shipment", repo.create_shipment(order_id, &req)).await {
+        Ok(shipment) => ApiResponse::created(shipment),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error creating shipment: {:?}", e);
+            ApiResponse::internal("Failed to create shipment")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/orders/{id}/shipment",
+    tag = "shipments",
+    summary = "Get the shipment of an order",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Shipment>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_shipment(
+    repo: web::Data<ShipmentRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+
+    match span("ShipmentRepository::get_shipment", repo.get_shipment(order_id)).await {
+        Ok(shipment) => ApiResponse::ok(shipment),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("No shipment for order {}", order_id)),
+        Err(e) => {
+            log::error!("DB error fetching shipment: {:?}", e);
+            ApiResponse::internal("Database error")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/orders/{id}/shipment/delivered",
+    tag = "shipments",
+    request_body(content = Option<DeliverShipment>, description = "Optional, delivered_at defaults to now"),
+    summary = "Mark a shipment delivered",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Shipment>),
+        (status = 400, description = "The body is malformed or failed validation, or delivered_at is before shipped_at", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn deliver_shipment(
+    repo: web::Data<ShipmentRepository>,
+    path: web::Path<Uuid>,
+    req: ValidOrDefault<DeliverShipment>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+    // The body is optional, delivered_at defaults to now
+
+    match span("ShipmentRepository::mark_delivered", repo.mark_delivered(order_id, &req)).await {
+        Ok(shipment) => ApiResponse::ok(shipment),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Order with id {} not found", order_id)),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error delivering shipment: {:?}", e);
+            ApiResponse::internal("Failed to mark shipment delivered")
+        }
+    }
+}
+
+// catalog db handler
+#[utoipa::path(
+    post,
+    path = "/db/categories",
+    tag = "catalog",
+    request_body = CreateCategory,
+    summary = "Create a category",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Category>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn create_category(
+    repo: web::Data<CatalogRepository>,
+    req: Valid<CreateCategory>,
+) -> impl Responder {
+    match span("CatalogRepository::create_category", repo.create_category(&req)).await {
+        Ok(category) => ApiResponse::created(category),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error creating category: {:?}", e);
+            ApiResponse::internal("Failed to create category")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/categories",
+    tag = "catalog",
+    summary = "List categories",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Category>>),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn list_categories(
+    repo: web::Data<CatalogRepository>,
+) -> impl Responder {
+    match span("CatalogRepository::list_categories", repo.list_categories()).await {
+        Ok(categories) => ApiResponse::ok(categories),
+        Err(e) => {
+            log::error!("DB error listing categories: {:?}", e);
+            ApiResponse::internal("Failed to fetch categories")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/categories/tree",
+    tag = "catalog",
+    summary = "Categories as a tree",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<CategoryNode>>),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_category_tree(
+    repo: web::Data<CatalogRepository>,
+) -> impl Responder {
+    match span("CatalogRepository::category_tree", repo.category_tree()).await {
+        Ok(tree) => ApiResponse::ok(tree),
+        Err(e) => {
+            log::error!("DB error building category tree: {:?}", e);
+            ApiResponse::internal("Failed to fetch categories")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/categories/{id}",
+    tag = "catalog",
+    summary = "Get a category",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Category>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_category(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let category_id = path.into_inner();
+
+    match span("CatalogRepository::get_category", repo.get_category(category_id)).await {
+        Ok(category) => ApiResponse::ok(category),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Category with id {} not found", category_id)),
+        Err(e) => {
+            log::error!("DB error fetching category: {:?}", e);
+            ApiResponse::internal("Database error")
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/db/categories/{id}",
+    tag = "catalog",
+    request_body = UpdateCategory,
+    summary = "Rename or move a category",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Category>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn update_category(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+    req: Valid<UpdateCategory>,
+) -> impl Responder {
+    let category_id = path.into_inner();
+
+    match span("CatalogRepository::update_category", repo.update_category(category_id, &req)).await {
+        Ok(category) => ApiResponse::ok(category),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Category with id {} not found", category_id)),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error updating category: {:?}", e);
+            ApiResponse::internal("Failed to update category")
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/db/categories/{id}",
+    tag = "catalog",
+    summary = "Delete an empty category",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Category>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn delete_category(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let category_id = path.into_inner();
+
+    match span("CatalogRepository::delete_category", repo.delete_category(category_id)).await {
+        Ok(category) => ApiResponse::ok(category),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Category with id {} not found", category_id)),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error deleting category: {:?}", e);
+            ApiResponse::internal("Failed to delete category")
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/tags",
+    tag = "catalog",
+    request_body = TagRequest,
+    summary = "Create a tag",
+    responses(
+        (status = 201, description = "Created", body = Envelope<Tag>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn create_tag(
+    repo: web::Data<CatalogRepository>,
+    req: Valid<TagRequest>,
+) -> impl Responder {
+    match span("CatalogRepository::create_tag", repo.create_tag(&req)).await {
+        Ok(tag) => ApiResponse::created(tag),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error creating tag: {:?}", e);
+            ApiResponse::internal("Failed to create tag")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/tags",
+    tag = "catalog",
+    summary = "List tags",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Tag>>),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn list_tags(
+    repo: web::Data<CatalogRepository>,
+) -> impl Responder {
+    match span("CatalogRepository::list_tags", repo.list_tags()).await {
+        Ok(tags) => ApiResponse::ok(tags),
+        Err(e) => {
+            log::error!("DB error listing tags: {:?}", e);
+            ApiResponse::internal("Failed to fetch tags")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/tags/{id}",
+    tag = "catalog",
+    summary = "Get a tag",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Tag>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_tag(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let tag_id = path.into_inner();
+
+    match span("CatalogRepository::get_tag", repo.get_tag(tag_id)).await {
+        Ok(tag) => ApiResponse::ok(tag),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Tag with id {} not found", tag_id)),
+        Err(e) => {
+            log::error!("DB error fetching tag: {:?}", e);
+            ApiResponse::internal("Database error")
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/db/tags/{id}",
+    tag = "catalog",
+    request_body = TagRequest,
+    summary = "Rename a tag",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Tag>),
+        (status = 400, description = "Invalid request, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 409, description = "Conflict", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn rename_tag(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+    req: Valid<TagRequest>,
+) -> impl Responder {
+    let tag_id = path.into_inner();
+
+    match span("CatalogRepository::rename_tag", repo.rename_tag(tag_id, &req)).await {
+        Ok(tag) => ApiResponse::ok(tag),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Tag with id {} not found", tag_id)),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(RepoError::Conflict(msg)) => ApiResponse::conflict(msg),
+        Err(e) => {
+            log::error!("DB error renaming tag: {:?}", e);
+            ApiResponse::internal("Failed to rename tag")
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/db/tags/{id}",
+    tag = "catalog",
+    summary = "Delete a tag",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Tag>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn delete_tag(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let tag_id = path.into_inner();
+
+    match span("CatalogRepository::delete_tag", repo.delete_tag(tag_id)).await {
+        Ok(tag) => ApiResponse::ok(tag),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Tag with id {} not found", tag_id)),
+        Err(e) => {
+            log::error!("DB error deleting tag: {:?}", e);
+            ApiResponse::internal("Failed to delete tag")
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/items/{id}/tags",
+    tag = "catalog",
+    summary = "Tags of an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Tag>>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_item_tags(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+
+    match span("CatalogRepository::get_item_tags", repo.get_item_tags(item_id)).await {
+        Ok(tags) => ApiResponse::ok(tags),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Item with id {} not found", item_id)),
+        Err(e) => {
+            log::error!("DB error fetching item tags: {:?}", e);
+            ApiResponse::internal("Failed to fetch item tags")
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/db/items/{id}/tags",
+    tag = "catalog",
+    request_body = SetItemTags,
+    summary = "Replace the tags of an item",
+    responses(
+        (status = 200, description = "OK", body = Envelope<Vec<Tag>>),
+        (status = 400, description = "Unknown tags, or the body failed validation", body = ErrorEnvelope),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn set_item_tags(
+    repo: web::Data<CatalogRepository>,
+    path: web::Path<Uuid>,
+    req: Valid<SetItemTags>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+
+    match span("CatalogRepository::set_item_tags", repo.set_item_tags(item_id, &req)).await {
+        Ok(tags) => ApiResponse::ok(tags),
+        Err(RepoError::NotFound) => ApiResponse::not_found(format!("Item with id {} not found", item_id)),
+        Err(RepoError::Validation(msg)) => ApiResponse::bad_request(msg),
+        Err(e) => {
+            log::error!("DB error setting item tags: {:?}", e);
+            ApiResponse::internal("Failed to set item tags")
+        }
+    }
+}
+
+// bulk import and export db handler
+#[utoipa::path(
+    post,
+    path = "/db/users/import",
+    tag = "imports",
+    request_body(content((String = "text/csv"), (String = "application/x-ndjson")), description = "The file to import"),
+    params(FormatQuery),
+    summary = "Import users from CSV or NDJSON in the background",
+    responses(
+        (status = 202, description = "Queued, the report is at /db/imports/{id}", body = Envelope<ImportAccepted>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+        (status = 503, description = "The job queue is full", body = ErrorEnvelope),
+    )
+)]
+pub async fn import_users(
+    repo: web::Data<BulkRepository>,
+    queue: web::Data<JobQueue>,
+    req: HttpRequest,
+    query: web::Query<FormatQuery>,
+    body: String,
+) -> impl Responder {
+    start_import(&repo, &queue, ImportKind::Users, &req, &query, body).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/db/items/import",
+    tag = "imports",
+    request_body(content((String = "text/csv"), (String = "application/x-ndjson")), description = "The file to import"),
+    params(FormatQuery),
+    summary = "Import items from CSV or NDJSON in the background",
+    responses(
+        (status = 202, description = "Queued, the report is at /db/imports/{id}", body = Envelope<ImportAccepted>),
+        (status = 400, description = "Invalid request", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+        (status = 503, description = "The job queue is full", body = ErrorEnvelope),
+    )
+)]
+pub async fn import_items(
+    repo: web::Data<BulkRepository>,
+    queue: web::Data<JobQueue>,
+    req: HttpRequest,
+    query: web::Query<FormatQuery>,
+    body: String,
+) -> impl Responder {
+    start_import(&repo, &queue, ImportKind::Items, &req, &query, body).await
+}
+
+// Stores the file and queues the job that imports it, the report is at /db/imports/{id}
+async fn start_import(
+    repo: &BulkRepository,
+    queue: &JobQueue,
+    kind: ImportKind,
+    req: &HttpRequest,
+    query: &FormatQuery,
+    body: String,
+) -> ApiResponse {
+    let format = match query.format {
+        Some(format) => format,
+        None => match req.content_type() {
+            "text/csv" => ImportFormat::Csv,
+            "application/x-ndjson" | "application/jsonl" => ImportFormat::Ndjson,
+            _ => return ApiResponse::bad_request("Pass ?format=csv|ndjson or a text/csv or application/x-ndjson Content-Type"),
+        },
+    };
+    if body.trim().is_empty() {
+        return ApiResponse::bad_request("The import file is empty");
+    }
+
+    let import = match span("BulkRepository::create_import", repo.create_import(kind, format, &body)).await {
+        Ok(import) => import,
+        Err(e) => {
+            log::error!("DB error creating import: {:?}", e);
+            return ApiResponse::internal("Failed to store import");
+        }
+    };
+
+    let job = CreateJob {
+        payload: format!("import:{}", import.id),
+        priority: None,
+        max_retries: None,
+        ttl_seconds: None,
+    };
+    match queue.add_job(job).await {
+        Ok(job) => {
+            if let Err(e) = span("BulkRepository::set_import_job", repo.set_import_job(import.id, job.job_id)).await {
+                log::error!("DB error linking import {} to job {}: {:?}", import.id, job.job_id, e);
+            }
+            ApiResponse::accepted(serde_json::json!({
+                "import_id": import.id,
+                "job_id": job.job_id,
+                "status": import.status,
             }))
         }
+        Err(e) => {
+            if let Err(e) = span("BulkRepository::delete_import", repo.delete_import(import.id)).await {
+                log::error!("DB error dropping import {}: {:?}", import.id, e);
+            }
+            ApiResponse::error(ErrorCode::QueueFull, e)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/imports/{id}",
+    tag = "imports",
+    summary = "Progress and rejected rows of an import",
+    responses(
+        (status = 200, description = "OK", body = Envelope<ImportReport>),
+        (status = 404, description = "Not found", body = ErrorEnvelope),
+        (status = 500, description = "Storage error", body = ErrorEnvelope),
+    )
+)]
+pub async fn get_import(
+    repo: web::Data<BulkRepository>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let import_id = path.into_inner();
+
+    match span("BulkRepository::get_import", repo.get_import(import_id)).await {
+        Ok(report) => ApiResponse::ok(report),
+        Err(sqlx::Error::RowNotFound) => ApiResponse::not_found(format!("Import with id {} not found", import_id)),
+        Err(e) => {
+            log::error!("DB error fetching import: {:?}", e);
+            ApiResponse::internal("Database error")
+        }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/db/users/export",
+    tag = "imports",
+    params(FormatQuery),
+    summary = "Export users as CSV or NDJSON",
+    responses(
+        (status = 200, description = "The export file, streamed", content((String = "text/csv"), (String = "application/x-ndjson"))),
+    )
+)]
+pub async fn export_users(
+    repo: web::Data<BulkRepository>,
+    query: web::Query<FormatQuery>,
+) -> impl Responder {
+    export(&repo, ExportKind::Users, &query)
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/items/export",
+    tag = "imports",
+    params(FormatQuery),
+    summary = "Export items as CSV or NDJSON",
+    responses(
+        (status = 200, description = "The export file, streamed", content((String = "text/csv"), (String = "application/x-ndjson"))),
+    )
+)]
+pub async fn export_items(
+    repo: web::Data<BulkRepository>,
+    query: web::Query<FormatQuery>,
+) -> impl Responder {
+    export(&repo, ExportKind::Items, &query)
+}
+
+#[utoipa::path(
+    get,
+    path = "/db/orders/export",
+    tag = "imports",
+    params(FormatQuery),
+    summary = "Export orders as CSV or NDJSON",
+    responses(
+        (status = 200, description = "The export file, streamed", content((String = "text/csv"), (String = "application/x-ndjson"))),
+    )
+)]
+pub async fn export_orders(
+    repo: web::Data<BulkRepository>,
+    query: web::Query<FormatQuery>,
+) -> impl Responder {
+    export(&repo, ExportKind::Orders, &query)
+}
+
+// CSV unless ?format=ndjson, the body is streamed batch by batch
+fn export(
+    repo: &BulkRepository,
+    kind: ExportKind,
+    query: &FormatQuery,
+) -> HttpResponse {
+    let format = query.format.unwrap_or(ImportFormat::Csv);
+    let (content_type, extension) = match format {
+        ImportFormat::Csv => ("text/csv", "csv"),
+        ImportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+    let filename = format!("{:?}.{}", kind, extension).to_lowercase();
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename)))
+        .streaming(repo.export(kind, format))
+}
@@ -0,0 +1,183 @@
+use chrono::Utc;
+use sqlx::{PgPool, PgConnection, Error};
+use uuid::Uuid;
+use crate::models::{Shipment, CreateShipment, DeliverShipment, OrderStatus};
+use crate::repository::error::RepoError;
+use crate::repository::order_db::OrderRepository;
+
+pub struct ShipmentRepository {
+    pool: PgPool
+}
+
+impl ShipmentRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    // Hands a paid order to the carrier and moves it to Shipping
+    pub async fn create_shipment(
+        &self,
+        order_id: Uuid,
+        req: &CreateShipment,
+    ) -> Result<Shipment, RepoError> {
+        if req.carrier.trim().is_empty() || req.tracking_number.trim().is_empty() {
+            return Err(RepoError::Validation("carrier and tracking_number are required".to_string()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let status = lock_order_status(&mut tx, order_id).await?;
+        if status != OrderStatus::Paid {
+            return Err(RepoError::Conflict(format!(
+                "Only Paid orders can be shipped, this one is {:?}", status
+            )));
+        }
+
+        let shipment = sqlx::query_as!(
+            Shipment,
+            r#"
+            INSERT INTO shipments (order_id, carrier, tracking_number, shipped_at)
+            VALUES ($1, $2, $3, COALESCE($4, now()))
+            RETURNING
+                id,
+                order_id,
+                carrier,
+                tracking_number,
+                shipped_at,
+                delivered_at,
+                created_at,
+                updated_at
+            "#,
+            order_id,
+            req.carrier.trim(),
+            req.tracking_number.trim(),
+            req.shipped_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        OrderRepository::set_status(&mut tx, order_id, &status, &OrderStatus::Shipping).await?;
+
+        tx.commit().await?;
+
+        Ok(shipment)
+    }
+
+    // Records delivery and moves the order to Delivered
+    pub async fn mark_delivered(
+        &self,
+        order_id: Uuid,
+        req: &DeliverShipment,
+    ) -> Result<Shipment, RepoError> {
+        let mut tx = self.pool.begin().await?;
+
+        let status = lock_order_status(&mut tx, order_id).await?;
+        if status != OrderStatus::Shipping {
+            return Err(RepoError::Conflict(format!(
+                "Only Shipping orders can be delivered, this one is {:?}", status
+            )));
+        }
+
+        let shipped_at = sqlx::query_scalar!(
+            "SELECT shipped_at FROM shipments WHERE order_id = $1",
+            order_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // The body's validation already keeps delivered_at out of the future
+        let delivered_at = req.delivered_at.unwrap_or_else(Utc::now);
+        if delivered_at < shipped_at {
+            return Err(RepoError::Validation(format!(
+                "delivered_at must not be before the order was shipped at {}", shipped_at
+            )));
+        }
+
+        let shipment = sqlx::query_as!(
+            Shipment,
+            r#"
+            UPDATE shipments
+            SET
+                delivered_at = $2,
+                updated_at = now()
+            WHERE order_id = $1
+            RETURNING
+                id,
+                order_id,
+                carrier,
+                tracking_number,
+                shipped_at,
+                delivered_at,
+                created_at,
+                updated_at
+            "#,
+            order_id,
+            delivered_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        OrderRepository::set_status(&mut tx, order_id, &status, &OrderStatus::Delivered).await?;
+
+        tx.commit().await?;
+
+        Ok(shipment)
+    }
+
+    pub async fn get_shipment(
+        &self,
+        order_id: Uuid,
+    ) -> Result<Shipment, Error> {
+        let mut conn = self.pool.acquire().await?;
+        find_shipment(&mut conn, order_id).await?.ok_or(Error::RowNotFound)
+    }
+}
+
+// The shipment of an order, None until it has been shipped
+pub async fn find_shipment(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+) -> Result<Option<Shipment>, Error> {
+    let shipment = sqlx::query_as!(
+        Shipment,
+        r#"
+        SELECT
+            id,
+            order_id,
+            carrier,
+            tracking_number,
+            shipped_at,
+            delivered_at,
+            created_at,
+            updated_at
+        FROM shipments
+        WHERE order_id = $1
+        "#,
+        order_id
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(shipment)
+}
+
+async fn lock_order_status(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+) -> Result<OrderStatus, Error> {
+    let status = sqlx::query_scalar!(
+        r#"
+        SELECT status as "status: OrderStatus"
+        FROM orders
+        WHERE id = $1 AND deleted_at IS NULL
+        FOR UPDATE
+        "#,
+        order_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(status)
+}
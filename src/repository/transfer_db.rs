@@ -0,0 +1,436 @@
+use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use sqlx::{PgPool, PgConnection, Error};
+use uuid::Uuid;
+use crate::models::{AppState, User, UserDB, Item, ItemDB, Order, OrderDB, OrderStatus, StockMovementReason};
+use crate::repository::error::RepoError;
+use crate::repository::items_db::record_stock_movement;
+use crate::repository::order_db::attach_order_lines;
+use crate::search::ItemIndex;
+
+// Rows written for one entity, skipped ones already had a row with the same id
+#[derive(Debug, Serialize, Default)]
+pub struct TransferCounts {
+    pub inserted: usize,
+    pub skipped: usize,
+    pub skipped_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferReport {
+    pub dry_run: bool,
+    pub users: TransferCounts,
+    pub items: TransferCounts,
+    pub orders: TransferCounts,
+    pub order_lines: usize,
+}
+
+// Moves data between the JSON store and Postgres, ids and timestamps are kept as they are
+pub struct TransferRepository {
+    pool: PgPool
+}
+
+impl TransferRepository {
+    pub fn new(pool: &PgPool) -> Self {
+        Self {
+            pool: pool.clone()
+        }
+    }
+
+    // Inserts every user, item and order of the state in one transaction, rows whose id
+    // is already in Postgres are left alone. A dry run does the same work and rolls it back.
+    // Also returns the rows with the state's ids as Postgres has them before the commit or rollback.
+    pub async fn import_state(
+        &self,
+        state: &AppState,
+        dry_run: bool,
+    ) -> Result<(TransferReport, AppState), RepoError> {
+        let mut tx = self.pool.begin().await?;
+
+        let problems = check_references(&mut tx, state).await?;
+        if !problems.is_empty() {
+            return Err(RepoError::Validation(problems.join("; ")));
+        }
+
+        let mut report = TransferReport {
+            dry_run,
+            users: TransferCounts::default(),
+            items: TransferCounts::default(),
+            orders: TransferCounts::default(),
+            order_lines: 0,
+        };
+
+        // Parents first so the foreign keys hold, in created order to keep the output stable
+        let mut users: Vec<&User> = state.users.values().collect();
+        users.sort_by_key(|user| (user.created_at, user.id));
+        for user in users {
+            let inserted = insert_user(&mut tx, user).await?;
+            count(&mut report.users, user.id, inserted);
+        }
+
+        let mut items: Vec<&Item> = state.items.values().collect();
+        items.sort_by_key(|item| (item.created_at, item.id));
+        for item in items {
+            let inserted = insert_item(&mut tx, item).await?;
+            count(&mut report.items, item.id, inserted);
+        }
+
+        let mut orders: Vec<&Order> = state.orders.values().collect();
+        orders.sort_by_key(|order| (order.created_at, order.id));
+        for order in orders {
+            let inserted = insert_order(&mut tx, order).await?;
+            count(&mut report.orders, order.id, inserted);
+            if inserted {
+                report.order_lines += order.lines().len();
+            }
+        }
+
+        let scope = StateIds {
+            users: state.users.keys().copied().collect(),
+            items: state.items.keys().copied().collect(),
+            orders: state.orders.keys().copied().collect(),
+        };
+        let written = load_state(&mut tx, Some(&scope)).await?;
+
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        Ok((report, written))
+    }
+
+    // Everything that is not soft deleted, shaped like the JSON store.
+    // Users carry their orders so the JSON handlers can find them.
+    pub async fn export_state(&self) -> Result<AppState, Error> {
+        let mut conn = self.pool.acquire().await?;
+        load_state(&mut conn, None).await
+    }
+}
+
+// Ids of the rows to load, all of them when there is no scope
+struct StateIds {
+    users: Vec<Uuid>,
+    items: Vec<Uuid>,
+    orders: Vec<Uuid>,
+}
+
+// Postgres in the JSON store's shape, only the scope's ids when there is one
+async fn load_state(
+    conn: &mut PgConnection,
+    scope: Option<&StateIds>,
+) -> Result<AppState, Error> {
+    let users_db = sqlx::query_as!(
+        UserDB,
+        r#"
+        SELECT id, name, email, is_active, created_at, updated_at, deleted_at
+        FROM users
+        WHERE deleted_at IS NULL AND ($1::uuid[] IS NULL OR id = ANY($1))
+        "#,
+        scope.map(|scope| scope.users.as_slice()) as Option<&[Uuid]>
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let items_db = sqlx::query_as!(
+        ItemDB,
+        r#"
+        SELECT
+            id,
+            name,
+            price_minor,
+            currency,
+            quantity,
+            description,
+            created_at,
+            updated_at,
+            is_active,
+            tax_category_id,
+            category_id,
+            deleted_at
+        FROM items
+        WHERE deleted_at IS NULL AND ($1::uuid[] IS NULL OR id = ANY($1))
+        "#,
+        scope.map(|scope| scope.items.as_slice()) as Option<&[Uuid]>
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let orders_db = sqlx::query_as!(
+        OrderDB,
+        r#"
+        SELECT
+            id,
+            user_id,
+            subtotal_minor,
+            discount_minor,
+            tax_minor,
+            amount_minor,
+            currency,
+            coupon_code,
+            status as "status: OrderStatus",
+            created_at,
+            updated_at,
+            deleted_at
+        FROM orders
+        WHERE deleted_at IS NULL AND ($1::uuid[] IS NULL OR id = ANY($1))
+        ORDER BY created_at DESC
+        "#,
+        scope.map(|scope| scope.orders.as_slice()) as Option<&[Uuid]>
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let orders = attach_order_lines(conn, orders_db, true).await?;
+
+    let mut orders_by_user: HashMap<Uuid, Vec<Order>> = HashMap::new();
+    for order in &orders {
+        orders_by_user.entry(order.user_id).or_default().push(order.clone());
+    }
+
+    let users = users_db
+        .into_iter()
+        .map(|user_db| User {
+            orders: orders_by_user.remove(&user_db.id).unwrap_or_default(),
+            id: user_db.id,
+            name: user_db.name,
+            email: user_db.email,
+            is_active: user_db.is_active,
+            created_at: user_db.created_at,
+            updated_at: user_db.updated_at,
+            deleted_at: user_db.deleted_at,
+        })
+        .map(|user| (user.id, user))
+        .collect();
+
+    let items: HashMap<Uuid, Item> = items_db
+        .into_iter()
+        .map(|item_db| (item_db.id, Item::from(item_db)))
+        .collect();
+
+    Ok(AppState {
+        users,
+        orders: orders.into_iter().map(|order| (order.id, order)).collect(),
+        item_index: ItemIndex::build(items.values()),
+        items,
+        data_file: Default::default(),
+    })
+}
+
+fn count(counts: &mut TransferCounts, id: Uuid, inserted: bool) {
+    if inserted {
+        counts.inserted += 1;
+    } else {
+        counts.skipped += 1;
+        counts.skipped_ids.push(id);
+    }
+}
+
+// References the foreign keys would reject, found up front so they are all reported at once
+async fn check_references(
+    conn: &mut PgConnection,
+    state: &AppState,
+) -> Result<Vec<String>, Error> {
+    let mut problems = Vec::new();
+
+    let user_ids: Vec<Uuid> = state.orders.values().map(|order| order.user_id).collect();
+    let known_users: HashSet<Uuid> = sqlx::query_scalar!("SELECT id FROM users WHERE id = ANY($1)", &user_ids)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .chain(state.users.keys().copied())
+        .collect();
+
+    let item_ids: Vec<Uuid> = state.orders.values()
+        .flat_map(|order| order.lines().iter().map(|line| line.item_id))
+        .collect();
+    let known_items: HashSet<Uuid> = sqlx::query_scalar!("SELECT id FROM items WHERE id = ANY($1)", &item_ids)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .chain(state.items.keys().copied())
+        .collect();
+
+    let codes: Vec<String> = state.orders.values().filter_map(|order| order.coupon_code.clone()).collect();
+    let known_codes: HashSet<String> = sqlx::query_scalar!("SELECT code FROM coupons WHERE code = ANY($1)", &codes)
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .collect();
+
+    let tax_category_ids: Vec<Uuid> = state.items.values().filter_map(|item| item.tax_category_id).collect();
+    let known_tax_categories: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT id FROM tax_categories WHERE id = ANY($1)",
+        &tax_category_ids
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    let category_ids: Vec<Uuid> = state.items.values().filter_map(|item| item.category_id).collect();
+    let known_categories: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT id FROM categories WHERE id = ANY($1)",
+        &category_ids
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    for order in state.orders.values() {
+        if !known_users.contains(&order.user_id) {
+            problems.push(format!("Order {} belongs to unknown user {}", order.id, order.user_id));
+        }
+        for line in order.lines() {
+            if !known_items.contains(&line.item_id) {
+                problems.push(format!("Order {} has unknown item {}", order.id, line.item_id));
+            }
+        }
+        if let Some(ref code) = order.coupon_code
+            && !known_codes.contains(code)
+        {
+            problems.push(format!("Order {} uses unknown coupon {}", order.id, code));
+        }
+    }
+    for item in state.items.values() {
+        if let Some(id) = item.tax_category_id
+            && !known_tax_categories.contains(&id)
+        {
+            problems.push(format!("Item {} has unknown tax category {}", item.id, id));
+        }
+        if let Some(id) = item.category_id
+            && !known_categories.contains(&id)
+        {
+            problems.push(format!("Item {} has unknown category {}", item.id, id));
+        }
+    }
+
+    problems.sort();
+    Ok(problems)
+}
+
+async fn insert_user(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Result<bool, RepoError> {
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (id, name, email, is_active, created_at, updated_at, deleted_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (id) DO NOTHING
+        RETURNING id
+        "#,
+        user.id,
+        user.name,
+        user.email,
+        user.is_active,
+        user.created_at,
+        user.updated_at,
+        user.deleted_at
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| match e {
+        Error::Database(ref db) if db.is_unique_violation() => RepoError::Conflict(format!(
+            "Email {} of user {} already belongs to another user", user.email, user.id
+        )),
+        other => other.into(),
+    })?;
+
+    Ok(inserted.is_some())
+}
+
+// A new item gets an Initial ledger entry for its quantity, like a created one
+async fn insert_item(
+    conn: &mut PgConnection,
+    item: &Item,
+) -> Result<bool, RepoError> {
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO items (
+            id, name, price_minor, currency, quantity, description, is_active,
+            tax_category_id, category_id, created_at, updated_at, deleted_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        ON CONFLICT (id) DO NOTHING
+        RETURNING id
+        "#,
+        item.id,
+        item.name,
+        item.price.amount_minor,
+        item.price.currency,
+        item.quantity,
+        item.description,
+        item.is_active,
+        item.tax_category_id,
+        item.category_id,
+        item.created_at,
+        item.updated_at,
+        item.deleted_at
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if inserted.is_some() && item.quantity != 0 {
+        record_stock_movement(conn, item.id, None, item.quantity, StockMovementReason::Initial).await?;
+    }
+
+    Ok(inserted.is_some())
+}
+
+// Stock is not touched, the JSON store already took ordered quantities off the items
+async fn insert_order(
+    conn: &mut PgConnection,
+    order: &Order,
+) -> Result<bool, RepoError> {
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO orders (
+            id, user_id, subtotal_minor, discount_minor, tax_minor, amount_minor, currency,
+            coupon_code, status, created_at, updated_at, deleted_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        ON CONFLICT (id) DO NOTHING
+        RETURNING id
+        "#,
+        order.id,
+        order.user_id,
+        order.subtotal.amount_minor,
+        order.discount.amount_minor,
+        order.tax.amount_minor,
+        order.amount.amount_minor,
+        order.amount.currency,
+        order.coupon_code,
+        order.status.clone() as OrderStatus,
+        order.created_at,
+        order.updated_at,
+        order.deleted_at
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if inserted.is_none() {
+        return Ok(false);
+    }
+
+    for line in order.lines() {
+        sqlx::query!(
+            r#"
+            INSERT INTO order_items (order_id, item_id, quantity, name, unit_price_minor, currency, tax_rate_bps)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            order.id,
+            line.item_id,
+            line.quantity,
+            line.name,
+            line.unit_price.amount_minor,
+            line.unit_price.currency,
+            line.tax_rate_bps
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(true)
+}
@@ -1,6 +1,16 @@
-use sqlx::{PgPool, Error};
+use std::collections::HashMap;
+use sqlx::{PgPool, Error, QueryBuilder};
 use uuid::Uuid;
-use crate::models::{User, UserDB, CreateUser, UpdateUser, Order, OrderStatus, OrderDB, Item};
+use crate::models::{User, UserDB, CreateUser, UpdateUser, OrderStatus, OrderDB, Order, BulkDeleteResult, ListParams, Page};
+use crate::repository::order_db::attach_order_lines;
+use crate::repository::error::RepoError;
+use crate::repository::pagination::{PageRequest, SortField, SortKind};
+
+const USER_SORTS: &[SortField] = &[
+    SortField { name: "created_at", column: "created_at", kind: SortKind::Timestamp },
+    SortField { name: "name", column: "name", kind: SortKind::Text },
+    SortField { name: "email", column: "email", kind: SortKind::Text },
+];
 
 pub struct UserRepository {
     pool: PgPool,
@@ -26,7 +36,8 @@ impl UserRepository {
                 email,
                 is_active,
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             "#,
             req.name,
             req.email
@@ -34,12 +45,13 @@ impl UserRepository {
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(self.enrich_user(user_db).await?)
+        self.enrich_user(user_db).await
     }
 
     pub async fn get_user(
         &self,
         id: Uuid,
+        include_deleted: bool,
     ) -> Result<User, Error> {
         let user_db = sqlx::query_as!(
             UserDB,
@@ -50,16 +62,18 @@ impl UserRepository {
                 email,
                 is_active,
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             FROM users
-            WHERE id = $1
+            WHERE id = $1 AND ($2 OR deleted_at IS NULL)
             "#,
-            id
+            id,
+            include_deleted
         )
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(self.enrich_user(user_db).await?)
+        self.enrich_user(user_db).await
     }
 
     pub async fn update_user(
@@ -75,14 +89,15 @@ impl UserRepository {
                 name = COALESCE($1, name),
                 email = COALESCE($2, email),
                 is_active = COALESCE($3, is_active)
-            WHERE id = $4
+            WHERE id = $4 AND deleted_at IS NULL
             RETURNING
                 id,
                 name,
                 email,
                 is_active,
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             "#,
             req.name.as_ref(),
             req.email.as_ref(),
@@ -92,102 +107,156 @@ impl UserRepository {
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(self.enrich_user(user_db).await?)
+        self.enrich_user(user_db).await
+    }
+
+    // Soft delete, the user and their order history stay until the purge job removes them.
+    // RowNotFound when there is no live user with this id.
+    pub async fn delete_user(&self, id: Uuid) -> Result<User, Error> {
+        let user_db = sqlx::query_as!(
+            UserDB,
+            r#"
+            UPDATE users
+            SET deleted_at = now()
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING
+                id,
+                name,
+                email,
+                is_active,
+                created_at,
+                updated_at,
+                deleted_at
+            "#,
+            id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.enrich_user(user_db).await
     }
 
-    pub async fn delete_user(&self, id: Uuid) -> Result<(), Error> {
-        sqlx::query!("DELETE FROM users WHERE id = $1", id)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
+    pub async fn delete_users(&self, ids: &[Uuid]) -> Result<Vec<BulkDeleteResult>, Error> {
+        let deleted = sqlx::query_scalar!(
+            r#"
+            UPDATE users
+            SET deleted_at = now()
+            WHERE id = ANY($1) AND deleted_at IS NULL
+            RETURNING id
+            "#,
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(BulkDeleteResult::for_ids(ids, &deleted))
     }
 
-    pub async fn list_users(&self) -> Result<Vec<User>, Error> {
-        let users_db = sqlx::query_as!(
+    pub async fn restore_user(&self, id: Uuid) -> Result<User, Error> {
+        let user_db = sqlx::query_as!(
             UserDB,
             r#"
-            SELECT
+            UPDATE users
+            SET deleted_at = NULL, updated_at = now()
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING
                 id,
                 name,
                 email,
                 is_active,
                 created_at,
-                updated_at
-            FROM users
-            ORDER BY created_at DESC
-            "#
+                updated_at,
+                deleted_at
+            "#,
+            id
         )
-        .fetch_all(&self.pool)
+        .fetch_one(&self.pool)
         .await?;
 
-        let mut users = Vec::with_capacity(users_db.len());
-        for user_db in users_db {
-            users.push(self.enrich_user(user_db).await?);
-        }
+        self.enrich_user(user_db).await
+    }
+
+    pub async fn list_users(
+        &self,
+        params: &ListParams,
+        expand_items: bool,
+    ) -> Result<Page<User>, RepoError> {
+        let page = PageRequest::new(params, USER_SORTS)?;
+
+        let mut qb = QueryBuilder::new(
+            "SELECT id, name, email, is_active, created_at, updated_at, deleted_at FROM users WHERE true"
+        );
+        page.push_conditions(&mut qb);
+        page.push_order_and_limit(&mut qb);
 
-        Ok(users)
+        let users_db: Vec<UserDB> = qb.build_query_as().fetch_all(&self.pool).await?;
+        let (users_db, next_cursor) = page.finish(users_db, |user, column| {
+            let value = match column {
+                "name" => user.name.clone(),
+                "email" => user.email.clone(),
+                _ => user.created_at.to_rfc3339(),
+            };
+            (user.id, value)
+        });
+
+        let users = self.enrich_users(users_db, expand_items).await?;
+
+        Ok(Page { results: users, next_cursor })
     }
 
-    /// Populate orders separately (this is the key idea)
+    // A single user always comes with the lines of their orders
     async fn enrich_user(&self, user_db: UserDB) -> Result<User, Error> {
+        let mut users = self.enrich_users(vec![user_db], true).await?;
+        Ok(users.remove(0))
+    }
+
+    /// Populate orders separately, one query for the orders of all users and one for their lines
+    async fn enrich_users(&self, users_db: Vec<UserDB>, expand_items: bool) -> Result<Vec<User>, Error> {
+        let user_ids: Vec<Uuid> = users_db.iter().map(|user| user.id).collect();
+        let mut conn = self.pool.acquire().await?;
+
         let orders_db = sqlx::query_as!(
             OrderDB,
             r#"
             SELECT
                 id,
                 user_id,
-                amount,
+                subtotal_minor,
+                discount_minor,
+                tax_minor,
+                amount_minor,
+                currency,
+                coupon_code,
                 status as "status: OrderStatus",
                 created_at,
-                updated_at
+                updated_at,
+                deleted_at
             FROM orders
-            WHERE user_id = $1
+            WHERE user_id = ANY($1) AND deleted_at IS NULL
             ORDER BY created_at DESC
             "#,
-            user_db.id
+            &user_ids
         )
-        .fetch_all(&self.pool)
+        .fetch_all(&mut *conn)
         .await?;
 
-        let mut orders = Vec::new();
-        for order_db in orders_db {
-            let items = self.get_order_items(order_db.id).await?;
-            orders.push(Order {
-                id: order_db.id,
-                user_id: order_db.user_id,
-                items,
-                amount: order_db.amount,
-                status: order_db.status,
-                created_at: order_db.created_at,
-                updated_at: order_db.updated_at,
-            });
+        let mut orders_by_user: HashMap<Uuid, Vec<Order>> = HashMap::with_capacity(user_ids.len());
+        for order in attach_order_lines(&mut conn, orders_db, expand_items).await? {
+            orders_by_user.entry(order.user_id).or_default().push(order);
         }
 
-        Ok(User {
-            id: user_db.id,
-            name: user_db.name,
-            email: user_db.email,
-            is_active: user_db.is_active,
-            created_at: user_db.created_at,
-            updated_at: user_db.updated_at,
-            orders,
-        })
-    }
-
-    async fn get_order_items(&self, order_id: Uuid) -> Result<Vec<Item>, Error> {
-        let items = sqlx::query_as!(
-            Item,
-            r#"
-            SELECT i.*
-            FROM items i
-            INNER JOIN order_items oi ON i.id = oi.item_id
-            WHERE oi.order_id = $1
-            "#,
-            order_id
-        )
-        .fetch_all(&self.pool)
-        .await?;
-    
-        Ok(items)
+        Ok(users_db
+            .into_iter()
+            .map(|user_db| User {
+                orders: orders_by_user.remove(&user_db.id).unwrap_or_default(),
+                id: user_db.id,
+                name: user_db.name,
+                email: user_db.email,
+                is_active: user_db.is_active,
+                created_at: user_db.created_at,
+                updated_at: user_db.updated_at,
+                deleted_at: user_db.deleted_at,
+            })
+            .collect())
     }
 }
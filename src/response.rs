@@ -0,0 +1,270 @@
+use actix_web::body::BoxBody;
+use actix_web::error::{ErrorNotFound, InternalError, JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use crate::config::{AppConfig, ResponseFormat};
+use crate::logging::current_request_id;
+
+// Lets one client pick envelope or legacy regardless of server.response_format
+pub const RESPONSE_FORMAT_HEADER: &str = "x-response-format";
+
+// Stable machine readable error codes, each one always comes with the same status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    InvalidJson,
+    ValidationFailed,
+    NotFound,
+    Conflict,
+    InsufficientStock,
+    PaymentDeclined,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    QueueFull,
+    DatabaseUnavailable,
+    NotReady,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidJson
+            | ErrorCode::ValidationFailed => StatusCode::BAD_REQUEST,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict | ErrorCode::InsufficientStock => StatusCode::CONFLICT,
+            ErrorCode::PaymentDeclined => StatusCode::PAYMENT_REQUIRED,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::QueueFull | ErrorCode::DatabaseUnavailable | ErrorCode::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorPayload {
+    code: ErrorCode,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+// What every handler returns, rendered once the request's format is known:
+//   envelope: {"data": .., "message"?: .., "request_id": ..}
+//             {"error": {"code": .., "message": .., "details"?: ..}, "request_id": ..}
+//   legacy:   the bare data, {"error": message, ..details} or the body set with legacy()
+pub struct ApiResponse {
+    status: StatusCode,
+    data: Option<Value>,
+    message: Option<String>,
+    error: Option<ErrorPayload>,
+    legacy: Option<Value>,
+    retry_after_secs: Option<u64>,
+}
+
+impl ApiResponse {
+    fn success(status: StatusCode, data: impl Serialize) -> Self {
+        match serde_json::to_value(data) {
+            Ok(data) => Self {
+                status,
+                data: Some(data),
+                message: None,
+                error: None,
+                legacy: None,
+                retry_after_secs: None,
+            },
+            Err(e) => {
+                log::error!("Failed to serialize response: {}", e);
+                Self::internal("Failed to serialize response")
+            }
+        }
+    }
+
+    pub fn ok(data: impl Serialize) -> Self {
+        Self::success(StatusCode::OK, data)
+    }
+
+    pub fn created(data: impl Serialize) -> Self {
+        Self::success(StatusCode::CREATED, data)
+    }
+
+    pub fn accepted(data: impl Serialize) -> Self {
+        Self::success(StatusCode::ACCEPTED, data)
+    }
+
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            status: code.status(),
+            data: None,
+            message: None,
+            error: Some(ErrorPayload { code, message: message.into(), details: None }),
+            legacy: None,
+            retry_after_secs: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::error(ErrorCode::InvalidRequest, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::error(ErrorCode::NotFound, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::error(ErrorCode::Conflict, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::error(ErrorCode::Internal, message)
+    }
+
+    // Only shown next to data, errors carry their own message
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    // An object, in legacy mode its fields sit next to "error"
+    pub fn details(mut self, details: impl Serialize) -> Self {
+        if let Some(error) = self.error.as_mut() {
+            error.details = serde_json::to_value(details).ok();
+        }
+        self
+    }
+
+    // The exact body legacy clients got from this handler before the envelope
+    pub fn legacy(mut self, body: Value) -> Self {
+        self.legacy = Some(body);
+        self
+    }
+
+    // The JSON store's older bodies, {"message": .., "<key>": data}, call after message()
+    pub fn legacy_under(mut self, key: &str) -> Self {
+        let mut body = serde_json::Map::new();
+        if let Some(message) = &self.message {
+            body.insert("message".to_string(), Value::String(message.clone()));
+        }
+        body.insert(key.to_string(), self.data.clone().unwrap_or(Value::Null));
+        self.legacy = Some(Value::Object(body));
+        self
+    }
+
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        self.retry_after_secs = Some(secs);
+        self
+    }
+
+    pub fn render(self, req: &HttpRequest) -> HttpResponse {
+        let body = match response_format(req) {
+            ResponseFormat::Envelope => self.envelope(),
+            ResponseFormat::Legacy => self.legacy_body(),
+        };
+        let mut response = HttpResponse::build(self.status);
+        if let Some(secs) = self.retry_after_secs {
+            response.insert_header(("Retry-After", secs.to_string()));
+        }
+        response.json(body)
+    }
+
+    fn envelope(&self) -> Value {
+        let mut body = serde_json::Map::new();
+        match &self.error {
+            Some(error) => {
+                body.insert("error".to_string(), serde_json::to_value(error).unwrap_or(Value::Null));
+            }
+            None => {
+                body.insert("data".to_string(), self.data.clone().unwrap_or(Value::Null));
+                if let Some(message) = &self.message {
+                    body.insert("message".to_string(), Value::String(message.clone()));
+                }
+            }
+        }
+        body.insert("request_id".to_string(), current_request_id().map_or(Value::Null, Value::String));
+        Value::Object(body)
+    }
+
+    fn legacy_body(&self) -> Value {
+        if let Some(body) = &self.legacy {
+            return body.clone();
+        }
+        match &self.error {
+            Some(error) => {
+                let mut body = serde_json::Map::new();
+                body.insert("error".to_string(), Value::String(error.message.clone()));
+                if let Some(Value::Object(details)) = &error.details {
+                    body.extend(details.clone());
+                }
+                Value::Object(body)
+            }
+            None => self.data.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+impl Responder for ApiResponse {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        self.render(req)
+    }
+}
+
+// The X-Response-Format header when it names a format, otherwise server.response_format
+pub fn response_format(req: &HttpRequest) -> ResponseFormat {
+    let requested = req
+        .headers()
+        .get(RESPONSE_FORMAT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().to_ascii_lowercase().parse().ok());
+    requested.unwrap_or_else(|| {
+        req.app_data::<web::Data<AppConfig>>()
+            .map_or(ResponseFormat::Envelope, |config| config.server.response_format)
+    })
+}
+
+// Extractor errors in the envelope, legacy clients keep actix's plain text bodies
+fn extractor_error<E>(err: E, req: &HttpRequest, code: ErrorCode) -> actix_web::Error
+where
+    E: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    let response = ApiResponse::error(code, err.to_string()).render(req);
+    InternalError::from_response(err, response).into()
+}
+
+fn is_legacy(req: &HttpRequest) -> bool {
+    response_format(req) == ResponseFormat::Legacy
+}
+
+pub fn json_error(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    if is_legacy(req) {
+        return err.into();
+    }
+    let code = match err {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => ErrorCode::PayloadTooLarge,
+        JsonPayloadError::ContentType => ErrorCode::UnsupportedMediaType,
+        _ => ErrorCode::InvalidJson,
+    };
+    extractor_error(err, req, code)
+}
+
+pub fn query_error(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    if is_legacy(req) {
+        return err.into();
+    }
+    extractor_error(err, req, ErrorCode::InvalidRequest)
+}
+
+// actix answers 404 for a path segment that does not parse, such as an id that is not a uuid
+pub fn path_error(err: PathError, req: &HttpRequest) -> actix_web::Error {
+    if is_legacy(req) {
+        return ErrorNotFound(err);
+    }
+    extractor_error(err, req, ErrorCode::NotFound)
+}
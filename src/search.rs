@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use crate::models::Item;
+
+// A word in the name counts more than one in the description, like the A and B weights in Postgres
+const NAME_WEIGHT: f32 = 1.0;
+const DESCRIPTION_WEIGHT: f32 = 0.4;
+
+// Words kept around the first match in a snippet, like MaxWords in ts_headline
+const SNIPPET_WORDS: usize = 20;
+const SNIPPET_LEAD: usize = 5;
+
+// What ts_headline is asked to put around matches, control characters it can't get from escaped item text
+const MATCH_START: char = '\u{2}';
+const MATCH_STOP: char = '\u{3}';
+
+// Lower cased alphanumeric words, documents and queries are split the same way
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// In-memory inverted index over item names and descriptions for the JSON backend
+#[derive(Debug, Default)]
+pub struct ItemIndex {
+    // token -> item -> weighted number of occurrences
+    postings: HashMap<String, HashMap<Uuid, f32>>,
+    // Tokens of each item so an update or removal can drop its old postings
+    terms: HashMap<Uuid, HashSet<String>>,
+}
+
+impl ItemIndex {
+    pub fn build<'a>(items: impl IntoIterator<Item = &'a Item>) -> Self {
+        let mut index = Self::default();
+        for item in items {
+            index.insert(item);
+        }
+        index
+    }
+
+    // Indexes an item, replacing whatever was indexed for it before
+    pub fn insert(&mut self, item: &Item) {
+        self.remove(item.id);
+
+        let mut weights: HashMap<String, f32> = HashMap::new();
+        for token in tokenize(&item.name) {
+            *weights.entry(token).or_default() += NAME_WEIGHT;
+        }
+        for token in tokenize(item.description.as_deref().unwrap_or_default()) {
+            *weights.entry(token).or_default() += DESCRIPTION_WEIGHT;
+        }
+
+        let mut terms = HashSet::with_capacity(weights.len());
+        for (token, weight) in weights {
+            self.postings.entry(token.clone()).or_default().insert(item.id, weight);
+            terms.insert(token);
+        }
+        self.terms.insert(item.id, terms);
+    }
+
+    pub fn remove(&mut self, id: Uuid) {
+        let Some(terms) = self.terms.remove(&id) else {
+            return;
+        };
+        for token in terms {
+            if let Some(items) = self.postings.get_mut(&token) {
+                items.remove(&id);
+                if items.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+    }
+
+    // Items containing every word of the query, best match first
+    pub fn search(&self, query: &str) -> Vec<(Uuid, f32)> {
+        let tokens: HashSet<String> = tokenize(query).into_iter().collect();
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+
+        let mut postings = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match self.postings.get(token) {
+                Some(items) => postings.push(items),
+                None => return Vec::new(),
+            }
+        }
+        // Walk the rarest token and look the candidates up in the others
+        postings.sort_by_key(|items| items.len());
+
+        let mut hits: Vec<(Uuid, f32)> = postings[0]
+            .iter()
+            .filter_map(|(id, weight)| {
+                postings[1..]
+                    .iter()
+                    .try_fold(*weight, |rank, items| items.get(id).map(|w| rank + w))
+                    .map(|rank| (*id, rank))
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits
+    }
+}
+
+// Name and description with the words matching the query wrapped in <mark>, cut to a window around the first match
+pub fn highlight(item: &Item, query: &str) -> String {
+    let tokens: HashSet<String> = tokenize(query).into_iter().collect();
+    let text = match item.description {
+        Some(ref description) => format!("{} {}", item.name, description),
+        None => item.name.clone(),
+    };
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let matches = |word: &str| tokenize(word).iter().any(|token| tokens.contains(token));
+
+    let first = words.iter().position(|word| matches(word)).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_LEAD);
+    let end = (start + SNIPPET_WORDS).min(words.len());
+
+    words[start..end]
+        .iter()
+        .map(|word| {
+            if matches(word) {
+                format!("<mark>{}</mark>", escape_html(word))
+            } else {
+                escape_html(word)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Item text is user input, it is escaped before <mark> goes in so the snippet is safe to render as HTML
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            MATCH_START | MATCH_STOP => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Turns a ts_headline snippet built with MATCH_START and MATCH_STOP into escaped HTML with <mark>
+pub fn mark_headline(headline: &str) -> String {
+    let mut marked = String::with_capacity(headline.len());
+    for c in headline.chars() {
+        match c {
+            MATCH_START => marked.push_str("<mark>"),
+            MATCH_STOP => marked.push_str("</mark>"),
+            c => marked.push_str(&escape_html(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crate::money::Money;
+
+    fn item(name: &str, description: Option<&str>) -> Item {
+        Item {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            price: Money::zero("USD"),
+            quantity: 0,
+            description: description.map(str::to_string),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            is_active: true,
+            tax_category_id: None,
+            category_id: None,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn highlight_escapes_item_text() {
+        let widget = item("<b>Widget</b>", Some("fits \"R&D\" <script>alert(1)</script>"));
+        assert_eq!(
+            highlight(&widget, "widget"),
+            "<mark>&lt;b&gt;Widget&lt;/b&gt;</mark> fits &quot;R&amp;D&quot; &lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+    }
+
+    #[test]
+    fn headline_markers_become_marks_after_escaping() {
+        let headline = format!("a {}<i>widget</i>{} & more", MATCH_START, MATCH_STOP);
+        assert_eq!(mark_headline(&headline), "a <mark>&lt;i&gt;widget&lt;/i&gt;</mark> &amp; more");
+        assert_eq!(escape_html(&format!("x{}y{}'", MATCH_START, MATCH_STOP)), "xy&#39;");
+    }
+}
@@ -0,0 +1,105 @@
+use std::io;
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use uuid::Uuid;
+use crate::cli::SeedArgs;
+use crate::config::AppConfig;
+use crate::models::{CreateUser, CreateItem, User, Item};
+use crate::money::Money;
+use crate::repository::db::{self, MIGRATOR};
+use crate::repository::items_db::ItemRepository;
+use crate::repository::users_db::UserRepository;
+use crate::utils::{load_data_from_file, write_to_file};
+
+const FIRST_NAMES: &[&str] = &[
+    "Amara", "Ben", "Chioma", "Daniel", "Elena", "Femi", "Grace", "Hiro", "Ifeoma", "James",
+    "Kemi", "Liam", "Maya", "Noah", "Olga", "Priya", "Quinn", "Rosa", "Samuel", "Tara",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Adeyemi", "Brown", "Costa", "Dubois", "Eze", "Fischer", "Garcia", "Hansen", "Ito", "Johnson",
+    "Kowalski", "Lopez", "Mensah", "Nakamura", "Okafor", "Patel", "Rossi", "Silva", "Tanaka", "Walker",
+];
+
+const EMAIL_DOMAINS: &[&str] = &["example.com", "example.org", "mail.example.net"];
+
+const ADJECTIVES: &[&str] = &[
+    "Classic", "Compact", "Deluxe", "Ergonomic", "Handmade", "Lightweight", "Organic", "Premium",
+    "Rustic", "Sleek", "Sturdy", "Vintage", "Waterproof", "Wireless",
+];
+
+const PRODUCTS: &[&str] = &[
+    "Backpack", "Blender", "Candle", "Chair", "Desk Lamp", "Headphones", "Kettle", "Keyboard",
+    "Mug", "Notebook", "Pillow", "Speaker", "Sunglasses", "Water Bottle",
+];
+
+const MATERIALS: &[&str] = &["bamboo", "cotton", "leather", "oak", "recycled plastic", "stainless steel", "wool"];
+
+fn pick(words: &'static [&'static str]) -> &'static str {
+    words.choose(&mut rand::rng()).copied().unwrap_or_default()
+}
+
+// A short random tag keeps seeded emails unique across runs
+fn fake_user() -> CreateUser {
+    let first = pick(FIRST_NAMES);
+    let last = pick(LAST_NAMES);
+    let tag = &Uuid::new_v4().simple().to_string()[..8];
+    CreateUser {
+        name: format!("{} {}", first, last),
+        email: format!("{}.{}.{}@{}", first.to_lowercase(), last.to_lowercase(), tag, pick(EMAIL_DOMAINS)),
+    }
+}
+
+fn fake_item() -> CreateItem {
+    let adjective = pick(ADJECTIVES);
+    let product = pick(PRODUCTS);
+    let (price_minor, quantity) = {
+        let mut rng = rand::rng();
+        // Prices end in 99 like shop prices do
+        (rng.random_range(1..=250) * 100 - 1, rng.random_range(0..=500))
+    };
+    CreateItem {
+        name: format!("{} {}", adjective, product),
+        price: Money::from_stored(price_minor, "USD".to_string()),
+        quantity,
+        description: Some(format!("{} {} made from {}.", adjective, product.to_lowercase(), pick(MATERIALS))),
+        tax_category_id: None,
+        category_id: None,
+    }
+}
+
+// `seed` subcommand, only the backends asked for are opened
+pub async fn run(config: &AppConfig, args: SeedArgs) -> io::Result<()> {
+    if args.backend.uses_postgres() {
+        let pool = db::connect(&config.database).await.map_err(io::Error::other)?;
+        MIGRATOR.run(&pool).await.map_err(io::Error::other)?;
+        let user_repo = UserRepository::new(&pool);
+        let items_repo = ItemRepository::new(&pool);
+
+        for _ in 0..args.users {
+            user_repo.create_user(&fake_user()).await.map_err(io::Error::other)?;
+        }
+        for _ in 0..args.items {
+            items_repo.create_item(&fake_item()).await.map_err(io::Error::other)?;
+        }
+        println!("Seeded {} user(s) and {} item(s) into Postgres", args.users, args.items);
+    }
+
+    if args.backend.uses_json() {
+        let mut state = load_data_from_file(&config.storage.data_file).await?;
+        for _ in 0..args.users {
+            let user: User = fake_user().into();
+            state.users.insert(user.id, user);
+        }
+        for _ in 0..args.items {
+            let item: Item = fake_item().into();
+            state.item_index.insert(&item);
+            state.items.insert(item.id, item);
+        }
+        write_to_file(&state).await?;
+        println!("Seeded {} user(s) and {} item(s) into {}", args.users, args.items, config.storage.data_file);
+    }
+
+    Ok(())
+}
+
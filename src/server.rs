@@ -0,0 +1,374 @@
+use crate::handlers::item_handler;
+use crate::handlers::user_handler;
+use crate::handlers::order_handler;
+use crate::handlers::admin_handler;
+use crate::handlers::health_handler::{self, ShutdownState};
+use crate::repository::db::Database;
+use crate::repository::items_db::ItemRepository;
+use crate::repository::order_db::OrderRepository;
+use crate::repository::users_db::UserRepository;
+use crate::repository::pricing_db::PricingRepository;
+use crate::repository::cart_db::CartRepository;
+use crate::repository::payments_db::PaymentRepository;
+use crate::repository::shipments_db::ShipmentRepository;
+use crate::repository::catalog_db::CatalogRepository;
+use crate::repository::bulk_db::BulkRepository;
+use crate::payments::PaymentProvider;
+use crate::payments::fake::FakePaymentProvider;
+// handler function for the task 3
+use crate::repository::repo_handler;
+
+use crate::jobs::workers::{spawn_workers, spawn_purge_schedule};
+use crate::jobs::handler;
+
+use crate::models::JobQueue;
+use crate::config::AppConfig;
+use crate::logging;
+use crate::openapi::{self, ApiDoc, DOCS_PATH};
+use crate::response::{self, ApiResponse, ErrorCode};
+
+use actix_web::{web, App, HttpServer};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServerHandle, ServiceRequest, ServiceResponse};
+use actix_web::middleware::{from_fn, Next};
+use sqlx::PgPool;
+use swagger_ui_dist::{ApiDefinition, OpenApiSource};
+use utoipa::OpenApi;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use crate::utils::load_data_from_file;
+use crate::bulk::IMPORT_MAX_BYTES;
+
+// Repositories behind the /db routes, only built when Postgres is in use
+#[derive(Clone)]
+struct DbData {
+    user_repo: web::Data<UserRepository>,
+    items_repo: web::Data<ItemRepository>,
+    order_repo: web::Data<OrderRepository>,
+    pricing_repo: web::Data<PricingRepository>,
+    cart_repo: web::Data<CartRepository>,
+    payment_repo: web::Data<PaymentRepository>,
+    shipment_repo: web::Data<ShipmentRepository>,
+    catalog_repo: web::Data<CatalogRepository>,
+    bulk_repo: web::Data<BulkRepository>,
+    payment_provider: web::Data<dyn PaymentProvider>,
+}
+
+impl DbData {
+    fn new(pool: &PgPool) -> Self {
+        Self {
+            user_repo: web::Data::new(UserRepository::new(pool)),
+            items_repo: web::Data::new(ItemRepository::new(pool)),
+            order_repo: web::Data::new(OrderRepository::new(pool)),
+            pricing_repo: web::Data::new(PricingRepository::new(pool)),
+            cart_repo: web::Data::new(CartRepository::new(pool)),
+            payment_repo: web::Data::new(PaymentRepository::new(pool)),
+            shipment_repo: web::Data::new(ShipmentRepository::new(pool)),
+            catalog_repo: web::Data::new(CatalogRepository::new(pool)),
+            bulk_repo: web::Data::new(BulkRepository::new(pool)),
+            // Only the local fake provider exists so far, a real one slots in behind the same trait
+            payment_provider: web::Data::from(Arc::new(FakePaymentProvider::new()) as Arc<dyn PaymentProvider>),
+        }
+    }
+
+    fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg
+            .app_data(self.user_repo.clone())
+            .app_data(self.items_repo.clone())
+            .app_data(self.order_repo.clone())
+            .app_data(self.pricing_repo.clone())
+            .app_data(self.cart_repo.clone())
+            .app_data(self.payment_repo.clone())
+            .app_data(self.shipment_repo.clone())
+            .app_data(self.catalog_repo.clone())
+            .app_data(self.bulk_repo.clone())
+            .app_data(self.payment_provider.clone());
+        db_routes(cfg);
+    }
+}
+
+// `serve` subcommand, the JSON store and Postgres are only opened for the backends asked for
+pub async fn serve(config: AppConfig) -> std::io::Result<()> {
+    let backend = config.server.backend;
+
+    // task 2 layer
+    let shared_state = if backend.uses_json() {
+        // Load data from file
+        let initial_state = load_data_from_file(&config.storage.data_file).await
+            .expect("Failed to load initial state");
+
+        // Wrap in Arc<Mutex<>> for shared state
+        Some(web::Data::new(Arc::new(Mutex::new(initial_state))))
+    } else {
+        None
+    };
+
+    // task 3 wrapper
+    // The server starts whether or not Postgres is up, the /db routes answer 503 until it is
+    let database = if backend.uses_postgres() {
+        Database::lazy(&config.database).map_err(std::io::Error::other)?
+    } else {
+        Database::disabled()
+    };
+    if backend.uses_postgres() && !database.is_configured() {
+        log::warn!("No database url configured, the /db routes will answer 503");
+    }
+    database.spawn_supervisor(Duration::from_secs(config.database.reconnect_max_backoff_secs));
+    let pool = database.pool().cloned();
+    let db_data = pool.as_ref().map(DbData::new);
+
+    // task 4 layer
+    // Initialize job queue
+    let job_queue: Arc<JobQueue> = Arc::new(JobQueue::new(config.jobs.queue_size));
+
+    // Spawn workers, jobs that need the database fail without one
+    let poll_interval = Duration::from_millis(config.jobs.poll_interval_ms);
+    let workers = spawn_workers(config.jobs.workers, job_queue.clone(), pool.clone(), poll_interval);
+
+    // Soft deleted rows are purged for good after the retention window
+    let retention_days = config.jobs.soft_delete_retention_days;
+    if pool.is_some() {
+        spawn_purge_schedule(job_queue.clone(), retention_days);
+    }
+
+    log::info!("Backend: {}", backend.label());
+    log::info!("Started {} worker(s)", config.jobs.workers);
+    log::info!("Max queue size: {}", config.jobs.queue_size);
+    log::info!("Soft delete retention: {} day(s)", retention_days);
+
+    // Start HTTP server
+    let queue_data: web::Data<JobQueue> = web::Data::from(job_queue);
+    let port = config.server.port;
+    let shutdown_grace = Duration::from_secs(config.server.shutdown_grace_secs);
+    let config_data = web::Data::new(config);
+    let database_data = web::Data::new(database);
+    let workers_data = web::Data::new(workers);
+    let shutdown = web::Data::new(ShutdownState::default());
+    let shutdown_data = shutdown.clone();
+    let openapi_data = web::Data::new(ApiDoc::openapi());
+
+    let server = HttpServer::new(move || {
+        App::new()
+            // Outermost, so every response carries X-Request-Id and is logged
+            .wrap(from_fn(logging::request_context))
+            .app_data(queue_data.clone())
+            .app_data(config_data.clone())
+            .app_data(database_data.clone())
+            .app_data(workers_data.clone())
+            .app_data(shutdown_data.clone())
+            .app_data(openapi_data.clone())
+            // Only the import endpoints read a raw body, their files can be large
+            .app_data(web::PayloadConfig::new(IMPORT_MAX_BYTES))
+            // Bodies, query strings and paths that do not parse get the same error shape as handlers
+            .app_data(web::JsonConfig::default().error_handler(response::json_error))
+            .app_data(web::QueryConfig::default().error_handler(response::query_error))
+            .app_data(web::PathConfig::default().error_handler(response::path_error))
+            .configure(|cfg| {
+                if let Some(ref state) = shared_state {
+                    cfg.app_data(state.clone()); // Share state across all workers // .clone() has a time complextity of O(1) here but under the hook is still preformace effective when wrapped around web::Data
+                    json_routes(cfg);
+                }
+            })
+            .configure(|cfg| {
+                if backend.uses_postgres() {
+                    cfg.service(
+                        web::scope("/db")
+                            .wrap(from_fn(require_database))
+                            .configure(|cfg| {
+                                if let Some(ref db_data) = db_data {
+                                    db_data.configure(cfg);
+                                }
+                            })
+                    );
+                }
+            })
+            .configure(job_routes)
+            .configure(admin_routes)
+            .configure(health_routes)
+            .configure(docs_routes)
+    })
+    .bind(format!("0.0.0.0:{}", port))?
+    // Signals are handled below so readiness can fail before connections are drained
+    .disable_signals()
+    .run();
+
+    tokio::spawn(drain_on_signal(server.handle(), shutdown, shutdown_grace));
+    server.await
+}
+
+// Ctrl-C or SIGTERM: report not ready for the grace period, then stop gracefully
+async fn drain_on_signal(handle: ServerHandle, shutdown: web::Data<ShutdownState>, grace: Duration) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+            log::error!("Could not listen for SIGTERM");
+            return;
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    if tokio::signal::ctrl_c().await.is_err() {
+        log::error!("Could not listen for Ctrl-C");
+        return;
+    }
+
+    shutdown.begin();
+    log::info!("Shutting down, readiness fails for {}s before connections are drained", grace.as_secs());
+    tokio::time::sleep(grace).await;
+    handle.stop(true).await;
+}
+
+// task 2 layer - CRUD to the JSON layer
+fn json_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // User routes
+        .route("/users", web::post().to(user_handler::create_user))
+        .route("/users", web::get().to(user_handler::list_users))
+        .route("/users/{id}", web::get().to(user_handler::get_user))
+        .route("/users/{id}", web::put().to(user_handler::update_user))
+        .route("/users/{id}", web::delete().to(user_handler::delete_user))
+        // Item routes
+        .route("/items", web::post().to(item_handler::create_item))
+        .route("/items", web::get().to(item_handler::list_items))
+        .route("/items/search", web::get().to(item_handler::search_items))
+        .route("/items/{id}", web::get().to(item_handler::get_item))
+        .route("/items/{id}", web::put().to(item_handler::update_item))
+        .route("/items/{id}", web::delete().to(item_handler::delete_item))
+        // Order routes
+        .route("/orders", web::post().to(order_handler::create_order))
+        .route("/orders", web::get().to(order_handler::list_orders))
+        .route("/orders/{id}", web::get().to(order_handler::get_order_with_details))
+        .route("/orders/{id}", web::put().to(order_handler::update_order));
+}
+
+// Answers 503 for every /db route while Postgres is not configured or not reachable
+async fn require_database(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let unavailable = match req.app_data::<web::Data<Database>>() {
+        Some(database) if database.is_connected() => None,
+        Some(database) if database.is_configured() => Some("Database is unavailable, reconnecting"),
+        _ => Some("Database is not configured, set DATABASE_URL to use the /db routes"),
+    };
+
+    if let Some(message) = unavailable {
+        let response = ApiResponse::error(ErrorCode::DatabaseUnavailable, message)
+            .retry_after(5)
+            .render(req.request());
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// task 3 layer - CRUD to the db layer, mounted under /db
+fn db_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // User routes
+        .route("/users", web::post().to(repo_handler::create_user))
+        .route("/users", web::get().to(repo_handler::list_users))
+        .route("/users/bulk-delete", web::post().to(repo_handler::delete_users))
+        .route("/users/import", web::post().to(repo_handler::import_users))
+        .route("/users/export", web::get().to(repo_handler::export_users))
+        .route("/users/{id}", web::get().to(repo_handler::get_user))
+        .route("/users/{id}", web::put().to(repo_handler::update_user))
+        .route("/users/{id}", web::delete().to(repo_handler::delete_user))
+        .route("/users/{id}/restore", web::post().to(repo_handler::restore_user))
+        // Cart routes
+        .route("/users/{id}/cart/items", web::get().to(repo_handler::get_cart))
+        .route("/users/{id}/cart/items", web::post().to(repo_handler::add_cart_item))
+        .route("/users/{id}/cart/items", web::delete().to(repo_handler::clear_cart))
+        .route("/users/{id}/cart/items/{item_id}", web::delete().to(repo_handler::remove_cart_item))
+        .route("/users/{id}/cart/checkout", web::post().to(repo_handler::checkout_cart))
+        // Item routes
+        .route("/items", web::post().to(repo_handler::create_item))
+        .route("/items", web::get().to(repo_handler::list_items))
+        .route("/items/active", web::get().to(repo_handler::list_active_items))
+        .route("/items/search", web::get().to(repo_handler::search_items))
+        .route("/items/bulk-delete", web::post().to(repo_handler::delete_items))
+        .route("/items/import", web::post().to(repo_handler::import_items))
+        .route("/items/export", web::get().to(repo_handler::export_items))
+        .route("/items/{id}", web::get().to(repo_handler::get_item))
+        .route("/items/{id}", web::put().to(repo_handler::update_item))
+        .route("/items/{id}", web::delete().to(repo_handler::delete_item))
+        .route("/items/{id}/ledger", web::get().to(repo_handler::get_item_stock_movements))
+        .route("/items/{id}/restore", web::post().to(repo_handler::restore_item))
+        .route("/items/{id}/tags", web::get().to(repo_handler::get_item_tags))
+        .route("/items/{id}/tags", web::put().to(repo_handler::set_item_tags))
+        // Category and tag routes
+        .route("/categories", web::post().to(repo_handler::create_category))
+        .route("/categories", web::get().to(repo_handler::list_categories))
+        .route("/categories/tree", web::get().to(repo_handler::get_category_tree))
+        .route("/categories/{id}", web::get().to(repo_handler::get_category))
+        .route("/categories/{id}", web::put().to(repo_handler::update_category))
+        .route("/categories/{id}", web::delete().to(repo_handler::delete_category))
+        .route("/tags", web::post().to(repo_handler::create_tag))
+        .route("/tags", web::get().to(repo_handler::list_tags))
+        .route("/tags/{id}", web::get().to(repo_handler::get_tag))
+        .route("/tags/{id}", web::put().to(repo_handler::rename_tag))
+        .route("/tags/{id}", web::delete().to(repo_handler::delete_tag))
+        // Order routes
+        .route("/orders", web::post().to(repo_handler::create_order))
+        .route("/orders", web::get().to(repo_handler::list_orders))
+        .route("/orders/quote", web::post().to(repo_handler::quote_order))
+        .route("/orders/bulk-delete", web::post().to(repo_handler::delete_orders))
+        .route("/orders/export", web::get().to(repo_handler::export_orders))
+        .route("/orders/status", web::get().to(repo_handler::get_orders_by_status)) // ?status=Pending, registered before {id} so it is reachable
+        .route("/orders/{id}", web::get().to(repo_handler::get_order))
+        .route("/orders/{id}/details", web::get().to(repo_handler::get_order_with_items))
+        .route("/orders/{id}/history", web::get().to(repo_handler::get_order_status_history))
+        .route("/orders/{id}/pay", web::post().to(repo_handler::pay_order))
+        .route("/orders/{id}/payments", web::get().to(repo_handler::list_order_payments))
+        .route("/orders/{id}/shipment", web::post().to(repo_handler::create_shipment))
+        .route("/orders/{id}/shipment", web::get().to(repo_handler::get_shipment))
+        .route("/orders/{id}/shipment/delivered", web::post().to(repo_handler::deliver_shipment))
+        .route("/orders/{id}", web::put().to(repo_handler::update_order))
+        .route("/orders/{id}", web::delete().to(repo_handler::delete_order))
+        .route("/orders/{id}/restore", web::post().to(repo_handler::restore_order))
+        .route("/orders/user/{user_id}", web::get().to(repo_handler::get_orders_by_user))
+        .route("/imports/{id}", web::get().to(repo_handler::get_import))
+        // Pricing routes
+        .route("/tax-categories", web::post().to(repo_handler::create_tax_category))
+        .route("/tax-categories", web::get().to(repo_handler::list_tax_categories))
+        .route("/coupons", web::post().to(repo_handler::create_coupon))
+        .route("/coupons", web::get().to(repo_handler::list_coupons))
+        .route("/coupons/{code}", web::get().to(repo_handler::get_coupon));
+}
+
+// task 4 routes
+fn job_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        .route("/jobs", web::post().to(handler::create_job))
+        .route("/jobs", web::get().to(handler::list_jobs))
+        .route("/jobs/status", web::get().to(handler::list_jobs_by_status)) // ?status=Pending, registered before {id} so it is reachable
+        .route("/jobs/{id}", web::get().to(handler::get_job));
+}
+
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        .route("/admin/config", web::get().to(admin_handler::get_config));
+}
+
+// Probes for the orchestrator
+fn health_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        .route("/health/live", web::get().to(health_handler::live))
+        .route("/health/ready", web::get().to(health_handler::ready));
+}
+
+// The spec and the bundled Swagger UI that reads it
+fn docs_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        .route("/openapi.json", web::get().to(openapi::openapi_json))
+        .service(swagger_ui_dist::generate_scope(ApiDefinition {
+            uri_prefix: DOCS_PATH,
+            api_definition: OpenApiSource::Uri("/openapi.json"),
+            title: Some("heartbeetle-task API"),
+        }));
+}
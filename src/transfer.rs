@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::config::AppConfig;
+use crate::models::{AppState, User, Item, Order, OrderLine};
+use crate::repository::db;
+use crate::repository::error::RepoError;
+use crate::repository::transfer_db::{TransferRepository, TransferReport};
+use crate::search::ItemIndex;
+
+const USAGE: &str = "\
+usage: heartbeetle-task transfer <command> [--file PATH] [--dry-run]
+
+commands:
+  to-postgres   insert users, items and orders from the JSON file, then verify the file's records
+                (default file storage.data_file)
+  to-json       write Postgres into a JSON file (default file data.export.json)
+  verify        compare the JSON file with Postgres (default file storage.data_file)
+
+--dry-run runs to-postgres and its check in a transaction that is rolled back, and makes to-json print
+instead of write";
+
+// Records that differ between the JSON file and Postgres, by id
+#[derive(Debug, Serialize, Default)]
+pub struct EntityDiff {
+    pub only_in_file: Vec<Uuid>,
+    pub only_in_db: Vec<Uuid>,
+    pub changed: Vec<ChangedRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedRecord {
+    pub id: Uuid,
+    pub fields: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferDiff {
+    pub users: EntityDiff,
+    pub items: EntityDiff,
+    pub orders: EntityDiff,
+}
+
+impl TransferDiff {
+    pub fn is_clean(&self) -> bool {
+        [&self.users, &self.items, &self.orders]
+            .iter()
+            .all(|diff| diff.only_in_file.is_empty() && diff.only_in_db.is_empty() && diff.changed.is_empty())
+    }
+}
+
+// Postgres keeps microseconds, the JSON file may hold nanoseconds
+fn same_time(a: &DateTime<Utc>, b: &DateTime<Utc>) -> bool {
+    a.timestamp_micros() == b.timestamp_micros()
+}
+
+fn user_changes(file: &User, db: &User) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if file.name != db.name { fields.push("name"); }
+    if file.email != db.email { fields.push("email"); }
+    if file.is_active != db.is_active { fields.push("is_active"); }
+    if !same_time(&file.created_at, &db.created_at) { fields.push("created_at"); }
+    if !same_time(&file.updated_at, &db.updated_at) { fields.push("updated_at"); }
+    fields
+}
+
+fn item_changes(file: &Item, db: &Item) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if file.name != db.name { fields.push("name"); }
+    if file.price != db.price { fields.push("price"); }
+    if file.quantity != db.quantity { fields.push("quantity"); }
+    if file.description != db.description { fields.push("description"); }
+    if file.is_active != db.is_active { fields.push("is_active"); }
+    if file.tax_category_id != db.tax_category_id { fields.push("tax_category_id"); }
+    if file.category_id != db.category_id { fields.push("category_id"); }
+    if !same_time(&file.created_at, &db.created_at) { fields.push("created_at"); }
+    if !same_time(&file.updated_at, &db.updated_at) { fields.push("updated_at"); }
+    fields
+}
+
+// Order lines compared regardless of their order
+fn line_keys(lines: &[OrderLine]) -> Vec<(Uuid, &str, i32, i64, &str, i32)> {
+    let mut keys: Vec<_> = lines.iter()
+        .map(|line| (
+            line.item_id,
+            line.name.as_str(),
+            line.quantity,
+            line.unit_price.amount_minor,
+            line.unit_price.currency.as_str(),
+            line.tax_rate_bps,
+        ))
+        .collect();
+    keys.sort();
+    keys
+}
+
+fn order_changes(file: &Order, db: &Order) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if file.user_id != db.user_id { fields.push("user_id"); }
+    if line_keys(file.lines()) != line_keys(db.lines()) { fields.push("items"); }
+    if file.subtotal != db.subtotal { fields.push("subtotal"); }
+    if file.discount != db.discount { fields.push("discount"); }
+    if file.tax != db.tax { fields.push("tax"); }
+    if file.amount != db.amount { fields.push("amount"); }
+    if file.coupon_code != db.coupon_code { fields.push("coupon_code"); }
+    if file.status != db.status { fields.push("status"); }
+    if !same_time(&file.created_at, &db.created_at) { fields.push("created_at"); }
+    if !same_time(&file.updated_at, &db.updated_at) { fields.push("updated_at"); }
+    fields
+}
+
+// Rows written by an import, which have to match the file, and rows it skipped because
+// Postgres already had their id. Differences in skipped rows are shown but are not an error.
+#[derive(Debug, Serialize)]
+pub struct ImportCheck {
+    pub imported: TransferDiff,
+    pub skipped: TransferDiff,
+}
+
+fn diff_maps<T>(
+    file: &HashMap<Uuid, T>,
+    db: &HashMap<Uuid, T>,
+    changes: impl Fn(&T, &T) -> Vec<&'static str>,
+    keep: impl Fn(&Uuid) -> bool,
+) -> EntityDiff {
+    let file: HashMap<&Uuid, &T> = file.iter().filter(|(id, _)| keep(id)).collect();
+    let db: HashMap<&Uuid, &T> = db.iter().filter(|(id, _)| keep(id)).collect();
+
+    let mut diff = EntityDiff::default();
+    for (id, file_record) in &file {
+        match db.get(id) {
+            None => diff.only_in_file.push(**id),
+            Some(db_record) => {
+                let fields = changes(file_record, db_record);
+                if !fields.is_empty() {
+                    diff.changed.push(ChangedRecord { id: **id, fields });
+                }
+            }
+        }
+    }
+    diff.only_in_db = db.keys().filter(|id| !file.contains_key(*id)).map(|id| **id).collect();
+
+    diff.only_in_file.sort();
+    diff.only_in_db.sort();
+    diff.changed.sort_by_key(|record| record.id);
+    diff
+}
+
+pub fn diff_states(file: &AppState, db: &AppState) -> TransferDiff {
+    TransferDiff {
+        users: diff_maps(&file.users, &db.users, user_changes, |_| true),
+        items: diff_maps(&file.items, &db.items, item_changes, |_| true),
+        orders: diff_maps(&file.orders, &db.orders, order_changes, |_| true),
+    }
+}
+
+// db only holds the rows with the file's ids, so nothing else in Postgres shows up as only_in_db
+pub fn check_import(file: &AppState, db: &AppState, report: &TransferReport) -> ImportCheck {
+    let users: HashSet<&Uuid> = report.users.skipped_ids.iter().collect();
+    let items: HashSet<&Uuid> = report.items.skipped_ids.iter().collect();
+    let orders: HashSet<&Uuid> = report.orders.skipped_ids.iter().collect();
+
+    ImportCheck {
+        imported: TransferDiff {
+            users: diff_maps(&file.users, &db.users, user_changes, |id| !users.contains(id)),
+            items: diff_maps(&file.items, &db.items, item_changes, |id| !items.contains(id)),
+            orders: diff_maps(&file.orders, &db.orders, order_changes, |id| !orders.contains(id)),
+        },
+        skipped: TransferDiff {
+            users: diff_maps(&file.users, &db.users, user_changes, |id| users.contains(id)),
+            items: diff_maps(&file.items, &db.items, item_changes, |id| items.contains(id)),
+            orders: diff_maps(&file.orders, &db.orders, order_changes, |id| orders.contains(id)),
+        },
+    }
+}
+
+async fn read_state(path: &str) -> io::Result<AppState> {
+    let content = tokio::fs::read_to_string(path).await?;
+    let mut state: AppState = serde_json::from_str(&content)?;
+    state.item_index = ItemIndex::build(state.items.values());
+    Ok(state)
+}
+
+fn print_json<T: Serialize>(value: &T) -> io::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+// Prints the diff and fails when the two sides do not match
+async fn verify(repo: &TransferRepository, state: &AppState) -> io::Result<()> {
+    let db_state = repo.export_state().await.map_err(io::Error::other)?;
+    let diff = diff_states(state, &db_state);
+    print_json(&diff)?;
+    if diff.is_clean() {
+        println!("JSON file and Postgres match");
+        Ok(())
+    } else {
+        Err(io::Error::other("JSON file and Postgres differ"))
+    }
+}
+
+// `transfer` subcommand, args are everything after the subcommand name
+pub async fn run_cli(config: &AppConfig, args: &[String]) -> io::Result<()> {
+    let mut command = None;
+    let mut file = None;
+    let mut dry_run = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--file" => file = Some(args.next().ok_or_else(|| io::Error::other("--file needs a path"))?.clone()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            other if command.is_none() && !other.starts_with('-') => command = Some(other.to_string()),
+            other => return Err(io::Error::other(format!("Unexpected argument {}\n{}", other, USAGE))),
+        }
+    }
+
+    let pool = db::connect(&config.database).await.map_err(io::Error::other)?;
+    db::MIGRATOR.run(&pool).await.map_err(io::Error::other)?;
+    let repo = TransferRepository::new(&pool);
+
+    match command.as_deref() {
+        Some("to-postgres") => {
+            let path = file.unwrap_or_else(|| config.storage.data_file.clone());
+            let state = read_state(&path).await?;
+            let (report, written) = repo.import_state(&state, dry_run).await.map_err(|e| match e {
+                RepoError::Validation(msg) => io::Error::other(format!("{} cannot be imported: {}", path, msg)),
+                other => io::Error::other(other.to_string()),
+            })?;
+            print_json(&report)?;
+
+            let check = check_import(&state, &written, &report);
+            print_json(&check)?;
+            if !check.skipped.is_clean() {
+                println!("Some skipped records differ from the file, Postgres keeps its own version of them");
+            }
+            if check.imported.is_clean() {
+                println!("Imported records match the JSON file");
+                Ok(())
+            } else {
+                Err(io::Error::other("Imported records differ from the JSON file"))
+            }
+        }
+        Some("to-json") => {
+            let path = file.unwrap_or_else(|| "data.export.json".to_string());
+            let state = repo.export_state().await.map_err(io::Error::other)?;
+            println!(
+                "{} user(s), {} item(s) and {} order(s) in Postgres",
+                state.users.len(), state.items.len(), state.orders.len()
+            );
+            if dry_run {
+                println!("Dry run, {} was not written", path);
+            } else {
+                tokio::fs::write(&path, serde_json::to_string_pretty(&state)?).await?;
+                println!("Wrote {}", path);
+            }
+            Ok(())
+        }
+        Some("verify") => {
+            let path = file.unwrap_or_else(|| config.storage.data_file.clone());
+            let state = read_state(&path).await?;
+            verify(&repo, &state).await
+        }
+        _ => Err(io::Error::other(USAGE)),
+    }
+}
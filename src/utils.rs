@@ -1,45 +1,48 @@
-use tokio;
-use serde_json;
 use crate::models::AppState;
+use crate::search::ItemIndex;
 use std::collections::HashMap;
-use std::io::Error;
+use std::path::PathBuf;
 use rand::Rng;
 
 pub async fn write_to_file(state: &AppState) -> std::io::Result<()> {
     let json_content = serde_json::to_string_pretty(state)?;
-    tokio::fs::write("data.json", json_content).await?;
+    tokio::fs::write(&state.data_file, json_content).await?;
     Ok(())
 }
 
-pub async fn load_data_from_file() -> std::io::Result<AppState> {
+// `path` is storage.data_file from the config
+pub async fn load_data_from_file(path: &str) -> std::io::Result<AppState> {
     // Check if file exists
-    if tokio::fs::metadata("data.json").await.is_ok() {
-        let content = tokio::fs::read_to_string("data.json").await?;
-        let state: AppState = serde_json::from_str(&content)?;
-        println!("Loaded existing data from data.json");
+    if tokio::fs::metadata(path).await.is_ok() {
+        let content = tokio::fs::read_to_string(path).await?;
+        let mut state: AppState = serde_json::from_str(&content)?;
+        state.item_index = ItemIndex::build(state.items.values());
+        state.data_file = PathBuf::from(path);
+        log::info!("Loaded existing data from {}", path);
         Ok(state)
     } else {
         // Create new empty state if file doesn't exist
-        println!("No data.json found, creating new file with empty state");
+        log::info!("No {} found, creating new file with empty state", path);
         let empty_state = AppState {
             users: HashMap::new(),
             orders: HashMap::new(),
             items: HashMap::new(),
+            item_index: ItemIndex::default(),
+            data_file: PathBuf::from(path),
         };
 
     // write the json file into the newly created file
     let empty_state_json = match  serde_json::to_string_pretty(&empty_state) {
         Ok(json) => json,
         Err(e) => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(std::io::Error::other(
                 format!("Failed to serialize: {}", e)
             ));
         }
     };
-    tokio::fs::write("data.json", empty_state_json).await?;
+    tokio::fs::write(path, empty_state_json).await?;
 
-    println!("Created data.json successfully");
+    log::info!("Created {} successfully", path);
     Ok(empty_state)
     }
 }
@@ -62,9 +65,7 @@ pub fn bubble_sort(arr: &mut [i32]) {
     for i in 0..n {
         for j in 0..(n-i-1) {
             if arr[j] > arr[j + 1] {
-                let temp_holder = arr[j]; // temporary holder for the item at the j index, since i32 implements the copy trait
-                arr[j] = arr[j + 1]; // item at 1 index after, since it's higher gets swapped to the smaller one beside it
-                arr[j + 1] = temp_holder;
+                arr.swap(j, j + 1); // item at 1 index after, since it's higher gets swapped to the smaller one beside it
 
                 swap_count += 1
             }
@@ -72,4 +73,100 @@ pub fn bubble_sort(arr: &mut [i32]) {
     }
 
     println!("Number of swaps performed: {}", swap_count);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    // The shape the JSON store wrote before order lines and minor units: f64 prices and amounts,
+    // orders holding one full item per unit ordered, users holding their orders
+    const BASELINE_DATA: &str = r#"{
+  "users": {
+    "6f1c4a52-0f7e-4f8e-9a43-2d3b1b0c5e01": {
+      "id": "6f1c4a52-0f7e-4f8e-9a43-2d3b1b0c5e01",
+      "name": "Ada",
+      "email": "ada@example.com",
+      "orders": [],
+      "created_at": "2025-09-01T10:00:00Z",
+      "updated_at": "2025-09-01T10:00:00Z",
+      "is_active": true
+    }
+  },
+  "orders": {
+    "0b8d3c1e-5a2f-4b7c-8e9d-1f2a3b4c5d01": {
+      "id": "0b8d3c1e-5a2f-4b7c-8e9d-1f2a3b4c5d01",
+      "user_id": "6f1c4a52-0f7e-4f8e-9a43-2d3b1b0c5e01",
+      "items": [
+        {
+          "id": "a3e1f2d4-6b7c-4d8e-9f0a-1b2c3d4e5f01",
+          "name": "Widget",
+          "price": 19.99,
+          "quantity": 5,
+          "description": null,
+          "created_at": "2025-09-01T10:00:00Z",
+          "updated_at": "2025-09-01T10:00:00Z",
+          "is_active": true
+        },
+        {
+          "id": "a3e1f2d4-6b7c-4d8e-9f0a-1b2c3d4e5f01",
+          "name": "Widget",
+          "price": 19.99,
+          "quantity": 5,
+          "description": null,
+          "created_at": "2025-09-01T10:00:00Z",
+          "updated_at": "2025-09-01T10:00:00Z",
+          "is_active": true
+        }
+      ],
+      "amount": 39.98,
+      "status": "Paid",
+      "created_at": "2025-09-02T10:00:00Z",
+      "updated_at": "2025-09-02T10:00:00Z"
+    }
+  },
+  "items": {
+    "a3e1f2d4-6b7c-4d8e-9f0a-1b2c3d4e5f01": {
+      "id": "a3e1f2d4-6b7c-4d8e-9f0a-1b2c3d4e5f01",
+      "name": "Widget",
+      "price": 19.99,
+      "quantity": 5,
+      "description": "A widget",
+      "created_at": "2025-09-01T10:00:00Z",
+      "updated_at": "2025-09-01T10:00:00Z",
+      "is_active": true
+    }
+  }
+}"#;
+
+    #[tokio::test]
+    async fn loads_a_baseline_data_file() {
+        let path = std::env::temp_dir().join(format!("heartbeetle-baseline-{}.json", Uuid::new_v4()));
+        tokio::fs::write(&path, BASELINE_DATA).await.unwrap();
+        let loaded = load_data_from_file(path.to_str().unwrap()).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        let state = loaded.unwrap();
+
+        let item_id: Uuid = "a3e1f2d4-6b7c-4d8e-9f0a-1b2c3d4e5f01".parse().unwrap();
+        let item = &state.items[&item_id];
+        assert_eq!((item.price.amount_minor, item.price.currency.as_str()), (1999, "USD"));
+        assert_eq!((item.tax_category_id, item.category_id, item.deleted_at), (None, None, None));
+
+        let order = state.orders.values().next().unwrap();
+        let lines = order.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!((lines[0].item_id, lines[0].quantity, lines[0].unit_price.amount_minor), (item_id, 2, 1999));
+        assert_eq!((order.subtotal.amount_minor, order.discount.amount_minor, order.tax.amount_minor), (3998, 0, 0));
+        assert_eq!(order.amount.amount_minor, 3998);
+        assert!(order.coupon_code.is_none());
+
+        assert_eq!(state.users.len(), 1);
+        assert_eq!(state.item_index.search("widget").len(), 1);
+
+        // Written back in the current shape, which loads the same way
+        let written: AppState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(written.orders[&order.id].lines()[0].quantity, 2);
+        assert_eq!(written.items[&item_id].price, item.price);
+    }
+}
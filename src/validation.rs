@@ -0,0 +1,306 @@
+use std::fmt;
+use actix_web::{web, FromRequest, HttpRequest};
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use utoipa::ToSchema;
+use crate::response::{ApiResponse, ErrorCode};
+
+// Request bodies list their rules in validate(), every broken rule is reported, not just the first
+pub trait Validate {
+    fn validate(&self, v: &mut Validator);
+}
+
+// One broken rule, field is a path into the body such as items[0].quantity
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Validator {
+    prefix: String,
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn check<T: Validate>(value: &T) -> Result<(), ValidationErrors> {
+        let mut v = Validator::default();
+        value.validate(&mut v);
+        if v.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(v.errors))
+        }
+    }
+
+    pub fn fail(&mut self, field: &str, message: impl Into<String>) -> &mut Self {
+        self.errors.push(FieldError {
+            field: format!("{}{}", self.prefix, field),
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) -> &mut Self {
+        if value.trim().is_empty() {
+            self.fail(field, "must not be empty");
+        }
+        self
+    }
+
+    pub fn max_len(&mut self, field: &str, value: &str, max: usize) -> &mut Self {
+        if value.chars().count() > max {
+            self.fail(field, format!("must be at most {} characters", max));
+        }
+        self
+    }
+
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        if !is_valid_email(value.trim()) {
+            self.fail(field, "must be a valid email address");
+        }
+        self
+    }
+
+    pub fn min(&mut self, field: &str, value: i64, min: i64) -> &mut Self {
+        if value < min {
+            match min {
+                0 => self.fail(field, "must not be negative"),
+                1 => self.fail(field, "must be greater than 0"),
+                _ => self.fail(field, format!("must be at least {}", min)),
+            };
+        }
+        self
+    }
+
+    pub fn range(&mut self, field: &str, value: i64, min: i64, max: i64) -> &mut Self {
+        if value < min || value > max {
+            self.fail(field, format!("must be between {} and {}", min, max));
+        }
+        self
+    }
+
+    pub fn not_empty<T>(&mut self, field: &str, values: &[T]) -> &mut Self {
+        if values.is_empty() {
+            self.fail(field, "must not be empty");
+        }
+        self
+    }
+
+    // Runs the rules of every element, their errors are reported as field[index].inner
+    pub fn each<T: Validate>(&mut self, field: &str, values: &[T]) -> &mut Self {
+        for (index, value) in values.iter().enumerate() {
+            let outer = std::mem::take(&mut self.prefix);
+            self.prefix = format!("{}{}[{}].", outer, field, index);
+            value.validate(self);
+            self.prefix = outer;
+        }
+        self
+    }
+}
+
+// Same rule the CSV/NDJSON user import applies to its rows
+pub fn is_valid_email(email: &str) -> bool {
+    email
+        .split_once('@')
+        .is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && !domain.contains('@')
+                && !email.chars().any(char::is_whitespace)
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|part| !part.is_empty())
+        })
+}
+
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} field(s) failed validation", self.0.len())
+    }
+}
+
+impl ValidationErrors {
+    // 400 with every broken rule under details.fields
+    pub fn into_error(self, req: &HttpRequest) -> actix_web::Error {
+        let response = ApiResponse::error(ErrorCode::ValidationFailed, "Validation failed")
+            .details(serde_json::json!({ "fields": self.0 }))
+            .render(req);
+        InternalError::from_response(self, response).into()
+    }
+}
+
+// Drop-in for web::Json<T> that rejects the request with 400 before the handler runs
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for Valid<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for Valid<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            Validator::check(&value).map_err(|errors| errors.into_error(&req))?;
+            Ok(Valid(value))
+        })
+    }
+}
+
+// For bodies that may be left out: no body at all gives T::default(), anything else
+// goes through Valid<T> so malformed JSON and broken rules get the same 400 as everywhere else
+pub struct ValidOrDefault<T>(pub T);
+
+impl<T> ValidOrDefault<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for ValidOrDefault<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn has_body(req: &HttpRequest) -> bool {
+    match req.headers().get(CONTENT_LENGTH) {
+        Some(length) => length.to_str().map_or(true, |length| length.trim() != "0"),
+        None => req.headers().contains_key(TRANSFER_ENCODING),
+    }
+}
+
+impl<T> FromRequest for ValidOrDefault<T>
+where
+    T: DeserializeOwned + Validate + Default + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if !has_body(req) {
+            return Box::pin(async { Ok(ValidOrDefault(T::default())) });
+        }
+        let valid = Valid::<T>::from_request(req, payload);
+        Box::pin(async move { Ok(ValidOrDefault(valid.await?.into_inner())) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Line {
+        quantity: i64,
+    }
+
+    impl Validate for Line {
+        fn validate(&self, v: &mut Validator) {
+            v.min("quantity", self.quantity, 1);
+        }
+    }
+
+    struct Order {
+        lines: Vec<Line>,
+    }
+
+    impl Validate for Order {
+        fn validate(&self, v: &mut Validator) {
+            v.not_empty("lines", &self.lines).each("lines", &self.lines);
+            v.fail("after", "still unprefixed");
+        }
+    }
+
+    fn errors<T: Validate>(value: &T) -> Vec<(String, String)> {
+        match Validator::check(value) {
+            Ok(()) => Vec::new(),
+            Err(ValidationErrors(errors)) => errors.into_iter().map(|e| (e.field, e.message)).collect(),
+        }
+    }
+
+    #[test]
+    fn email_addresses() {
+        for email in ["a@b.co", "first.last+tag@mail.example.com", "x@sub.domain.org"] {
+            assert!(is_valid_email(email), "{}", email);
+        }
+        for email in ["", "plain", "@b.co", "a@", "a@b", "a@b.", "a@.co", "a@b..co", "a@@b.co", "a@b@c.co", "a b@c.co", "a@b.co "] {
+            assert!(!is_valid_email(email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn each_prefixes_element_errors_with_their_index() {
+        let order = Order {
+            lines: vec![Line { quantity: 1 }, Line { quantity: 0 }, Line { quantity: -2 }],
+        };
+        assert_eq!(errors(&order), vec![
+            ("lines[1].quantity".to_string(), "must be greater than 0".to_string()),
+            ("lines[2].quantity".to_string(), "must be greater than 0".to_string()),
+            ("after".to_string(), "still unprefixed".to_string()),
+        ]);
+
+        let empty = Order { lines: Vec::new() };
+        assert_eq!(errors(&empty)[0], ("lines".to_string(), "must not be empty".to_string()));
+    }
+
+    #[test]
+    fn nested_each_stacks_prefixes() {
+        struct Batch {
+            orders: Vec<Order>,
+        }
+        impl Validate for Batch {
+            fn validate(&self, v: &mut Validator) {
+                v.each("orders", &self.orders);
+            }
+        }
+
+        let batch = Batch {
+            orders: vec![Order { lines: vec![Line { quantity: 0 }] }],
+        };
+        assert_eq!(errors(&batch), vec![
+            ("orders[0].lines[0].quantity".to_string(), "must be greater than 0".to_string()),
+            ("orders[0].after".to_string(), "still unprefixed".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn min_and_range_messages() {
+        let mut v = Validator::default();
+        v.min("a", -1, 0).min("b", 0, 1).min("c", 4, 5).min("ok", 5, 5);
+        v.range("d", 0, 1, 10).range("e", 11, 1, 10).range("ok", 10, 1, 10);
+        let messages: Vec<(&str, &str)> = v.errors.iter().map(|e| (e.field.as_str(), e.message.as_str())).collect();
+        assert_eq!(messages, vec![
+            ("a", "must not be negative"),
+            ("b", "must be greater than 0"),
+            ("c", "must be at least 5"),
+            ("d", "must be between 1 and 10"),
+            ("e", "must be between 1 and 10"),
+        ]);
+    }
+}